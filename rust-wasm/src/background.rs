@@ -0,0 +1,112 @@
+use crate::face_detect::{is_skin, FaceBox};
+use image::imageops::{self, FilterType};
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use std::collections::VecDeque;
+
+/// Longest side of the downscaled copy the background is segmented in
+const SEGMENTATION_SIDE: u32 = 400;
+
+/// Share of the frame height, from the top, whose side borders are expected to be background;
+/// the subject's shoulders usually reach the sides further down
+const BORDER_DEPTH: f64 = 0.7;
+
+/// Replacement is skipped when the background covers less or more of the photo than this
+const BACKGROUND_SHARE: std::ops::RangeInclusive<f64> = 0.05..=0.95;
+
+/// Replace the plain background behind a portrait with `color`. The background is grown from
+/// the top and side borders through pixels close to the border colour (`tolerance` is an RGB
+/// distance), never into `protected` boxes or skin. Returns `None` when the border is not a
+/// uniform backdrop.
+pub fn replace_background(image: &RgbImage, color: [u8; 3], tolerance: f64, protected: &[FaceBox]) -> Option<RgbImage> {
+    let (width, height) = image.dimensions();
+    let scale = (SEGMENTATION_SIDE as f64 / width.max(height) as f64).min(1.0);
+    let (small_width, small_height) = (((width as f64 * scale).round() as u32).max(2), ((height as f64 * scale).round() as u32).max(2));
+    let small = imageops::resize(image, small_width, small_height, FilterType::Triangle);
+
+    let border: Vec<(u32, u32)> = (0..small_width)
+        .map(|x| (x, 0))
+        .chain((1..(small_height as f64 * BORDER_DEPTH) as u32).flat_map(|y| [(0, y), (small_width - 1, y)]))
+        .collect();
+    let reference = median_color(border.iter().map(|&(x, y)| *small.get_pixel(x, y)));
+    let mut spread: Vec<f64> = border.iter().map(|&(x, y)| distance(small.get_pixel(x, y), &reference)).collect();
+    spread.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    if spread[spread.len() * 6 / 10] > tolerance * 2.0 {
+        log::info!("Photo border is not a plain backdrop (spread {:.0})", spread[spread.len() * 6 / 10]);
+        return None;
+    }
+
+    // A warm beige backdrop passes the skin test itself, so only the face boxes protect the subject
+    let skin_backdrop = is_skin(reference.0);
+    let is_protected = |x: u32, y: u32| {
+        let (fx, fy) = (x as f64 / scale, y as f64 / scale);
+        let in_face = protected.iter().any(|face| {
+            // Include hair above and ears beside the detected skin box
+            let margin = face.width * 0.15;
+            fx >= face.x - margin && fx <= face.x + face.width + margin && fy >= face.y - face.height * 0.3 && fy <= face.y + face.height
+        });
+        in_face || (!skin_backdrop && is_skin(small.get_pixel(x, y).0))
+    };
+
+    // Flood fill: each step must be a small colour change that stays near the backdrop colour
+    let mut background = GrayImage::new(small_width, small_height);
+    let mut queue: VecDeque<(u32, u32)> = border
+        .into_iter()
+        .filter(|&(x, y)| distance(small.get_pixel(x, y), &reference) <= tolerance * 2.0 && !is_protected(x, y))
+        .collect();
+    for &(x, y) in &queue {
+        background.put_pixel(x, y, Luma([255]));
+    }
+    while let Some((x, y)) = queue.pop_front() {
+        let current = small.get_pixel(x, y);
+        let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+        for (nx, ny) in neighbours {
+            if nx >= small_width || ny >= small_height || background.get_pixel(nx, ny).0[0] > 0 {
+                continue;
+            }
+            let candidate = small.get_pixel(nx, ny);
+            if distance(candidate, current) <= tolerance / 2.0 && distance(candidate, &reference) <= tolerance * 3.0 && !is_protected(nx, ny) {
+                background.put_pixel(nx, ny, Luma([255]));
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+
+    let share = background.pixels().filter(|pixel| pixel.0[0] > 0).count() as f64 / background.len() as f64;
+    if !BACKGROUND_SHARE.contains(&share) {
+        log::info!("Background segmentation covered {:.0}% of the photo; leaving it unchanged", share * 100.0);
+        return None;
+    }
+
+    // Soft matte at full size so hair and shoulders blend into the new colour
+    let matte = imageops::resize(&imageops::blur(&background, 1.0), width, height, FilterType::Triangle);
+    let mut output = image.clone();
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        let alpha = matte.get_pixel(x, y).0[0] as f64 / 255.0;
+        for (value, &target) in pixel.0.iter_mut().zip(&color) {
+            *value = (*value as f64 * (1.0 - alpha) + target as f64 * alpha).round() as u8;
+        }
+    }
+    log::info!("Replaced {:.0}% of the photo with background {:?}", share * 100.0, color);
+    Some(output)
+}
+
+fn median_color(pixels: impl Iterator<Item = Rgb<u8>>) -> Rgb<u8> {
+    let mut channels: [Vec<u8>; 3] = Default::default();
+    for pixel in pixels {
+        for (channel, &value) in channels.iter_mut().zip(&pixel.0) {
+            channel.push(value);
+        }
+    }
+    Rgb(channels.map(|mut values| {
+        values.sort_unstable();
+        values.get(values.len() / 2).copied().unwrap_or(255)
+    }))
+}
+
+fn distance(a: &Rgb<u8>, b: &Rgb<u8>) -> f64 {
+    a.0.iter()
+        .zip(&b.0)
+        .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
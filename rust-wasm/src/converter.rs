@@ -1,118 +1,547 @@
 use crate::types::*;
-use crate::image_processor::ImageProcessor;
+use crate::image_processor::{self, ImageProcessor};
+use crate::metadata;
 use crate::pdf_processor::PdfProcessor;
+use crate::sniff;
+use crate::storage::{FileStore, MemoryStore};
 use base64::{Engine as _, engine::general_purpose};
-use image::ImageFormat;
-use std::collections::HashMap;
+use futures_util::{stream, StreamExt};
+use image::{DynamicImage, ImageFormat};
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
 use uuid::Uuid;
 
+/// 35 x 45 mm, the usual passport photo format
+const PASSPORT_ASPECT_RATIO: f32 = 3.5 / 4.5;
+
+/// Receives the steps of a running batch conversion and can stop it between them
+pub trait ProgressListener: Send + Sync {
+    fn on_event(&self, event: ProgressEvent);
+
+    /// Checked before each conversion step; `true` abandons the rest of the batch
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Listener for conversions nobody is watching
+struct NoProgress;
+
+impl ProgressListener for NoProgress {
+    fn on_event(&self, _event: ProgressEvent) {}
+}
+
+/// Reports the stages of converting one file to one target format
+#[derive(Clone)]
+struct StageReporter {
+    listener: Arc<dyn ProgressListener>,
+    file: usize,
+    format: String,
+    attempts: Arc<AtomicU32>,
+}
+
+impl StageReporter {
+    fn new(listener: Arc<dyn ProgressListener>, file: usize, format: &str) -> Self {
+        Self {
+            listener,
+            file,
+            format: format.to_string(),
+            attempts: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    fn report(&self, stage: ConversionStage) {
+        self.listener.on_event(ProgressEvent::Stage {
+            file: self.file,
+            format: self.format.clone(),
+            stage,
+        });
+    }
+
+    /// Hook for the processors that numbers their encoding attempts
+    fn attempt_hook(&self) -> Option<AttemptHook> {
+        let reporter = self.clone();
+        Some(Arc::new(move || {
+            let iteration = reporter.attempts.fetch_add(1, Ordering::Relaxed) + 1;
+            reporter.report(ConversionStage::Compressing { iteration });
+        }))
+    }
+}
+
+/// Converts uploads and keeps the results for download. Cloning is cheap and every clone shares
+/// the same storage, so one converter can serve any number of conversions at once.
+#[derive(Clone)]
 pub struct DocumentConverter {
-    pub temp_storage: HashMap<String, Vec<u8>>,
+    storage: Arc<dyn FileStore>,
     image_processor: ImageProcessor,
     pdf_processor: PdfProcessor,
+    /// Conversions of one batch, each one file to one format, that may run at once
+    max_parallel_tasks: usize,
+}
+
+impl Default for DocumentConverter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DocumentConverter {
     pub fn new() -> Self {
+        Self::with_storage(Arc::new(MemoryStore::new()))
+    }
+
+    /// Converter keeping its results in `storage`
+    pub fn with_storage(storage: Arc<dyn FileStore>) -> Self {
         Self {
-            temp_storage: HashMap::new(),
+            storage,
             image_processor: ImageProcessor::new(),
             pdf_processor: PdfProcessor::new(),
+            max_parallel_tasks: std::thread::available_parallelism().map_or(1, usize::from),
+        }
+    }
+
+    /// Copy of this converter running at most `limit` conversions of a batch at once; the
+    /// default is one per core
+    pub fn with_max_parallel_tasks(&self, limit: usize) -> Self {
+        Self {
+            max_parallel_tasks: limit.max(1),
+            ..self.clone()
         }
     }
 
     pub async fn convert_documents(
-        &mut self,
+        &self,
         request: &ConvertRequest,
     ) -> Result<Vec<ConvertedFile>, ConversionError> {
-        let mut converted_files = Vec::new();
-
-        log::info!("Starting conversion for {} files to formats: {:?}", 
-            request.files.len(), request.target_formats);
+        let uploads = Self::decode_uploads(request)?;
+        self.convert_uploads(request, uploads).await
+    }
 
-        for (file_index, file_data) in request.files.iter().enumerate() {
-            log::info!("Processing file {}/{}: {}", file_index + 1, request.files.len(), file_data.name);
-            
+    /// Decode the base64 files of a JSON request
+    pub fn decode_uploads(request: &ConvertRequest) -> Result<Vec<Upload>, ConversionError> {
+        let mut uploads = Vec::with_capacity(request.files.len());
+        for file_data in &request.files {
             // Decode base64 content
             let content = general_purpose::STANDARD
                 .decode(&file_data.content)
                 .map_err(ConversionError::Base64)?;
+            uploads.push(Upload {
+                name: file_data.name.clone(),
+                content,
+                mime_type: file_data.mime_type.clone(),
+                options: file_data.options.clone(),
+            });
+        }
+        Ok(uploads)
+    }
 
-            if content.is_empty() {
-                log::warn!("Empty file content for: {}", file_data.name);
+    /// Convert files whose content is already decoded, with the formats, limits and options of
+    /// `request` (its own `files` are not read)
+    pub async fn convert_uploads(
+        &self,
+        request: &ConvertRequest,
+        uploads: Vec<Upload>,
+    ) -> Result<Vec<ConvertedFile>, ConversionError> {
+        self.convert_uploads_with_progress(request, uploads, Arc::new(NoProgress)).await
+    }
+
+    /// `convert_uploads`, reporting each step to `progress` and stopping with
+    /// `ConversionError::Cancelled` once it asks to
+    pub async fn convert_uploads_with_progress(
+        &self,
+        request: &ConvertRequest,
+        uploads: Vec<Upload>,
+        progress: Arc<dyn ProgressListener>,
+    ) -> Result<Vec<ConvertedFile>, ConversionError> {
+        let mut converted_files = Vec::new();
+        let mut documents = Vec::with_capacity(uploads.len());
+        // Per document: its position among the uploads, its own options and a note that its
+        // content is not the type the client declared
+        let mut document_indices = Vec::with_capacity(uploads.len());
+        let mut document_options = Vec::with_capacity(uploads.len());
+        let mut type_warnings = Vec::with_capacity(uploads.len());
+
+        log::info!("Starting conversion for {} files to formats: {:?}", 
+            uploads.len(), request.target_formats);
+
+        let file_count = uploads.len();
+        for (file_index, upload) in uploads.into_iter().enumerate() {
+            log::info!("Processing file {}/{}: {}", file_index + 1, file_count, upload.name);
+
+            if upload.content.is_empty() {
+                log::warn!("Empty file content for: {}", upload.name);
+                progress.on_event(ProgressEvent::FileSkipped { file: file_index, reason: "File is empty".to_string() });
                 continue;
             }
 
-            let document = DocumentInfo {
-                name: file_data.name.clone(),
-                content,
-                mime_type: file_data.mime_type.clone(),
-                size: content.len() as u64,
+            let mut document = DocumentInfo {
+                name: upload.name,
+                size: upload.content.len() as u64,
+                content: upload.content,
+                mime_type: upload.mime_type,
             };
+            let type_warning = Self::sniff_mime_type(&mut document);
 
             log::info!("Document info - Name: {}, Size: {} bytes, MIME: {}", 
                 document.name, document.size, document.mime_type);
+            documents.push(document);
+            document_indices.push(file_index);
+            document_options.push(upload.options);
+            type_warnings.push(type_warning);
+        }
+
+        // In merge mode the PDF output is the combined document built below
+        let formats: Vec<&String> = request.target_formats
+            .iter()
+            .filter(|format| !(request.options.merge && format.eq_ignore_ascii_case("PDF")))
+            .collect();
+        if formats.is_empty() {
+            for &file_index in &document_indices {
+                progress.on_event(ProgressEvent::FileStarted { file: file_index });
+                progress.on_event(ProgressEvent::FileFinished { file: file_index });
+            }
+        }
 
-            // Convert to each target format
-            for format in &request.target_formats {
-                let max_size = request.max_sizes.get(format).copied().unwrap_or(u64::MAX);
-                
-                log::info!("Converting {} to {} (max size: {} bytes)", document.name, format, max_size);
-                
-                match self.convert_to_format(&document, format, max_size).await {
-                    Ok(converted) => {
-                        log::info!("✅ Successfully converted {} to {} ({} bytes)", 
-                            document.name, format, converted.size);
-                        converted_files.push(converted);
+        // Convert every document to every target format, several at a time. `buffered` hands the
+        // results back in task order, so the response order does not depend on which finishes first.
+        let started: Vec<AtomicBool> = documents.iter().map(|_| AtomicBool::new(false)).collect();
+        let mut finished_formats = vec![0; documents.len()];
+        let tasks = documents
+            .iter()
+            .enumerate()
+            .flat_map(|(position, document)| formats.iter().map(move |&format| (position, document, format)));
+        let mut results = stream::iter(tasks)
+            .map(|(position, document, format)| {
+                let (started, document_indices, document_options, type_warnings, progress) =
+                    (&started, &document_indices, &document_options, &type_warnings, &progress);
+                async move {
+                    let file_index = document_indices[position];
+                    let options = document_options[position].as_ref().unwrap_or(&request.options);
+                    if !started[position].swap(true, Ordering::Relaxed) {
+                        progress.on_event(ProgressEvent::FileStarted { file: file_index });
                     }
-                    Err(e) => {
-                        log::error!("❌ Failed to convert {} to {}: {}", document.name, format, e);
-                        // Add error entry instead of failing completely
-                        converted_files.push(ConvertedFile {
-                            original_name: document.name.clone(),
-                            converted_name: format!("ERROR_{}.{}", 
-                                document.name.split('.').next().unwrap_or("file"), 
-                                format.to_lowercase()
-                            ),
-                            download_url: String::new(),
-                            format: format.clone(),
-                            size: 0,
-                            compression_ratio: None,
-                        });
+                    if progress.is_cancelled() {
+                        log::info!("Conversion cancelled before {} to {}", document.name, format);
+                        return Err(ConversionError::Cancelled);
                     }
+
+                    let limits = SizeLimits {
+                        min_size: request.min_sizes.get(format).copied().unwrap_or(0),
+                        max_size: request.max_sizes.get(format).copied().unwrap_or(u64::MAX),
+                        min_dimensions: options.min_dimensions,
+                    };
+
+                    log::info!("Converting {} to {} (size limits: {}..={} bytes)", document.name, format, limits.min_size, limits.max_size);
+
+                    let stages = StageReporter::new(Arc::clone(progress), file_index, format);
+                    let mut converted = match self.convert_to_format(document, format, limits, options, &stages).await {
+                        Ok(converted) => {
+                            log::info!("✅ Successfully converted {} to {} ({} file(s), {} bytes)", 
+                                document.name, format, converted.len(), converted.iter().map(|f| f.size).sum::<u64>());
+                            converted
+                        }
+                        Err(e) => {
+                            log::error!("❌ Failed to convert {} to {}: {}", document.name, format, e);
+                            // Add error entry instead of failing completely
+                            vec![Self::failed_conversion(&document.name, format)]
+                        }
+                    };
+                    for file in &mut converted {
+                        file.warnings.extend(type_warnings[position].iter().cloned());
+                    }
+                    Ok((position, format, converted))
                 }
+            })
+            .buffered(self.max_parallel_tasks);
+
+        while let Some(result) = results.next().await {
+            let (position, format, converted) = result?;
+            let file_index = document_indices[position];
+            progress.on_event(ProgressEvent::FormatFinished {
+                file: file_index,
+                format: format.clone(),
+                results: converted.clone(),
+            });
+            converted_files.extend(converted);
+            finished_formats[position] += 1;
+            if finished_formats[position] == formats.len() {
+                progress.on_event(ProgressEvent::FileFinished { file: file_index });
             }
         }
+        drop(results);
+
+        if request.options.merge && !documents.is_empty() {
+            if progress.is_cancelled() {
+                log::info!("Conversion cancelled before merging");
+                return Err(ConversionError::Cancelled);
+            }
+            let merged_name = request.options.merged_name.as_deref().unwrap_or("merged");
+            let merged = match self.merge_to_pdf(&documents, merged_name, request).await {
+                Ok(mut converted) => {
+                    converted.warnings.extend(type_warnings.into_iter().flatten());
+                    log::info!("✅ Merged {} files into {} ({} bytes)", 
+                        documents.len(), converted.converted_name, converted.size);
+                    converted
+                }
+                Err(e) => {
+                    log::error!("❌ Failed to merge {} files into one PDF: {}", documents.len(), e);
+                    Self::failed_conversion(merged_name, "PDF")
+                }
+            };
+            progress.on_event(ProgressEvent::MergeFinished { result: merged.clone() });
+            converted_files.push(merged);
+        }
 
         log::info!("Conversion completed. {} files processed", converted_files.len());
         Ok(converted_files)
     }
 
     async fn convert_to_format(
-        &mut self,
+        &self,
         document: &DocumentInfo,
         target_format: &str,
-        max_size: u64,
-    ) -> Result<ConvertedFile, ConversionError> {
-        let original_size = document.size;
-        
-        let converted_content = match target_format.to_uppercase().as_str() {
-            "PDF" => self.convert_to_pdf(document, Some(max_size)).await?,
-            "JPEG" | "JPG" => self.convert_to_jpeg(document, max_size).await?,
-            "PNG" => self.convert_to_png(document, max_size).await?,
-            "DOCX" => self.convert_to_docx(document).await?,
+        limits: SizeLimits,
+        options: &ConversionOptions,
+        stages: &StageReporter,
+    ) -> Result<Vec<ConvertedFile>, ConversionError> {
+        let outputs = {
+            let (document, target_format, options, stages) = (document.clone(), target_format.to_string(), options.clone(), stages.clone());
+            self.on_blocking_pool(move |converter| async move {
+                converter.encode_outputs(&document, &target_format, limits, &options, &stages).await
+            })
+            .await?
+        };
+
+        // Number the outputs when one document produced several files
+        let numbered = outputs.len() > 1;
+        Ok(outputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, output)| {
+                let converted_name = Self::converted_name(&document.name, target_format, numbered.then_some(index + 1));
+                let stored = self.store_converted_file(&document.name, converted_name, document.size, target_format, output);
+                stages.report(ConversionStage::Stored);
+                stored
+            })
+            .collect())
+    }
+
+    /// Produce, scrub and size-check the outputs of one document in one format
+    async fn encode_outputs(
+        &self,
+        document: &DocumentInfo,
+        target_format: &str,
+        limits: SizeLimits,
+        options: &ConversionOptions,
+        stages: &StageReporter,
+    ) -> Result<Vec<ConversionOutput>, ConversionError> {
+        let max_size = limits.max_size;
+        let outputs = match target_format.to_uppercase().as_str() {
+            "PDF" => self.convert_to_pdf(document, Some(max_size), options, stages).await?,
+            "JPEG" | "JPG" => self.convert_to_jpeg(document, limits, options, stages).await?,
+            "PNG" => self.convert_to_png(document, limits, options, stages).await?,
+            "WEBP" => self.convert_to_webp(document, limits, options, stages).await?,
+            "DOCX" => vec![self.convert_to_docx(document).await?.into()],
             _ => return Err(ConversionError::UnsupportedFormat {
                 format: target_format.to_string(),
             }),
         };
 
+        let mut scrubbed = Vec::with_capacity(outputs.len());
+        for output in outputs {
+            scrubbed.push(self.scrub_metadata(std::slice::from_ref(document), output, max_size, options).await?);
+        }
+        let outputs = scrubbed;
+
         // Final size check
-        if converted_content.len() as u64 > max_size {
+        if let Some(oversized) = outputs.iter().find(|output| output.content.len() as u64 > max_size) {
             return Err(ConversionError::SizeLimit {
-                actual: converted_content.len() as u64,
+                actual: oversized.content.len() as u64,
                 limit: max_size,
             });
         }
+        if let Some(undersized) = outputs.iter().find(|output| (output.content.len() as u64) < limits.min_size) {
+            return Err(ConversionError::MinimumNotMet {
+                message: format!("{} output is {} bytes, below the {} byte minimum", target_format, undersized.content.len(), limits.min_size),
+            });
+        }
+        Ok(outputs)
+    }
+
+    /// Run CPU-heavy conversion work on the blocking thread pool, where it cannot stall the
+    /// async workers. `work` gets a clone of the converter to run with.
+    async fn on_blocking_pool<T, F, Fut>(&self, work: F) -> Result<T, ConversionError>
+    where
+        T: Send + 'static,
+        F: FnOnce(DocumentConverter) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, ConversionError>>,
+    {
+        let converter = self.clone();
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || runtime.block_on(work(converter)))
+            .await
+            .map_err(|e| ConversionError::TaskFailed { message: e.to_string() })?
+    }
+
+    fn image_outputs(images: Vec<EncodedImage>) -> Vec<ConversionOutput> {
+        images.into_iter().map(ConversionOutput::from).collect()
+    }
+
+    /// Combine all documents, in request order, into one PDF within the PDF size limit
+    async fn merge_to_pdf(
+        &self,
+        documents: &[DocumentInfo],
+        merged_name: &str,
+        request: &ConvertRequest,
+    ) -> Result<ConvertedFile, ConversionError> {
+        let max_size = request.max_sizes.get("PDF").copied().unwrap_or(u64::MAX);
+        log::info!("Merging {} files into {}.pdf (max size: {} bytes)", documents.len(), merged_name, max_size);
+
+        let merged = {
+            let (documents, options) = (documents.to_vec(), request.options.clone());
+            self.on_blocking_pool(move |converter| async move {
+                converter.merge_outputs(&documents, max_size, &options).await
+            })
+            .await?
+        };
+        let original_size = documents.iter().map(|d| d.size).sum();
+        let converted_name = Self::converted_name(merged_name, "PDF", None);
+        Ok(self.store_converted_file(merged_name, converted_name, original_size, "PDF", merged))
+    }
+
+    /// Build and scrub the merged PDF
+    async fn merge_outputs(&self, documents: &[DocumentInfo], max_size: u64, options: &ConversionOptions) -> Result<ConversionOutput, ConversionError> {
+        let mut parts = Vec::with_capacity(documents.len());
+        for document in documents {
+            if document.mime_type == "text/plain" {
+                let content = self.create_text_pdf(&document.content).await?;
+                parts.push(DocumentInfo {
+                    name: document.name.clone(),
+                    size: content.len() as u64,
+                    content,
+                    mime_type: "application/pdf".to_string(),
+                });
+            } else {
+                parts.push(document.clone());
+            }
+        }
+
+        let merged = self.pdf_processor.merge_documents(&parts, max_size).await?;
+        self.scrub_metadata(documents, merged.into(), max_size, options).await
+    }
+
+    /// Privacy scrub: strip EXIF/XMP/ICC and PDF Info/XMP from an output and report
+    /// what the source documents carried that the output no longer does
+    async fn scrub_metadata(
+        &self,
+        sources: &[DocumentInfo],
+        mut output: ConversionOutput,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<ConversionOutput, ConversionError> {
+        let mut removed = Vec::new();
+        for source in sources {
+            if source.mime_type == "application/pdf" {
+                removed.extend(self.pdf_processor.metadata_fields(&source.content).await);
+            } else {
+                removed.extend(metadata::scan(&source.content));
+            }
+        }
+
+        if output.content.starts_with(b"%PDF") {
+            let (content, stripped) = self.pdf_processor.scrub_metadata(&output.content).await?;
+            output.content = content;
+            removed.extend(stripped);
+        } else {
+            let (content, stripped) = metadata::strip(&output.content, options.keep_icc_profile);
+            output.content = content;
+            removed.extend(stripped);
+
+            // Re-encoding drops the colour profile; put the source's back if it still fits
+            if options.keep_icc_profile && metadata::icc_profile(&output.content).is_none() {
+                if let Some(profile) = sources.iter().find_map(|source| metadata::icc_profile(&source.content)) {
+                    let with_profile = metadata::embed_icc_profile(output.content.clone(), &profile);
+                    if with_profile.len() > output.content.len() && with_profile.len() as u64 <= max_size {
+                        output.content = with_profile;
+                        removed.retain(|field| *field != MetadataField::IccProfile);
+                    }
+                }
+            }
+        }
+
+        removed.sort();
+        removed.dedup();
+        if !removed.is_empty() {
+            log::info!("Removed metadata {:?}", removed);
+        }
+        output.metadata_removed = removed;
+        Ok(output)
+    }
+
+    /// Replace the declared MIME type with the one the content's magic bytes identify, returning
+    /// a warning when the two disagree
+    fn sniff_mime_type(document: &mut DocumentInfo) -> Option<String> {
+        let detected = sniff::detect_mime_type(&document.content)?;
+        let declared = std::mem::replace(&mut document.mime_type, detected.to_string());
+        if sniff::is_generic_mime_type(&declared) {
+            log::info!("{} was sent as {:?}; content is {}", document.name, declared, detected);
+            return None;
+        }
+        if sniff::canonical_mime_type(&declared) == detected {
+            return None;
+        }
+
+        log::warn!("{} was declared as {} but its content is {}", document.name, declared, detected);
+        Some(format!("{} was declared as {} but its content is {}, which was used instead", document.name, declared, detected))
+    }
+
+    /// Build the output file name from the original name and target format
+    fn converted_name(original_name: &str, target_format: &str, part: Option<usize>) -> String {
+        let extension = target_format.to_lowercase();
+        let base_name = original_name
+            .split('.')
+            .next()
+            .unwrap_or("document");
+        
+        match part {
+            Some(part) => format!("{}_{}.{}", base_name, part, extension),
+            None => format!("{}.{}", base_name, extension),
+        }
+    }
+
+    /// Response entry for a conversion that failed
+    fn failed_conversion(original_name: &str, format: &str) -> ConvertedFile {
+        ConvertedFile {
+            original_name: original_name.to_string(),
+            converted_name: format!("ERROR_{}.{}", 
+                original_name.split('.').next().unwrap_or("file"), 
+                format.to_lowercase()
+            ),
+            download_url: String::new(),
+            format: format.to_string(),
+            size: 0,
+            compression_ratio: None,
+            pdf_strategy: None,
+            quality: None,
+            width: None,
+            height: None,
+            metadata_removed: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Store converted content and describe it for the response
+    fn store_converted_file(
+        &self,
+        original_name: &str,
+        converted_name: String,
+        original_size: u64,
+        target_format: &str,
+        output: ConversionOutput,
+    ) -> ConvertedFile {
+        let ConversionOutput { content: converted_content, pdf_strategy, quality, dimensions, metadata_removed, warnings } = output;
 
         // Calculate compression ratio
         let compression_ratio = if original_size > 0 {
@@ -121,52 +550,62 @@ impl DocumentConverter {
             None
         };
 
-        // Generate unique filename and store
+        // Generate unique ID and store
         let file_id = Uuid::new_v4().to_string();
-        let extension = target_format.to_lowercase();
-        let base_name = document.name
-            .split('.')
-            .next()
-            .unwrap_or("document")
-            .to_string();
-        
-        let converted_name = format!("{}.{}", base_name, extension);
-
-        // Store in temporary storage
-        self.temp_storage.insert(file_id.clone(), converted_content.clone());
+        let size = converted_content.len() as u64;
+        self.storage.insert(file_id.clone(), converted_content);
         let download_url = format!("/api/download/{}", file_id);
 
         log::info!("Stored converted file: {} ({} bytes, compression: {:.2}%)", 
             converted_name, 
-            converted_content.len(),
+            size,
             compression_ratio.unwrap_or(1.0) * 100.0
         );
 
-        Ok(ConvertedFile {
-            original_name: document.name.clone(),
+        ConvertedFile {
+            original_name: original_name.to_string(),
             converted_name,
             download_url,
             format: target_format.to_string(),
-            size: converted_content.len() as u64,
+            size,
             compression_ratio,
-        })
+            pdf_strategy,
+            quality,
+            width: dimensions.map(|(width, _)| width),
+            height: dimensions.map(|(_, height)| height),
+            metadata_removed,
+            warnings,
+        }
     }
 
     // === FORMAT-SPECIFIC CONVERSION METHODS ===
 
-    async fn convert_to_pdf(&self, document: &DocumentInfo, max_size: Option<u64>) -> Result<Vec<u8>, ConversionError> {
+    async fn convert_to_pdf(&self, document: &DocumentInfo, max_size: Option<u64>, options: &ConversionOptions, stages: &StageReporter) -> Result<Vec<ConversionOutput>, ConversionError> {
+        let budget = max_size.unwrap_or(u64::MAX);
+        let pdf_processor = self.pdf_processor.with_attempt_hook(stages.attempt_hook());
         match document.mime_type.as_str() {
-            "application/pdf" => {
-                log::info!("Optimizing existing PDF");
-                self.pdf_processor.optimize_pdf(&document.content).await
-            }
-            "image/jpeg" | "image/jpg" | "image/png" | "image/webp" => {
+            "application/pdf" => match &options.extract {
+                Some(extraction) => {
+                    log::info!("Extracting pages from PDF ({:?})", extraction);
+                    let mut outputs = Vec::new();
+                    for part in pdf_processor.extract_pages(&document.content, extraction).await? {
+                        outputs.push(Self::optimize_existing_pdf(&pdf_processor, &part, budget).await?);
+                    }
+                    Ok(outputs)
+                }
+                None => {
+                    log::info!("Optimizing existing PDF");
+                    Ok(vec![Self::optimize_existing_pdf(&pdf_processor, &document.content, budget).await?])
+                }
+            },
+            mime if image_processor::is_supported_image(mime) => {
                 log::info!("Converting image to PDF");
-                self.pdf_processor.create_pdf_from_image(&document.content, max_size).await
+                let frames = self.decode_frames(document, options, stages)?;
+                Ok(vec![pdf_processor.create_pdf_from_decoded_images(frames, max_size).await?.into()])
             }
             "text/plain" => {
                 log::info!("Converting text to PDF");
-                self.create_text_pdf(&document.content).await
+                Ok(vec![self.create_text_pdf(&document.content).await?.into()])
             }
             _ => {
                 log::warn!("Unsupported format for PDF conversion: {}", document.mime_type);
@@ -177,19 +616,97 @@ impl DocumentConverter {
         }
     }
 
-    async fn convert_to_jpeg(&self, document: &DocumentInfo, max_size: u64) -> Result<Vec<u8>, ConversionError> {
-        match document.mime_type.as_str() {
-            "image/jpeg" | "image/jpg" => {
-                log::info!("Compressing JPEG image");
-                self.image_processor.compress_jpeg_to_size(&document.content, max_size).await
+    /// Encode an uploaded image, one output per TIFF page
+    async fn encode_upload(&self, document: &DocumentInfo, format: ImageFormat, limits: SizeLimits, options: &ConversionOptions, stages: &StageReporter) -> Result<Vec<ConversionOutput>, ConversionError> {
+        let mut outputs = Vec::new();
+        for frame in self.decode_frames(document, options, stages)? {
+            outputs.push(self.encode_frame(document, frame, format, limits, options, stages).await?);
+        }
+        Ok(outputs)
+    }
+
+    /// Encode one decoded image, through the passport framing or signature clean-up when the
+    /// request asks for it
+    async fn encode_frame(&self, document: &DocumentInfo, mut img: DynamicImage, format: ImageFormat, limits: SizeLimits, options: &ConversionOptions, stages: &StageReporter) -> Result<ConversionOutput, ConversionError> {
+        let dimensions = options.dimensions()?;
+        let mut warnings = Vec::new();
+        if let Some(replacement) = &options.background {
+            let (replaced, warning) = self.image_processor.replace_background(&img, replacement);
+            if let Some(warning) = &warning {
+                log::warn!("{}: {}", document.name, warning);
+            }
+            img = replaced;
+            warnings.extend(warning);
+        }
+        if let Some(passport) = &options.passport_photo {
+            let aspect_ratio = dimensions.map_or(PASSPORT_ASPECT_RATIO, DimensionSpec::aspect_ratio);
+            let (framed, warning) = self.image_processor.crop_passport_photo(&img, aspect_ratio, passport.face_height);
+            if let Some(warning) = &warning {
+                log::warn!("{}: {}", document.name, warning);
+            }
+            img = framed;
+            warnings.extend(warning);
+        }
+
+        // Page clean-up was already reported when the frames were decoded
+        let cleaned_up = options.scan || options.enhance.is_some();
+        if !cleaned_up && (options.background.is_some() || options.passport_photo.is_some()) {
+            stages.report(ConversionStage::Enhanced);
+        }
+
+        let processor = self.image_processor
+            .with_attempt_hook(stages.attempt_hook())
+            .with_output_dpi(options.output_dpi())
+            .with_lossless_webp(options.lossless_webp);
+        let encoded = match &options.signature {
+            Some(cleanup) => processor.encode_signature(&img, format, limits, dimensions, cleanup).await?,
+            None => processor.encode_image(&img, format, limits, dimensions).await?,
+        };
+        Ok(ConversionOutput { warnings, ..encoded.into() })
+    }
+
+    /// Decode an uploaded image's frames upright and apply the requested page flattening and
+    /// clean-up to each
+    fn decode_frames(&self, document: &DocumentInfo, options: &ConversionOptions, stages: &StageReporter) -> Result<Vec<DynamicImage>, ConversionError> {
+        let mut frames = image_processor::load_frames(&document.content)?;
+        stages.report(ConversionStage::Decoded);
+        if !options.scan && options.enhance.is_none() {
+            return Ok(frames);
+        }
+        for img in &mut frames {
+            if options.scan {
+                *img = self.image_processor.scan_document(img);
             }
-            "image/png" | "image/webp" => {
-                log::info!("Converting image to JPEG");
-                self.image_processor.convert_to_jpeg(&document.content, max_size).await
+            if let Some(enhancement) = &options.enhance {
+                *img = self.image_processor.enhance_document(img, enhancement);
+            }
+        }
+        stages.report(ConversionStage::Enhanced);
+        Ok(frames)
+    }
+
+    async fn optimize_existing_pdf(pdf_processor: &PdfProcessor, content: &[u8], max_size: u64) -> Result<ConversionOutput, ConversionError> {
+        let (content, strategy) = pdf_processor.optimize_pdf_to_size(content, max_size).await?;
+        Ok(ConversionOutput {
+            content,
+            pdf_strategy: Some(strategy),
+            ..ConversionOutput::default()
+        })
+    }
+
+    async fn convert_to_jpeg(&self, document: &DocumentInfo, limits: SizeLimits, options: &ConversionOptions, stages: &StageReporter) -> Result<Vec<ConversionOutput>, ConversionError> {
+        match document.mime_type.as_str() {
+            mime if image_processor::is_supported_image(mime) => {
+                log::info!("Encoding {} as JPEG (dimensions: {:?})", document.mime_type, options.dimensions);
+                self.encode_upload(document, ImageFormat::Jpeg, limits, options, stages).await
             }
             "application/pdf" => {
-                log::info!("Converting PDF to JPEG");
-                self.pdf_processor.pdf_to_image(&document.content, ImageFormat::Jpeg, max_size).await
+                log::info!("Converting PDF to JPEG ({:?}, {:?})", options.pages, options.page_layout);
+                let images = self.pdf_processor
+                    .with_attempt_hook(stages.attempt_hook())
+                    .pdf_to_images(&document.content, ImageFormat::Jpeg, limits, options)
+                    .await?;
+                Ok(Self::image_outputs(images))
             }
             _ => Err(ConversionError::UnsupportedFormat {
                 format: format!("{} to JPEG", document.mime_type),
@@ -197,19 +714,19 @@ impl DocumentConverter {
         }
     }
 
-    async fn convert_to_png(&self, document: &DocumentInfo, max_size: u64) -> Result<Vec<u8>, ConversionError> {
+    async fn convert_to_png(&self, document: &DocumentInfo, limits: SizeLimits, options: &ConversionOptions, stages: &StageReporter) -> Result<Vec<ConversionOutput>, ConversionError> {
         match document.mime_type.as_str() {
-            "image/png" => {
-                log::info!("Compressing PNG image");
-                self.image_processor.compress_png_to_size(&document.content, max_size).await
-            }
-            "image/jpeg" | "image/jpg" | "image/webp" => {
-                log::info!("Converting image to PNG");
-                self.image_processor.convert_to_png(&document.content, max_size).await
+            mime if image_processor::is_supported_image(mime) => {
+                log::info!("Encoding {} as PNG (dimensions: {:?})", document.mime_type, options.dimensions);
+                self.encode_upload(document, ImageFormat::Png, limits, options, stages).await
             }
             "application/pdf" => {
-                log::info!("Converting PDF to PNG");
-                self.pdf_processor.pdf_to_image(&document.content, ImageFormat::Png, max_size).await
+                log::info!("Converting PDF to PNG ({:?}, {:?})", options.pages, options.page_layout);
+                let images = self.pdf_processor
+                    .with_attempt_hook(stages.attempt_hook())
+                    .pdf_to_images(&document.content, ImageFormat::Png, limits, options)
+                    .await?;
+                Ok(Self::image_outputs(images))
             }
             _ => Err(ConversionError::UnsupportedFormat {
                 format: format!("{} to PNG", document.mime_type),
@@ -217,6 +734,26 @@ impl DocumentConverter {
         }
     }
 
+    async fn convert_to_webp(&self, document: &DocumentInfo, limits: SizeLimits, options: &ConversionOptions, stages: &StageReporter) -> Result<Vec<ConversionOutput>, ConversionError> {
+        match document.mime_type.as_str() {
+            mime if image_processor::is_supported_image(mime) => {
+                log::info!("Encoding {} as WebP (lossless: {}, dimensions: {:?})", document.mime_type, options.lossless_webp, options.dimensions);
+                self.encode_upload(document, ImageFormat::WebP, limits, options, stages).await
+            }
+            "application/pdf" => {
+                log::info!("Converting PDF to WebP ({:?}, {:?})", options.pages, options.page_layout);
+                let images = self.pdf_processor
+                    .with_attempt_hook(stages.attempt_hook())
+                    .pdf_to_images(&document.content, ImageFormat::WebP, limits, options)
+                    .await?;
+                Ok(Self::image_outputs(images))
+            }
+            _ => Err(ConversionError::UnsupportedFormat {
+                format: format!("{} to WebP", document.mime_type),
+            }),
+        }
+    }
+
     async fn convert_to_docx(&self, document: &DocumentInfo) -> Result<Vec<u8>, ConversionError> {
         match document.mime_type.as_str() {
             "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
@@ -243,35 +780,45 @@ impl DocumentConverter {
     async fn create_text_pdf(&self, text_content: &[u8]) -> Result<Vec<u8>, ConversionError> {
         let text = String::from_utf8_lossy(text_content);
         
+        let catalog_id = Ref::new(1);
+        let page_tree_id = Ref::new(2);
+        let page_id = Ref::new(3);
+        let font_id = Ref::new(4);
+        let content_id = Ref::new(5);
+        let font_name = Name(b"F1");
+        
         let mut pdf = Pdf::new();
-        let page_id = pdf.alloc_ref();
+        pdf.catalog(catalog_id).pages(page_tree_id);
+        pdf.pages(page_tree_id).kids([page_id]).count(1);
         
         let mut page = pdf.page(page_id);
-        page.media_box([0.0, 0.0, 612.0, 792.0]); // Letter size
-        page.parent(pdf.pages_id());
-        
-        let content_id = pdf.alloc_ref();
+        page.media_box(Rect::new(0.0, 0.0, 612.0, 792.0)); // Letter size
+        page.parent(page_tree_id);
         page.contents(content_id);
+        page.resources().fonts().pair(font_name, font_id);
         page.finish();
         
         // Add text content (simplified - real implementation would handle fonts, formatting, etc.)
-        let mut content = pdf.content_stream(content_id);
+        pdf.type1_font(font_id).base_font(Name(b"Helvetica"));
+        
+        let mut content = Content::new();
         content.begin_text();
-        content.set_font(pdf.alloc_ref(), 12.0);
+        content.set_font(font_name, 12.0);
         content.next_line(50.0, 750.0);
         
         // Split text into lines and add to PDF
-        for (i, line) in text.lines().take(50).enumerate() {
-            content.next_line(50.0, 750.0 - (i as f32 * 15.0));
-            content.show_string(line.chars().take(80).collect::<String>());
+        for line in text.lines().take(50) {
+            let line: Vec<u8> = line
+                .chars()
+                .take(80)
+                .map(|c| if (c as u32) < 256 { c as u8 } else { b'?' })
+                .collect();
+            content.show(Str(&line));
+            content.next_line(0.0, -15.0);
         }
         
         content.end_text();
-        content.finish();
-        
-        let mut page_tree = PageTreeWriter::new(&mut pdf);
-        page_tree.add_page(page_id);
-        page_tree.finish();
+        pdf.stream(content_id, &content.finish());
         
         Ok(pdf.finish())
     }
@@ -297,20 +844,19 @@ impl DocumentConverter {
         Ok(docx_xml.into_bytes())
     }
 
-    pub fn get_stored_file(&self, file_id: &str) -> Option<&Vec<u8>> {
-        self.temp_storage.get(file_id)
+    pub fn get_stored_file(&self, file_id: &str) -> Option<Arc<Vec<u8>>> {
+        self.storage.get(file_id)
     }
 
-    pub fn cleanup_temp_files(&mut self) {
-        let count = self.temp_storage.len();
-        self.temp_storage.clear();
+    /// Drop every stored file, returning how many there were and their total size
+    pub fn cleanup_temp_files(&self) -> (usize, u64) {
+        let (count, size) = self.storage.clear();
         log::info!("Cleaned up {} temporary files", count);
+        (count, size)
     }
 
     pub fn get_storage_stats(&self) -> (usize, u64) {
-        let count = self.temp_storage.len();
-        let total_size: u64 = self.temp_storage.values().map(|v| v.len() as u64).sum();
-        (count, total_size)
+        self.storage.stats()
     }
 }
 
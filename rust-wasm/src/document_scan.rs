@@ -0,0 +1,623 @@
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+use std::collections::VecDeque;
+
+/// Longest side of the downscaled copy used to find the page outline
+const DETECTION_SIDE: u32 = 400;
+
+/// The page must cover at least this fraction of the photo to be trusted
+const MIN_PAGE_AREA: f64 = 0.2;
+
+/// Above this fraction the photo is already a flat scan and is left alone
+const MAX_PAGE_AREA: f64 = 0.95;
+
+/// Smallest brightness gap between paper and background that counts as a usable edge
+const MIN_CONTRAST: f64 = 30.0;
+
+pub type Point = (f64, f64);
+
+/// Corners of the page in `image`, ordered top-left, top-right, bottom-right, bottom-left.
+/// Returns `None` when no page-like bright quadrilateral stands out from the background.
+pub fn detect_page(image: &RgbImage) -> Option<[Point; 4]> {
+    let (width, height) = image.dimensions();
+    let scale = (DETECTION_SIDE as f64 / width.max(height) as f64).min(1.0);
+    let work_width = ((width as f64 * scale).round() as u32).max(1);
+    let work_height = ((height as f64 * scale).round() as u32).max(1);
+
+    let gray = imageops::grayscale(&imageops::resize(image, work_width, work_height, FilterType::Triangle));
+    let gray = imageops::blur(&gray, 2.0);
+    let threshold = otsu_threshold(&gray, MIN_CONTRAST)?;
+
+    let component = largest_component(&gray, threshold);
+    if component.len() < 4 {
+        return None;
+    }
+    let hull = convex_hull(component);
+    let quad = largest_quadrilateral(&hull)?;
+
+    let coverage = polygon_area(&quad) / (work_width as f64 * work_height as f64);
+    if !(MIN_PAGE_AREA..=MAX_PAGE_AREA).contains(&coverage) {
+        log::info!("No page outline found (best candidate covers {:.0}% of the photo)", coverage * 100.0);
+        return None;
+    }
+
+    // Pull the corners in by one detection pixel so the blurred table edge stays outside
+    let (cx, cy) = quad.iter().fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x / 4.0, sy + y / 4.0));
+    let corners = order_corners(quad).map(|(x, y)| {
+        let (dx, dy) = (cx - x, cy - y);
+        let length = (dx * dx + dy * dy).sqrt().max(1.0);
+        ((x + dx / length + 0.5) / scale, (y + dy / length + 0.5) / scale)
+    });
+    Some(corners)
+}
+
+/// Map the quadrilateral `corners` of `image` onto an upright rectangle sized by its longest edges
+pub fn warp_to_rectangle(image: &RgbImage, corners: &[Point; 4]) -> Option<RgbImage> {
+    let [top_left, top_right, bottom_right, bottom_left] = *corners;
+    let width = distance(top_left, top_right).max(distance(bottom_left, bottom_right)).round() as u32;
+    let height = distance(top_left, bottom_left).max(distance(top_right, bottom_right)).round() as u32;
+    if width < 2 || height < 2 {
+        return None;
+    }
+
+    let (w, h) = ((width - 1) as f64, (height - 1) as f64);
+    let homography = homography(&[(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)], corners)?;
+    Some(RgbImage::from_fn(width, height, |u, v| {
+        let (u, v) = (u as f64, v as f64);
+        let z = homography[6] * u + homography[7] * v + 1.0;
+        let x = (homography[0] * u + homography[1] * v + homography[2]) / z;
+        let y = (homography[3] * u + homography[4] * v + homography[5]) / z;
+        sample_bilinear(image, x, y)
+    }))
+}
+
+/// Otsu's threshold on the brightness histogram, or `None` when the two classes are too close
+fn otsu_threshold(gray: &GrayImage, min_contrast: f64) -> Option<u8> {
+    let mut histogram = [0u64; 256];
+    for pixel in gray.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+    let total = gray.len() as f64;
+    let sum: f64 = histogram.iter().enumerate().map(|(value, &count)| value as f64 * count as f64).sum();
+
+    let (mut background_weight, mut background_sum) = (0.0, 0.0);
+    let mut best = (0.0, 0u8, 0.0);
+    for (value, &count) in histogram.iter().enumerate() {
+        background_weight += count as f64;
+        background_sum += value as f64 * count as f64;
+        let foreground_weight = total - background_weight;
+        if background_weight == 0.0 || foreground_weight == 0.0 {
+            continue;
+        }
+        let background_mean = background_sum / background_weight;
+        let foreground_mean = (sum - background_sum) / foreground_weight;
+        let variance = background_weight * foreground_weight * (background_mean - foreground_mean).powi(2);
+        if variance > best.0 {
+            best = (variance, value as u8, foreground_mean - background_mean);
+        }
+    }
+    (best.2 >= min_contrast).then_some(best.1)
+}
+
+/// Row extremes of the largest 4-connected region brighter than `threshold`; the interior
+/// is irrelevant for the convex hull
+fn largest_component(gray: &GrayImage, threshold: u8) -> Vec<Point> {
+    let (width, height) = gray.dimensions();
+    let index = |x: u32, y: u32| (y * width + x) as usize;
+    let mut labels = vec![0u32; (width * height) as usize];
+    let mut best: (usize, Vec<Point>) = (0, Vec::new());
+    let mut next_label = 0;
+
+    for start_y in 0..height {
+        for start_x in 0..width {
+            if labels[index(start_x, start_y)] != 0 || gray.get_pixel(start_x, start_y).0[0] <= threshold {
+                continue;
+            }
+            next_label += 1;
+            labels[index(start_x, start_y)] = next_label;
+            let mut row_extent = vec![(u32::MAX, 0u32); height as usize];
+            let mut size = 0;
+            let mut queue = VecDeque::from([(start_x, start_y)]);
+            while let Some((x, y)) = queue.pop_front() {
+                size += 1;
+                let extent = &mut row_extent[y as usize];
+                *extent = (extent.0.min(x), extent.1.max(x));
+                let neighbours = [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ];
+                for (nx, ny) in neighbours {
+                    if nx < width && ny < height && labels[index(nx, ny)] == 0 && gray.get_pixel(nx, ny).0[0] > threshold {
+                        labels[index(nx, ny)] = next_label;
+                        queue.push_back((nx, ny));
+                    }
+                }
+            }
+            if size > best.0 {
+                let points = row_extent
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (min, _))| *min != u32::MAX)
+                    .flat_map(|(y, &(min, max))| [(min as f64, y as f64), (max as f64, y as f64)])
+                    .collect();
+                best = (size, points);
+            }
+        }
+    }
+    best.1
+}
+
+/// Andrew's monotone chain; vertices come out counter-clockwise in y-up terms
+fn convex_hull(mut points: Vec<Point>) -> Vec<Point> {
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+
+    let mut hull: Vec<Point> = Vec::with_capacity(points.len() * 2);
+    for pass in [points.clone(), points.into_iter().rev().collect()] {
+        let start = hull.len();
+        for point in pass {
+            while hull.len() >= start + 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0.0 {
+                hull.pop();
+            }
+            hull.push(point);
+        }
+        hull.pop();
+    }
+    hull
+}
+
+/// Largest-area quadrilateral whose corners are hull vertices
+fn largest_quadrilateral(hull: &[Point]) -> Option<[Point; 4]> {
+    let n = hull.len();
+    if n < 4 {
+        return None;
+    }
+
+    let triangle = |a: Point, b: Point, c: Point| cross(a, b, c).abs() / 2.0;
+    let mut best = (0.0, [0usize; 4]);
+    // Every quadrilateral splits along a diagonal (i, j) into the largest triangle on each side
+    for i in 0..n {
+        for j in i + 2..n {
+            let side = |range: &mut dyn Iterator<Item = usize>| {
+                range
+                    .map(|k| (triangle(hull[i], hull[j], hull[k]), k))
+                    .fold((0.0, i), |best, candidate| if candidate.0 > best.0 { candidate } else { best })
+            };
+            let (left_area, k) = side(&mut (i + 1..j));
+            let (right_area, l) = side(&mut (j + 1..n).chain(0..i));
+            if left_area + right_area > best.0 {
+                best = (left_area + right_area, [i, k, j, l]);
+            }
+        }
+    }
+    (best.0 > 0.0).then(|| best.1.map(|index| hull[index]))
+}
+
+/// Order four convex corners as top-left, top-right, bottom-right, bottom-left
+fn order_corners(quad: [Point; 4]) -> [Point; 4] {
+    let (cx, cy) = quad.iter().fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x / 4.0, sy + y / 4.0));
+    let mut corners = quad;
+    // With y pointing down, increasing angle runs clockwise on screen
+    corners.sort_by(|a, b| (a.1 - cy).atan2(a.0 - cx).partial_cmp(&(b.1 - cy).atan2(b.0 - cx)).unwrap());
+    let top_left = (0..4)
+        .min_by(|&a, &b| (corners[a].0 + corners[a].1).partial_cmp(&(corners[b].0 + corners[b].1)).unwrap())
+        .unwrap_or(0);
+    corners.rotate_left(top_left);
+    corners
+}
+
+/// Projective transform taking each `from` point to the matching `to` point, as the eight
+/// coefficients of a 3x3 matrix whose last entry is 1
+fn homography(from: &[Point; 4], to: &[Point; 4]) -> Option<[f64; 8]> {
+    let mut system = [[0.0f64; 9]; 8];
+    for (row, (&(u, v), &(x, y))) in from.iter().zip(to).enumerate() {
+        system[row * 2] = [u, v, 1.0, 0.0, 0.0, 0.0, -u * x, -v * x, x];
+        system[row * 2 + 1] = [0.0, 0.0, 0.0, u, v, 1.0, -u * y, -v * y, y];
+    }
+
+    // Gaussian elimination with partial pivoting
+    for column in 0..8 {
+        let pivot = (column..8).max_by(|&a, &b| system[a][column].abs().partial_cmp(&system[b][column].abs()).unwrap())?;
+        if system[pivot][column].abs() < 1e-9 {
+            return None;
+        }
+        system.swap(column, pivot);
+        let pivot_row = system[column];
+        for (row, equation) in system.iter_mut().enumerate() {
+            if row != column {
+                let factor = equation[column] / pivot_row[column];
+                for (value, pivot_value) in equation.iter_mut().zip(pivot_row).skip(column) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+
+    let mut coefficients = [0.0; 8];
+    for (i, coefficient) in coefficients.iter_mut().enumerate() {
+        *coefficient = system[i][8] / system[i][i];
+    }
+    Some(coefficients)
+}
+
+fn sample_bilinear(image: &RgbImage, x: f64, y: f64) -> Rgb<u8> {
+    let (width, height) = image.dimensions();
+    let x = x.clamp(0.0, (width - 1) as f64);
+    let y = y.clamp(0.0, (height - 1) as f64);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+
+    let mut pixel = [0u8; 3];
+    for (channel, value) in pixel.iter_mut().enumerate() {
+        let at = |px: u32, py: u32| image.get_pixel(px, py).0[channel] as f64;
+        let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+        let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+        *value = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    Rgb(pixel)
+}
+
+fn cross(o: Point, a: Point, b: Point) -> f64 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+fn polygon_area(points: &[Point]) -> f64 {
+    let n = points.len();
+    (0..n)
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % n]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum::<f64>()
+        .abs()
+        / 2.0
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+// === PAGE ENHANCEMENT ===
+
+/// Normalized brightness above which non-ink pixels are treated as paper and whitened
+const PAPER_LEVEL: u8 = 190;
+
+/// A pixel is ink when it is this much darker than its neighbourhood
+const INK_CONTRAST: f64 = 0.15;
+
+/// Brighten uneven lighting to white paper, straighten text lines tilted by up to
+/// `max_skew_degrees` and whiten the background; `binarize` reduces the page to pure black and white
+pub fn enhance_page(image: &RgbImage, max_skew_degrees: f32, binarize: bool) -> DynamicImage {
+    let mut page = flatten_illumination(image);
+
+    let skew = estimate_skew(&ink_mask(&imageops::grayscale(&page)), max_skew_degrees);
+    if skew.abs() >= 0.1 {
+        log::info!("Deskewing page by {:.1}°", skew);
+        page = rotate(&page, skew);
+    }
+
+    let luminance = imageops::grayscale(&page);
+    let ink = ink_mask(&luminance);
+    if binarize {
+        return DynamicImage::ImageLuma8(GrayImage::from_fn(page.width(), page.height(), |x, y| {
+            Luma([if ink.get_pixel(x, y).0[0] > 0 { 0 } else { 255 }])
+        }));
+    }
+
+    // Stretch what remains so the darkest ink is black and clean paper is pure white
+    let black = percentile(&luminance, 0.02).min(100) as f64;
+    let white = 230.0;
+    for (x, y, pixel) in page.enumerate_pixels_mut() {
+        if ink.get_pixel(x, y).0[0] == 0 && luminance.get_pixel(x, y).0[0] > PAPER_LEVEL {
+            *pixel = Rgb([255, 255, 255]);
+        } else {
+            for value in pixel.0.iter_mut() {
+                *value = ((*value as f64 - black) * 255.0 / (white - black)).clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    DynamicImage::ImageRgb8(page)
+}
+
+/// Divide out the paper brightness so shadows and lighting gradients become white
+fn flatten_illumination(image: &RgbImage) -> RgbImage {
+    let background = background_luminance(&imageops::grayscale(image));
+    let mut flat = image.clone();
+    for (x, y, pixel) in flat.enumerate_pixels_mut() {
+        let gain = 255.0 / background.get_pixel(x, y).0[0].max(1) as f64;
+        for value in pixel.0.iter_mut() {
+            *value = (*value as f64 * gain).min(255.0) as u8;
+        }
+    }
+    flat
+}
+
+/// Paper brightness at every pixel: the bright percentile of coarse blocks, spread past
+/// ink-filled blocks and interpolated back to full size
+fn background_luminance(gray: &GrayImage) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let block = (width.max(height) / 32).max(8);
+    let (grid_width, grid_height) = (width.div_ceil(block), height.div_ceil(block));
+
+    let mut grid = GrayImage::from_fn(grid_width, grid_height, |gx, gy| {
+        let tile = imageops::crop_imm(gray, gx * block, gy * block, block, block).to_image();
+        Luma([percentile(&tile, 0.9)])
+    });
+    grid = GrayImage::from_fn(grid_width, grid_height, |gx, gy| {
+        let neighbours = neighbourhood(gx, gy, grid_width, grid_height).map(|(nx, ny)| grid.get_pixel(nx, ny).0[0]);
+        Luma([neighbours.max().unwrap_or(255)])
+    });
+    let smoothed = imageops::blur(&grid, 1.0);
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let gx = ((x as f64 + 0.5) / block as f64 - 0.5).clamp(0.0, (grid_width - 1) as f64);
+        let gy = ((y as f64 + 0.5) / block as f64 - 0.5).clamp(0.0, (grid_height - 1) as f64);
+        let (x0, y0) = (gx.floor() as u32, gy.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(grid_width - 1), (y0 + 1).min(grid_height - 1));
+        let (fx, fy) = (gx - x0 as f64, gy - y0 as f64);
+        let at = |px: u32, py: u32| smoothed.get_pixel(px, py).0[0] as f64;
+        let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+        let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+        Luma([(top * (1.0 - fy) + bottom * fy).round() as u8])
+    })
+}
+
+/// 3x3 neighbourhood of a grid cell, clipped to the grid
+fn neighbourhood(x: u32, y: u32, width: u32, height: u32) -> impl Iterator<Item = (u32, u32)> {
+    let xs = x.saturating_sub(1)..=(x + 1).min(width - 1);
+    let ys = y.saturating_sub(1)..=(y + 1).min(height - 1);
+    ys.flat_map(move |ny| xs.clone().map(move |nx| (nx, ny)))
+}
+
+/// Adaptive threshold against the local mean (via an integral image); 255 marks ink
+fn ink_mask(gray: &GrayImage) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let radius = (width.min(height) / 80).max(7) as i64;
+
+    let stride = width as usize + 1;
+    let mut integral = vec![0u64; stride * (height as usize + 1)];
+    for y in 0..height as usize {
+        let mut row_sum = 0u64;
+        for x in 0..width as usize {
+            row_sum += gray.get_pixel(x as u32, y as u32).0[0] as u64;
+            integral[(y + 1) * stride + x + 1] = integral[y * stride + x + 1] + row_sum;
+        }
+    }
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let x0 = (x as i64 - radius).max(0) as usize;
+        let y0 = (y as i64 - radius).max(0) as usize;
+        let x1 = (x as i64 + radius + 1).min(width as i64) as usize;
+        let y1 = (y as i64 + radius + 1).min(height as i64) as usize;
+        let sum = integral[y1 * stride + x1] + integral[y0 * stride + x0] - integral[y0 * stride + x1] - integral[y1 * stride + x0];
+        let mean = sum as f64 / ((x1 - x0) * (y1 - y0)) as f64;
+        let value = gray.get_pixel(x, y).0[0] as f64;
+        // Solid dark areas have a dark local mean, so very dark pixels always count as ink
+        Luma([if value < mean * (1.0 - INK_CONTRAST) || value < 80.0 { 255 } else { 0 }])
+    })
+}
+
+/// Text angle in degrees (positive when lines fall to the right), found by maximizing the
+/// sharpness of the row profile of ink pixels
+fn estimate_skew(ink: &GrayImage, max_degrees: f32) -> f32 {
+    if max_degrees <= 0.0 {
+        return 0.0;
+    }
+    let points: Vec<(f64, f64)> = ink
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel.0[0] > 0)
+        .map(|(x, y, _)| (x as f64, y as f64))
+        .collect();
+    // Keep the search cheap on large pages
+    let step = points.len() / 60_000 + 1;
+    let points: Vec<(f64, f64)> = points.into_iter().step_by(step).collect();
+    if points.len() < 100 {
+        return 0.0;
+    }
+
+    let height = ink.height() as f64;
+    let score = |degrees: f64| {
+        let slope = degrees.to_radians().tan();
+        let offset = ink.width() as f64 * slope.abs();
+        let mut rows = vec![0u64; (height + 2.0 * offset) as usize + 2];
+        for &(x, y) in &points {
+            rows[(y - x * slope + offset).round().max(0.0) as usize] += 1;
+        }
+        rows.iter().map(|&count| count * count).sum::<u64>()
+    };
+    let best_in = |from: f64, to: f64, step: f64| {
+        let steps = ((to - from) / step).round() as i32;
+        (0..=steps)
+            .map(|i| from + i as f64 * step)
+            .max_by_key(|&degrees| score(degrees))
+            .unwrap_or(0.0)
+    };
+
+    let max = max_degrees as f64;
+    let coarse = best_in(-max, max, 0.5);
+    best_in((coarse - 0.5).max(-max), (coarse + 0.5).min(max), 0.1) as f32
+}
+
+/// Rotate about the centre so lines at `degrees` become horizontal, filling the corners with white
+fn rotate(image: &RgbImage, degrees: f32) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    RgbImage::from_fn(width, height, |u, v| {
+        let (du, dv) = (u as f64 - cx, v as f64 - cy);
+        let x = cx + du * cos - dv * sin;
+        let y = cy + du * sin + dv * cos;
+        if x < 0.0 || y < 0.0 || x > (width - 1) as f64 || y > (height - 1) as f64 {
+            Rgb([255, 255, 255])
+        } else {
+            sample_bilinear(image, x, y)
+        }
+    })
+}
+
+/// Brightness below which `fraction` of the pixels fall
+fn percentile(gray: &GrayImage, fraction: f64) -> u8 {
+    let mut histogram = [0usize; 256];
+    for pixel in gray.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+    let target = (gray.len() as f64 * fraction) as usize;
+    let mut seen = 0;
+    for (value, &count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen > target {
+            return value as u8;
+        }
+    }
+    255
+}
+
+// === SIGNATURE CLEAN-UP ===
+
+/// Ink must be at least this much darker than the flattened paper
+const SIGNATURE_CONTRAST: f64 = 40.0;
+
+/// Faint ruled lines sit above this brightness once lighting is flattened
+const MAX_INK_LEVEL: u8 = 170;
+
+/// Isolate signature ink from its paper: lighting is flattened, ruled lines and specks are
+/// dropped, strokes are darkened by `darken` (0-1) and the image is cropped to the ink with a
+/// margin of `padding` times the ink's larger side. The paper becomes white, or transparent when
+/// `transparent` is set. Returns `None` when no ink stands out from the paper.
+pub fn clean_signature(image: &RgbImage, padding: f32, darken: f32, transparent: bool) -> Option<DynamicImage> {
+    let flat = flatten_illumination(image);
+    let luminance = imageops::grayscale(&flat);
+    let threshold = otsu_threshold(&luminance, SIGNATURE_CONTRAST)?.min(MAX_INK_LEVEL);
+
+    let mut ink = GrayImage::from_fn(flat.width(), flat.height(), |x, y| {
+        Luma([if luminance.get_pixel(x, y).0[0] <= threshold { 255 } else { 0 }])
+    });
+    remove_ruled_lines(&mut ink);
+    let ink_pixels = ink.pixels().filter(|pixel| pixel.0[0] > 0).count();
+    remove_small_components(&mut ink, (ink_pixels / 500).max(4));
+    let (left, top, right, bottom) = ink_bounds(&ink)?;
+
+    // Anti-aliased edge: full coverage at the typical ink level, none a little above the threshold
+    let ink_level = {
+        let mut levels: Vec<u8> = ink
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel.0[0] > 0)
+            .map(|(x, y, _)| luminance.get_pixel(x, y).0[0])
+            .collect();
+        levels.sort_unstable();
+        levels[levels.len() / 5] as f64
+    };
+    let edge = (threshold as f64 + 20.0).min(250.0);
+    let coverage = |x: u32, y: u32| -> f64 {
+        let near_ink = neighbourhood(x, y, ink.width(), ink.height()).any(|(nx, ny)| ink.get_pixel(nx, ny).0[0] > 0);
+        if !near_ink {
+            return 0.0;
+        }
+        ((edge - luminance.get_pixel(x, y).0[0] as f64) / (edge - ink_level).max(1.0)).clamp(0.0, 1.0)
+    };
+
+    let margin = (padding.max(0.0) * (right - left).max(bottom - top) as f32).round().max(2.0) as i64;
+    let (origin_x, origin_y) = (left as i64 - margin, top as i64 - margin);
+    let width = (right - left) as i64 + 1 + 2 * margin;
+    let height = (bottom - top) as i64 + 1 + 2 * margin;
+    let source = |u: u32, v: u32| {
+        let (x, y) = (origin_x + u as i64, origin_y + v as i64);
+        let inside = x >= 0 && y >= 0 && x < flat.width() as i64 && y < flat.height() as i64;
+        inside.then(|| {
+            let (x, y) = (x as u32, y as u32);
+            let dark = flat.get_pixel(x, y).0.map(|value| (value as f32 * (1.0 - darken.clamp(0.0, 1.0))) as u8);
+            (dark, coverage(x, y))
+        })
+    };
+
+    let (width, height) = (width as u32, height as u32);
+    Some(if transparent {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |u, v| match source(u, v) {
+            Some(([r, g, b], alpha)) if alpha > 0.0 => Rgba([r, g, b, (alpha * 255.0).round() as u8]),
+            _ => Rgba([255, 255, 255, 0]),
+        }))
+    } else {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |u, v| match source(u, v) {
+            Some((dark, alpha)) => Rgb(dark.map(|value| (value as f64 * alpha + 255.0 * (1.0 - alpha)).round() as u8)),
+            None => Rgb([255, 255, 255]),
+        }))
+    })
+}
+
+/// Clear ink runs spanning a third of the image or more; those are ruled or margin lines,
+/// not pen strokes
+fn remove_ruled_lines(ink: &mut GrayImage) {
+    let (width, height) = ink.dimensions();
+    let mut cleared = Vec::new();
+    for y in 0..height {
+        collect_long_runs((0..width).map(|x| (x, y)), width / 3, ink, &mut cleared);
+    }
+    for x in 0..width {
+        collect_long_runs((0..height).map(|y| (x, y)), height / 3, ink, &mut cleared);
+    }
+    for (x, y) in cleared {
+        ink.put_pixel(x, y, Luma([0]));
+    }
+}
+
+fn collect_long_runs(line: impl Iterator<Item = (u32, u32)>, min_length: u32, ink: &GrayImage, cleared: &mut Vec<(u32, u32)>) {
+    let mut run = Vec::new();
+    for (x, y) in line.chain(std::iter::once((u32::MAX, u32::MAX))) {
+        if x != u32::MAX && ink.get_pixel(x, y).0[0] > 0 {
+            run.push((x, y));
+            continue;
+        }
+        if run.len() as u32 >= min_length.max(2) {
+            cleared.append(&mut run);
+        }
+        run.clear();
+    }
+}
+
+/// Drop 8-connected ink regions smaller than `min_size` pixels (dust, paper texture)
+fn remove_small_components(ink: &mut GrayImage, min_size: usize) {
+    let (width, height) = ink.dimensions();
+    let mut visited = vec![false; (width * height) as usize];
+    for start in 0..width * height {
+        let (start_x, start_y) = (start % width, start / width);
+        if visited[start as usize] || ink.get_pixel(start_x, start_y).0[0] == 0 {
+            continue;
+        }
+        visited[start as usize] = true;
+        let mut component = vec![(start_x, start_y)];
+        let mut next = 0;
+        while let Some(&(x, y)) = component.get(next) {
+            next += 1;
+            for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                    let index = (ny * width + nx) as usize;
+                    if !visited[index] && ink.get_pixel(nx, ny).0[0] > 0 {
+                        visited[index] = true;
+                        component.push((nx, ny));
+                    }
+                }
+            }
+        }
+        if component.len() < min_size {
+            for (x, y) in component {
+                ink.put_pixel(x, y, Luma([0]));
+            }
+        }
+    }
+}
+
+/// Inclusive bounding box (left, top, right, bottom) of the ink
+fn ink_bounds(ink: &GrayImage) -> Option<(u32, u32, u32, u32)> {
+    ink.enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel.0[0] > 0)
+        .fold(None, |bounds, (x, y, _)| match bounds {
+            None => Some((x, y, x, y)),
+            Some((left, top, right, bottom)) => Some((left.min(x), top.min(y), right.max(x), bottom.max(y))),
+        })
+}
@@ -0,0 +1,206 @@
+use image::imageops::{self, FilterType};
+use image::{GrayImage, Luma, RgbImage};
+
+/// Longest side of the downscaled copy faces are searched in
+const DETECTION_SIDE: u32 = 320;
+
+/// Skin regions smaller than this fraction of the photo are not considered
+const MIN_FACE_AREA: f64 = 0.01;
+
+/// A face is at most this much taller than wide; skin further down is neck
+const MAX_FACE_ASPECT: f64 = 1.3;
+
+/// Regions flatter than this are arms, hands or backgrounds rather than faces
+const MIN_FACE_ASPECT: f64 = 0.8;
+
+/// Share of a face's bounding box that must be skin
+const MIN_SKIN_FILL: f64 = 0.5;
+
+/// Share of the eye band that must be darker features (eyes, brows) for a frontal face
+const MIN_FEATURE_SHARE: f64 = 0.02;
+
+/// Faces smaller than this fraction of the largest one are treated as noise
+const MIN_RELATIVE_AREA: f64 = 0.1;
+
+/// Face bounding box in pixels of the original image, from hairline to chin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaceBox {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl FaceBox {
+    pub fn center(&self) -> (f64, f64) {
+        (self.x + self.width / 2.0, self.y + self.height / 2.0)
+    }
+}
+
+/// Classical frontal face detector: skin-tone regions in YCbCr that are head-shaped and show a
+/// darker eye band. Faces are returned largest first.
+pub fn detect_faces(image: &RgbImage) -> Vec<FaceBox> {
+    let (width, height) = image.dimensions();
+    let scale = (DETECTION_SIDE as f64 / width.max(height) as f64).min(1.0);
+    let small = imageops::resize(
+        image,
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+        FilterType::Triangle,
+    );
+
+    let luminance = GrayImage::from_fn(small.width(), small.height(), |x, y| {
+        let [r, g, b] = small.get_pixel(x, y).0.map(|value| value as f64);
+        Luma([(0.299 * r + 0.587 * g + 0.114 * b) as u8])
+    });
+    let mut skin = GrayImage::from_fn(small.width(), small.height(), |x, y| {
+        Luma([if is_skin(small.get_pixel(x, y).0) { 255 } else { 0 }])
+    });
+    for _ in 0..2 {
+        skin = majority_filter(&skin);
+    }
+
+    let min_area = (MIN_FACE_AREA * skin.len() as f64) as usize;
+    let mut faces: Vec<(usize, FaceBox)> = skin_regions(&skin)
+        .into_iter()
+        .filter(|region| region.area >= min_area)
+        .filter_map(|region| face_in_region(&region, &skin, &luminance))
+        .collect();
+    faces.sort_by_key(|(area, _)| std::cmp::Reverse(*area));
+
+    let largest = faces.first().map_or(0, |(area, _)| *area);
+    faces
+        .into_iter()
+        .filter(|(area, _)| *area as f64 >= largest as f64 * MIN_RELATIVE_AREA)
+        .map(|(_, face)| FaceBox {
+            x: face.x / scale,
+            y: face.y / scale,
+            width: face.width / scale,
+            height: face.height / scale,
+        })
+        .collect()
+}
+
+/// Skin chrominance box of Chai and Ngan, ignoring pixels as dark as hair
+pub fn is_skin([r, g, b]: [u8; 3]) -> bool {
+    let (r, g, b) = (r as f64, g as f64, b as f64);
+    let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+    luma >= 55.0 && (77.0..=127.0).contains(&cb) && (133.0..=173.0).contains(&cr)
+}
+
+/// Majority vote over each 3x3 neighbourhood to close small gaps and drop speckles
+fn majority_filter(mask: &GrayImage) -> GrayImage {
+    let (width, height) = mask.dimensions();
+    GrayImage::from_fn(width, height, |x, y| {
+        let mut set = 0;
+        let mut total = 0;
+        for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+            for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                total += 1;
+                if mask.get_pixel(nx, ny).0[0] > 0 {
+                    set += 1;
+                }
+            }
+        }
+        Luma([if set * 2 > total { 255 } else { 0 }])
+    })
+}
+
+/// An 8-connected skin region with the horizontal extent of each of its rows
+struct Region {
+    area: usize,
+    top: u32,
+    /// (row, leftmost, rightmost)
+    rows: Vec<(u32, u32, u32)>,
+}
+
+fn skin_regions(skin: &GrayImage) -> Vec<Region> {
+    let (width, height) = skin.dimensions();
+    let mut visited = vec![false; (width * height) as usize];
+    let mut regions = Vec::new();
+
+    for start in 0..width * height {
+        let (start_x, start_y) = (start % width, start / width);
+        if visited[start as usize] || skin.get_pixel(start_x, start_y).0[0] == 0 {
+            continue;
+        }
+        visited[start as usize] = true;
+        let mut stack = vec![(start_x, start_y)];
+        let mut extents = vec![(u32::MAX, 0u32); height as usize];
+        let mut area = 0;
+        while let Some((x, y)) = stack.pop() {
+            area += 1;
+            let extent = &mut extents[y as usize];
+            *extent = (extent.0.min(x), extent.1.max(x));
+            for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                    let index = (ny * width + nx) as usize;
+                    if !visited[index] && skin.get_pixel(nx, ny).0[0] > 0 {
+                        visited[index] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+        }
+
+        let rows: Vec<(u32, u32, u32)> = extents
+            .iter()
+            .enumerate()
+            .filter(|(_, (left, _))| *left != u32::MAX)
+            .map(|(y, &(left, right))| (y as u32, left, right))
+            .collect();
+        regions.push(Region { area, top: rows[0].0, rows });
+    }
+    regions
+}
+
+/// The face box of a skin region, if it is head-shaped and has an eye band; paired with the
+/// skin area inside the box
+fn face_in_region(region: &Region, skin: &GrayImage, luminance: &GrayImage) -> Option<(usize, FaceBox)> {
+    let left = region.rows.iter().map(|row| row.1).min()?;
+    let right = region.rows.iter().map(|row| row.2).max()?;
+    let width = (right - left + 1) as f64;
+    let bottom = region.rows.last()?.0;
+    let height = ((bottom - region.top + 1) as f64).min(width * MAX_FACE_ASPECT);
+    if height < width * MIN_FACE_ASPECT {
+        return None;
+    }
+
+    let (x0, y0) = (left, region.top);
+    let (x1, y1) = (right + 1, region.top + height as u32);
+    let mut skin_area = 0;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            if skin.get_pixel(x, y).0[0] > 0 {
+                skin_area += 1;
+            }
+        }
+    }
+    if (skin_area as f64) < MIN_SKIN_FILL * width * height {
+        return None;
+    }
+
+    // Eyes and brows: non-skin or clearly darker pixels across the upper-middle band
+    let skin_luma = {
+        let (sum, count) = (y0..y1)
+            .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+            .filter(|&(x, y)| skin.get_pixel(x, y).0[0] > 0)
+            .fold((0u64, 0u64), |(sum, count), (x, y)| (sum + luminance.get_pixel(x, y).0[0] as u64, count + 1));
+        sum as f64 / count.max(1) as f64
+    };
+    let band_x = (x0 as f64 + width * 0.15) as u32..(x0 as f64 + width * 0.85) as u32;
+    let band_y = (y0 as f64 + height * 0.2) as u32..(y0 as f64 + height * 0.5) as u32;
+    let band: Vec<(u32, u32)> = band_y.flat_map(|y| band_x.clone().map(move |x| (x, y))).collect();
+    let features = band
+        .iter()
+        .filter(|&&(x, y)| skin.get_pixel(x, y).0[0] == 0 || (luminance.get_pixel(x, y).0[0] as f64) < skin_luma * 0.7)
+        .count();
+    let share = features as f64 / band.len().max(1) as f64;
+    if !(MIN_FEATURE_SHARE..=0.6).contains(&share) {
+        return None;
+    }
+
+    Some((skin_area, FaceBox { x: x0 as f64, y: y0 as f64, width, height }))
+}
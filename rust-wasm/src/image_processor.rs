@@ -1,153 +1,701 @@
+use crate::background;
+use crate::document_scan;
+use crate::face_detect;
+use crate::metadata;
 use crate::types::*;
-use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use crate::webp;
+use image::codecs::jpeg::{JpegEncoder, PixelDensity};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageOutputFormat, Rgb, RgbImage, Rgba, RgbaImage};
 use std::io::Cursor;
+use tiff::decoder::{Decoder as TiffDecoder, DecodingResult};
+use tiff::tags::Tag;
+use tiff::ColorType as TiffColor;
 
+/// Lowest JPEG or lossy WebP quality the encoder will go down to
+const MIN_LOSSY_QUALITY: u8 = 10;
+
+/// Below this quality, shrinking the image is preferred over losing more quality
+const MIN_FULL_SIZE_QUALITY: u8 = 50;
+
+/// Smallest longest side, in pixels, the resolution search will shrink an image to
+const MIN_IMAGE_SIDE: u32 = 16;
+
+/// Upscaling rounds tried when an image is below the minimum file size
+const MAX_UPSCALE_STEPS: usize = 6;
+
+#[derive(Clone)]
 pub struct ImageProcessor {
     compression_settings: CompressionSettings,
+    on_attempt: Option<AttemptHook>,
+}
+
+impl Default for ImageProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ImageProcessor {
     pub fn new() -> Self {
         Self {
             compression_settings: CompressionSettings::default(),
+            on_attempt: None,
         }
     }
 
-    pub fn with_settings(settings: CompressionSettings) -> Self {
+    /// Copy of this processor that records `dpi` in the metadata of the images it encodes
+    pub fn with_output_dpi(&self, dpi: Option<u16>) -> Self {
         Self {
-            compression_settings: settings,
+            compression_settings: CompressionSettings {
+                dpi,
+                ..self.compression_settings.clone()
+            },
+            on_attempt: self.on_attempt.clone(),
+        }
+    }
+
+    /// Copy of this processor that encodes WebP losslessly or lossy
+    pub fn with_lossless_webp(&self, lossless_webp: bool) -> Self {
+        Self {
+            compression_settings: CompressionSettings {
+                lossless_webp,
+                ..self.compression_settings.clone()
+            },
+            on_attempt: self.on_attempt.clone(),
+        }
+    }
+
+    /// Copy of this processor that calls `on_attempt` before every encode of its size searches
+    pub fn with_attempt_hook(&self, on_attempt: Option<AttemptHook>) -> Self {
+        Self {
+            compression_settings: self.compression_settings.clone(),
+            on_attempt,
+        }
+    }
+
+    /// Encode an image as JPEG, PNG or WebP within `limits`. With a dimension spec the image is
+    /// first brought to that exact size, and only quality may change to meet the limits.
+    pub async fn encode_image(
+        &self,
+        img: &DynamicImage,
+        format: ImageFormat,
+        limits: SizeLimits,
+        dimensions: Option<&DimensionSpec>,
+    ) -> Result<EncodedImage, ConversionError> {
+        if let Some(spec) = dimensions {
+            Self::check_min_dimensions(spec, limits)?;
+            return self.encode_at_size(&self.fit_to_dimensions(img, spec), format, limits);
+        }
+
+        let source = self.upscale_to_minimum(img, limits.min_dimensions)?;
+        let min_side = Self::min_longest_side(&source, limits.min_dimensions);
+        let encoded = if self.is_lossy(format) {
+            self.fit_lossy(&source, format, limits.max_size, min_side)?
+        } else {
+            self.fit_lossless(&source, format, limits.max_size, min_side)?
+        };
+
+        if encoded.data.len() as u64 >= limits.min_size {
+            return Ok(encoded);
         }
+        self.raise_to_min_size(&source, format, limits, true)
     }
 
-    /// Compress JPEG image to meet size requirements
-    pub async fn compress_jpeg_to_size(&self, content: &[u8], max_size: u64) -> Result<Vec<u8>, ConversionError> {
-        let img = image::load_from_memory(content)?;
-        let mut quality = self.compression_settings.quality;
-        let mut iterations = 0;
+    /// Clean up a photographed signature and encode it; with a dimension spec the cropped ink
+    /// is padded, never cropped, to the exact size. Transparency is only kept for PNG and WebP.
+    pub async fn encode_signature(
+        &self,
+        img: &DynamicImage,
+        format: ImageFormat,
+        limits: SizeLimits,
+        dimensions: Option<&DimensionSpec>,
+        cleanup: &SignatureCleanup,
+    ) -> Result<EncodedImage, ConversionError> {
+        let transparent = cleanup.transparent && matches!(format, ImageFormat::Png | ImageFormat::WebP);
+        let signature = document_scan::clean_signature(&img.to_rgb8(), cleanup.padding, cleanup.darken, transparent)
+            .ok_or_else(|| ConversionError::InvalidContent {
+                message: "No signature ink found on the page".to_string(),
+            })?;
+        log::info!("Isolated signature ink: {}x{} -> {}x{}", img.width(), img.height(), signature.width(), signature.height());
 
-        while iterations < self.compression_settings.max_iterations {
-            let compressed = self.encode_jpeg(&img, quality)?;
-            
-            if compressed.len() as u64 <= max_size || quality <= 10 {
-                log::info!("JPEG compressed to {} bytes with {}% quality", compressed.len(), quality);
-                return Ok(compressed);
+        match dimensions {
+            Some(spec) => {
+                Self::check_min_dimensions(spec, limits)?;
+                let (width, height) = spec.pixel_size();
+                self.encode_at_size(&Self::pad_to_size(&signature, width, height), format, limits)
             }
-            
-            // Reduce quality for next iteration
-            quality = std::cmp::max(10, (quality as f32 * 0.85) as u8);
-            iterations += 1;
+            None => self.encode_image(&signature, format, limits, None).await,
         }
+    }
 
-        // If still too large, try resizing
-        self.resize_and_compress_jpeg(&img, max_size).await
+    fn check_min_dimensions(spec: &DimensionSpec, limits: SizeLimits) -> Result<(), ConversionError> {
+        let (width, height) = spec.pixel_size();
+        match limits.min_dimensions.filter(|min| width < min.width || height < min.height) {
+            Some(min) => Err(ConversionError::MinimumNotMet {
+                message: format!("Required size {}x{} is below the {}x{} minimum", width, height, min.width, min.height),
+            }),
+            None => Ok(()),
+        }
     }
 
-    /// Compress PNG image to meet size requirements
-    pub async fn compress_png_to_size(&self, content: &[u8], max_size: u64) -> Result<Vec<u8>, ConversionError> {
-        let img = image::load_from_memory(content)?;
-        
-        // PNG is lossless, so we can only resize to reduce size
-        let compressed = self.encode_png(&img)?;
-        
-        if compressed.len() as u64 <= max_size {
-            log::info!("PNG size: {} bytes (within limit)", compressed.len());
-            return Ok(compressed);
+    /// Encode at the image's current pixel size; only lossy quality may change to meet `limits`
+    fn encode_at_size(&self, img: &DynamicImage, format: ImageFormat, limits: SizeLimits) -> Result<EncodedImage, ConversionError> {
+        let encoded = self.encode_exact(img, format, limits.max_size)?;
+        if encoded.data.len() as u64 >= limits.min_size {
+            return Ok(encoded);
         }
+        self.raise_to_min_size(img, format, limits, false)
+    }
 
-        // Resize image to meet size requirements
-        self.resize_and_compress_png(&img, max_size).await
+    /// Scale into `width` x `height` and centre on white, or on transparency for images with alpha
+    fn pad_to_size(img: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+        let scaled = img.resize(width, height, FilterType::Lanczos3);
+        let (x, y) = ((width - scaled.width()) as i64 / 2, (height - scaled.height()) as i64 / 2);
+        if img.color().has_alpha() {
+            let mut canvas = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 0]));
+            image::imageops::overlay(&mut canvas, &scaled.to_rgba8(), x, y);
+            DynamicImage::ImageRgba8(canvas)
+        } else {
+            let mut canvas = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+            image::imageops::overlay(&mut canvas, &scaled.to_rgb8(), x, y);
+            DynamicImage::ImageRgb8(canvas)
+        }
     }
 
-    /// Convert any image format to JPEG with size constraint
-    pub async fn convert_to_jpeg(&self, content: &[u8], max_size: u64) -> Result<Vec<u8>, ConversionError> {
-        let img = image::load_from_memory(content)?;
-        self.compress_jpeg_to_size(&self.encode_jpeg(&img, self.compression_settings.quality)?, max_size).await
+    /// Enlarge an image, keeping its aspect ratio, until both sides meet the minimum; fails when
+    /// that would take more than `MAX_OUTPUT_PIXELS`
+    fn upscale_to_minimum(&self, img: &DynamicImage, min: Option<MinDimensions>) -> Result<DynamicImage, ConversionError> {
+        let Some(min) = min.filter(|min| img.width() < min.width || img.height() < min.height) else {
+            return Ok(img.clone());
+        };
+        let scale = (min.width as f64 / img.width() as f64).max(min.height as f64 / img.height() as f64);
+        let (width, height) = ((img.width() as f64 * scale).ceil(), (img.height() as f64 * scale).ceil());
+        if width * height > MAX_OUTPUT_PIXELS as f64 {
+            return Err(ConversionError::MinimumNotMet {
+                message: format!("Meeting the {}x{} minimum would need a {}x{} image, over the {} pixel limit",
+                    min.width, min.height, width, height, MAX_OUTPUT_PIXELS),
+            });
+        }
+        let (width, height) = (width as u32, height as u32);
+        log::info!("Upscaling {}x{} image to {}x{} to meet the {}x{} minimum", 
+            img.width(), img.height(), width, height, min.width, min.height);
+        Ok(img.resize_exact(width, height, FilterType::CatmullRom))
     }
 
-    /// Convert any image format to PNG with size constraint
-    pub async fn convert_to_png(&self, content: &[u8], max_size: u64) -> Result<Vec<u8>, ConversionError> {
-        let img = image::load_from_memory(content)?;
-        self.compress_png_to_size(&self.encode_png(&img)?, max_size).await
+    /// Smallest longest side the resolution search may go down to without breaking the minimum
+    fn min_longest_side(img: &DynamicImage, min: Option<MinDimensions>) -> u32 {
+        let longest_side = img.width().max(img.height()) as f32;
+        let needed = min.map_or(0.0, |min| {
+            (min.width as f32 * longest_side / img.width() as f32)
+                .max(min.height as f32 * longest_side / img.height() as f32)
+                .ceil()
+        });
+        (needed as u32).max(MIN_IMAGE_SIDE)
     }
 
-    /// Resize image and compress to JPEG
-    async fn resize_and_compress_jpeg(&self, img: &DynamicImage, max_size: u64) -> Result<Vec<u8>, ConversionError> {
-        let (width, height) = img.dimensions();
-        let mut scale_factor = 0.9;
-        
-        for iteration in 0..self.compression_settings.max_iterations {
-            let new_width = std::cmp::max(1, (width as f32 * scale_factor) as u32);
-            let new_height = std::cmp::max(1, (height as f32 * scale_factor) as u32);
-            
-            let resized = img.resize(new_width, new_height, image::imageops::FilterType::Lanczos3);
-            let compressed = self.encode_jpeg(&resized, self.compression_settings.quality)?;
-            
-            if compressed.len() as u64 <= max_size {
-                log::info!("JPEG resized and compressed: {}x{}, {} bytes", new_width, new_height, compressed.len());
-                return Ok(compressed);
+    /// Grow an output that is below the minimum file size by raising lossy quality and, when the
+    /// pixel size is not fixed, upscaling
+    fn raise_to_min_size(&self, img: &DynamicImage, format: ImageFormat, limits: SizeLimits, allow_upscale: bool) -> Result<EncodedImage, ConversionError> {
+        let mut current = img.clone();
+        let mut last_size = 0;
+
+        for _ in 0..=MAX_UPSCALE_STEPS {
+            let encoded = if self.is_lossy(format) {
+                self.best_quality(&current, format, MIN_LOSSY_QUALITY, 100, limits.max_size)?
+            } else {
+                let compressed = self.encode_lossless(&current, format)?;
+                (compressed.len() as u64 <= limits.max_size).then(|| Self::encoded(&current, compressed, None))
+            };
+            let Some(encoded) = encoded else {
+                break;
+            };
+            if encoded.data.len() as u64 >= limits.min_size {
+                log::info!("Raised {:?} output to {} bytes ({}x{}, quality {:?}) to meet the {} byte minimum", 
+                    format, encoded.data.len(), encoded.width, encoded.height, encoded.quality, limits.min_size);
+                return Ok(encoded);
+            }
+            last_size = encoded.data.len();
+            if !allow_upscale {
+                break;
             }
-            
-            scale_factor *= 0.8;
+
+            // File size grows roughly with pixel count
+            let scale = (limits.min_size as f32 / last_size.max(1) as f32).sqrt().clamp(1.1, 2.0);
+            let width = (current.width() as f32 * scale).ceil() as u32;
+            let height = (current.height() as f32 * scale).ceil() as u32;
+            if width as u64 * height as u64 > MAX_OUTPUT_PIXELS {
+                return Err(ConversionError::MinimumNotMet {
+                    message: format!("{:?} output reached only {} bytes, below the {} byte minimum, at {}x{}; growing it further would exceed the {} pixel limit",
+                        format, last_size, limits.min_size, current.width(), current.height(), MAX_OUTPUT_PIXELS),
+                });
+            }
+            current = img.resize_exact(width, height, FilterType::CatmullRom);
         }
-        
-        Err(ConversionError::CompressionFailed {
-            message: format!("Could not compress JPEG to {} bytes after {} iterations", max_size, self.compression_settings.max_iterations),
+
+        Err(ConversionError::MinimumNotMet {
+            message: format!("{:?} output reached only {} bytes, below the {} byte minimum, without exceeding {} bytes", 
+                format, last_size, limits.min_size, limits.max_size),
         })
     }
 
-    /// Resize image and compress to PNG
-    async fn resize_and_compress_png(&self, img: &DynamicImage, max_size: u64) -> Result<Vec<u8>, ConversionError> {
-        let (width, height) = img.dimensions();
-        let mut scale_factor = 0.9;
+    /// Find the page in a photographed document, undo the perspective and crop away the
+    /// background; images without a clear page outline are returned unchanged
+    pub fn scan_document(&self, img: &DynamicImage) -> DynamicImage {
+        let rgb = img.to_rgb8();
+        let scanned = document_scan::detect_page(&rgb)
+            .and_then(|corners| document_scan::warp_to_rectangle(&rgb, &corners).map(|page| (corners, page)));
+        match scanned {
+            Some((corners, page)) => {
+                log::info!("Flattened page with corners {:?} to {}x{}", corners, page.width(), page.height());
+                DynamicImage::ImageRgb8(page)
+            }
+            None => img.clone(),
+        }
+    }
+
+    /// Flatten uneven lighting, deskew and whiten a scanned page so it compresses well
+    pub fn enhance_document(&self, img: &DynamicImage, enhancement: &Enhancement) -> DynamicImage {
+        document_scan::enhance_page(&img.to_rgb8(), enhancement.max_skew_degrees, enhancement.binarize)
+    }
+
+    /// Paint the backdrop behind a portrait in the requested colour, keeping the detected faces
+    /// intact. Returns a warning and the photo unchanged when the backdrop cannot be separated.
+    pub fn replace_background(&self, img: &DynamicImage, replacement: &BackgroundReplacement) -> (DynamicImage, Option<String>) {
+        let rgb = img.to_rgb8();
+        let faces = face_detect::detect_faces(&rgb);
+        match background::replace_background(&rgb, replacement.color, replacement.tolerance as f64, &faces) {
+            Some(replaced) => (DynamicImage::ImageRgb8(replaced), None),
+            None => (img.clone(), Some("Background could not be separated from the subject; it was left unchanged".to_string())),
+        }
+    }
+
+    /// Crop a portrait to `aspect_ratio` (width / height) so the detected face fills
+    /// `face_height` of the frame, centred. Without a single clear face a warning is returned,
+    /// and the photo is left as it is when no face is found at all.
+    pub fn crop_passport_photo(&self, img: &DynamicImage, aspect_ratio: f32, face_height: f32) -> (DynamicImage, Option<String>) {
+        let faces = face_detect::detect_faces(&img.to_rgb8());
+        let Some(face) = faces.first() else {
+            return (img.clone(), Some("No face detected; the photo was not re-centred".to_string()));
+        };
+        let warning = (faces.len() > 1).then(|| format!("{} faces detected; the photo was framed around the largest", faces.len()));
+
+        let (image_width, image_height) = (img.width() as f64, img.height() as f64);
+        let mut frame_height = face.height / face_height.clamp(0.1, 1.0) as f64;
+        let mut frame_width = frame_height * aspect_ratio as f64;
+        // A tightly shot photo cannot give the face its share; keep as much as the photo has
+        let shrink = (image_width / frame_width).min(image_height / frame_height).min(1.0);
+        // Rounding can leave the shrunk frame a hair past the photo's edge
+        frame_width = (frame_width * shrink).min(image_width);
+        frame_height = (frame_height * shrink).min(image_height);
+
+        let (center_x, center_y) = face.center();
+        let left = (center_x - frame_width / 2.0).clamp(0.0, image_width - frame_width);
+        let top = (center_y - frame_height / 2.0).clamp(0.0, image_height - frame_height);
+        log::info!("Framing face {:?} in a {:.0}x{:.0} crop at ({:.0}, {:.0})", face, frame_width, frame_height, left, top);
+
+        let cropped = img.crop_imm(left as u32, top as u32, (frame_width as u32).max(1), (frame_height as u32).max(1));
+        (cropped, warning)
+    }
+
+    /// Bring an image to the exact pixel size of `spec` using its fit policy
+    pub fn fit_to_dimensions(&self, img: &DynamicImage, spec: &DimensionSpec) -> DynamicImage {
+        let (width, height) = spec.pixel_size();
+        let background = Rgb(spec.background);
+        let flattened = self.flatten(img, background);
+
+        let fitted = match spec.fit {
+            FitPolicy::Crop => flattened.resize_to_fill(width, height, FilterType::Lanczos3),
+            FitPolicy::Stretch => flattened.resize_exact(width, height, FilterType::Lanczos3),
+            FitPolicy::Pad => {
+                let scaled = flattened.resize(width, height, FilterType::Lanczos3).to_rgb8();
+                let mut canvas = RgbImage::from_pixel(width, height, background);
+                let x = (width - scaled.width()) / 2;
+                let y = (height - scaled.height()) / 2;
+                image::imageops::overlay(&mut canvas, &scaled, x as i64, y as i64);
+                DynamicImage::ImageRgb8(canvas)
+            }
+        };
+
+        log::info!("Fitted {}x{} image to {}x{} ({:?}, aspect {:.3})", 
+            img.width(), img.height(), width, height, spec.fit, spec.aspect_ratio());
+        fitted
+    }
+
+    /// Composite any transparency onto a solid background
+    fn flatten(&self, img: &DynamicImage, background: Rgb<u8>) -> DynamicImage {
+        if !img.color().has_alpha() {
+            return DynamicImage::ImageRgb8(img.to_rgb8());
+        }
+        let rgba = img.to_rgba8();
+        let flattened = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+            let [r, g, b, a] = rgba.get_pixel(x, y).0;
+            let blend = |c: u8, bg: u8| ((c as u16 * a as u16 + bg as u16 * (255 - a as u16)) / 255) as u8;
+            Rgb([blend(r, background[0]), blend(g, background[1]), blend(b, background[2])])
+        });
+        DynamicImage::ImageRgb8(flattened)
+    }
+
+    /// Encode without changing the pixel size, lowering only lossy quality to meet `max_size`
+    fn encode_exact(&self, img: &DynamicImage, format: ImageFormat, max_size: u64) -> Result<EncodedImage, ConversionError> {
+        let encoded = if self.is_lossy(format) {
+            let max_quality = self.compression_settings.quality.clamp(MIN_LOSSY_QUALITY, 100);
+            self.best_quality(img, format, MIN_LOSSY_QUALITY, max_quality, max_size)?
+        } else {
+            let compressed = self.encode_lossless(img, format)?;
+            (compressed.len() as u64 <= max_size).then(|| Self::encoded(img, compressed, None))
+        };
+
+        encoded.ok_or_else(|| ConversionError::CompressionFailed {
+            message: format!("Could not fit a {}x{} {:?} image within {} bytes", img.width(), img.height(), format, max_size),
+        })
+    }
+
+    /// JPEG and lossy WebP search: keep the image as large as possible, then pick the highest
+    /// quality that still fits, never shrinking the longest side below `min_side`
+    fn fit_lossy(&self, img: &DynamicImage, format: ImageFormat, max_size: u64, min_side: u32) -> Result<EncodedImage, ConversionError> {
+        let max_quality = self.compression_settings.quality.clamp(MIN_LOSSY_QUALITY, 100);
+        let preferred_floor = MIN_FULL_SIZE_QUALITY.min(max_quality);
+
+        // Full resolution at an acceptable quality
+        if let Some(encoded) = self.best_quality(img, format, preferred_floor, max_quality, max_size)? {
+            log::info!("{:?} compressed to {} bytes with {:?}% quality", format, encoded.data.len(), encoded.quality);
+            return Ok(encoded);
+        }
+
+        // Otherwise the largest size that fits at the quality floor, then the best quality at that size
+        let mut resized = None;
+        for floor in [preferred_floor, MIN_LOSSY_QUALITY] {
+            if let Some(candidate) = self.largest_fitting_size(img, max_size, min_side, |candidate| self.encode_lossy(candidate, format, floor))? {
+                resized = Some((candidate, floor));
+                break;
+            }
+        }
+        let Some((resized, floor)) = resized else {
+            return Err(ConversionError::CompressionFailed {
+                message: format!("Could not compress {:?} to {} bytes at any size or quality", format, max_size),
+            });
+        };
+        match self.best_quality(&resized, format, floor, max_quality, max_size)? {
+            Some(encoded) => {
+                log::info!("{:?} resized and compressed: {}x{}, {} bytes with {:?}% quality", 
+                    format, encoded.width, encoded.height, encoded.data.len(), encoded.quality);
+                Ok(encoded)
+            }
+            None => Err(ConversionError::CompressionFailed {
+                message: format!("Could not compress {:?} to {} bytes at any size or quality", format, max_size),
+            }),
+        }
+    }
+
+    /// PNG and lossless WebP search, never shrinking the longest side below `min_side`
+    fn fit_lossless(&self, img: &DynamicImage, format: ImageFormat, max_size: u64, min_side: u32) -> Result<EncodedImage, ConversionError> {
+        // Lossless output can only get smaller by resizing
+        let compressed = self.encode_lossless(img, format)?;
         
-        for iteration in 0..self.compression_settings.max_iterations {
-            let new_width = std::cmp::max(1, (width as f32 * scale_factor) as u32);
-            let new_height = std::cmp::max(1, (height as f32 * scale_factor) as u32);
-            
-            let resized = img.resize(new_width, new_height, image::imageops::FilterType::Lanczos3);
-            let compressed = self.encode_png(&resized)?;
-            
+        if compressed.len() as u64 <= max_size {
+            log::info!("{:?} size: {} bytes (within limit)", format, compressed.len());
+            return Ok(Self::encoded(img, compressed, None));
+        }
+
+        // Resize image to meet size requirements
+        match self.largest_fitting_size(img, max_size, min_side, |candidate| self.encode_lossless(candidate, format))? {
+            Some(resized) => {
+                let compressed = self.encode_lossless(&resized, format)?;
+                log::info!("{:?} resized: {}x{}, {} bytes", format, resized.width(), resized.height(), compressed.len());
+                Ok(Self::encoded(&resized, compressed, None))
+            }
+            None => Err(ConversionError::CompressionFailed {
+                message: format!("Could not compress {:?} to {} bytes at any size", format, max_size),
+            }),
+        }
+    }
+
+    /// Binary search for the highest lossy quality in `low..=high` that fits within `max_size`
+    fn best_quality(&self, img: &DynamicImage, format: ImageFormat, low: u8, high: u8, max_size: u64) -> Result<Option<EncodedImage>, ConversionError> {
+        let (mut low, mut high) = (low as u32, high as u32);
+        let mut best = None;
+
+        while low <= high {
+            let quality = (low + high) / 2;
+            let compressed = self.encode_lossy(img, format, quality as u8)?;
             if compressed.len() as u64 <= max_size {
-                log::info!("PNG resized: {}x{}, {} bytes", new_width, new_height, compressed.len());
-                return Ok(compressed);
+                best = Some(Self::encoded(img, compressed, Some(quality as u8)));
+                low = quality + 1;
+            } else if quality == 0 {
+                break;
+            } else {
+                high = quality - 1;
             }
-            
-            scale_factor *= 0.8;
         }
-        
-        Err(ConversionError::CompressionFailed {
-            message: format!("Could not compress PNG to {} bytes after {} iterations", max_size, self.compression_settings.max_iterations),
-        })
+
+        Ok(best)
+    }
+
+    /// Binary search for the largest downscaled copy of `img`, with a longest side of at least
+    /// `min_side`, whose encoding fits within `max_size`
+    fn largest_fitting_size<F>(&self, img: &DynamicImage, max_size: u64, min_side: u32, encode: F) -> Result<Option<DynamicImage>, ConversionError>
+    where
+        F: Fn(&DynamicImage) -> Result<Vec<u8>, ConversionError>,
+    {
+        let longest_side = img.width().max(img.height());
+        let (mut low, mut high) = (min_side.min(longest_side), longest_side.saturating_sub(1));
+        let mut best = None;
+
+        while low <= high {
+            let side = (low + high) / 2;
+            // `resize` keeps the aspect ratio, fitting the image inside a side x side box
+            let resized = img.resize(side, side, FilterType::Lanczos3);
+            if encode(&resized)?.len() as u64 <= max_size {
+                best = Some(resized);
+                low = side + 1;
+            } else if side == 0 {
+                break;
+            } else {
+                high = side - 1;
+            }
+        }
+
+        Ok(best)
+    }
+
+    fn encoded(img: &DynamicImage, data: Vec<u8>, quality: Option<u8>) -> EncodedImage {
+        EncodedImage {
+            data,
+            quality,
+            width: img.width(),
+            height: img.height(),
+        }
+    }
+
+    /// Arrange images on a white canvas, `columns` per row; a single column stacks them vertically
+    pub fn arrange_grid(&self, images: &[DynamicImage], columns: u32) -> DynamicImage {
+        let sizes: Vec<_> = images.iter().map(|img| (img.width(), img.height())).collect();
+        let (column_widths, row_heights) = grid_cells(&sizes, columns);
+        let columns = column_widths.len();
+
+        let width = column_widths.iter().sum::<u32>().max(1);
+        let height = row_heights.iter().sum::<u32>().max(1);
+        let mut canvas = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+
+        for (i, img) in images.iter().enumerate() {
+            let (column, row) = (i % columns, i / columns);
+            // Center each image horizontally within its cell, aligned to the top
+            let x = column_widths[..column].iter().sum::<u32>() + (column_widths[column] - img.width()) / 2;
+            let y = row_heights[..row].iter().sum::<u32>();
+            image::imageops::overlay(&mut canvas, &img.to_rgb8(), x as i64, y as i64);
+        }
+
+        log::info!("Arranged {} images in {} columns: {}x{}", images.len(), columns, width, height);
+        DynamicImage::ImageRgb8(canvas)
+    }
+
+    fn report_attempt(&self) {
+        if let Some(on_attempt) = &self.on_attempt {
+            on_attempt();
+        }
+    }
+
+    /// Whether `format` is encoded with a quality setting rather than losslessly
+    fn is_lossy(&self, format: ImageFormat) -> bool {
+        format == ImageFormat::Jpeg || (format == ImageFormat::WebP && !self.compression_settings.lossless_webp)
     }
 
-    /// Encode image as JPEG with specified quality
+    fn encode_lossy(&self, img: &DynamicImage, format: ImageFormat, quality: u8) -> Result<Vec<u8>, ConversionError> {
+        self.report_attempt();
+        match format {
+            ImageFormat::Jpeg => self.encode_jpeg(img, quality),
+            ImageFormat::WebP => webp::encode(img, Some(quality)),
+            other => Err(ConversionError::UnsupportedFormat {
+                format: format!("{:?}", other),
+            }),
+        }
+    }
+
+    fn encode_lossless(&self, img: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, ConversionError> {
+        self.report_attempt();
+        match format {
+            ImageFormat::Png => self.encode_png(img),
+            ImageFormat::WebP => webp::encode(img, None),
+            other => Err(ConversionError::UnsupportedFormat {
+                format: format!("{:?}", other),
+            }),
+        }
+    }
+
+    /// Encode image as JPEG with specified quality, recording the DPI in the JFIF header
     fn encode_jpeg(&self, img: &DynamicImage, quality: u8) -> Result<Vec<u8>, ConversionError> {
         let mut output = Vec::new();
-        let mut cursor = Cursor::new(&mut output);
+        let mut encoder = JpegEncoder::new_with_quality(&mut output, quality);
+        if let Some(dpi) = self.compression_settings.dpi {
+            encoder.set_pixel_density(PixelDensity::dpi(dpi));
+        }
         
-        img.write_to(&mut cursor, ImageOutputFormat::Jpeg(quality))?;
+        encoder.encode_image(img)?;
         Ok(output)
     }
 
-    /// Encode image as PNG
+    /// Encode image as PNG, recording the DPI in a pHYs chunk
     fn encode_png(&self, img: &DynamicImage) -> Result<Vec<u8>, ConversionError> {
         let mut output = Vec::new();
         let mut cursor = Cursor::new(&mut output);
         
         img.write_to(&mut cursor, ImageOutputFormat::Png)?;
+        if let Some(dpi) = self.compression_settings.dpi {
+            output = Self::with_png_density(output, dpi);
+        }
         Ok(output)
     }
 
-    /// Get optimal dimensions for target file size
-    pub fn calculate_target_dimensions(&self, width: u32, height: u32, current_size: u64, target_size: u64) -> (u32, u32) {
-        if current_size <= target_size {
-            return (width, height);
+    /// Insert a pHYs chunk right after IHDR; the `image` PNG encoder has no density option
+    fn with_png_density(png: Vec<u8>, dpi: u16) -> Vec<u8> {
+        // 8-byte signature followed by the 25-byte IHDR chunk
+        const IHDR_END: usize = 8 + 25;
+        if png.len() < IHDR_END || &png[12..16] != b"IHDR" {
+            return png;
         }
 
-        let scale_factor = (target_size as f64 / current_size as f64).sqrt();
-        let new_width = std::cmp::max(1, (width as f64 * scale_factor) as u32);
-        let new_height = std::cmp::max(1, (height as f64 * scale_factor) as u32);
-        
-        (new_width, new_height)
+        let pixels_per_meter = (dpi as f64 / 0.0254).round() as u32;
+        let mut data = Vec::with_capacity(9);
+        data.extend_from_slice(&pixels_per_meter.to_be_bytes());
+        data.extend_from_slice(&pixels_per_meter.to_be_bytes());
+        data.push(1); // unit: metre
+        let chunk = metadata::png_chunk(b"pHYs", &data);
+
+        let mut output = Vec::with_capacity(png.len() + chunk.len());
+        output.extend_from_slice(&png[..IHDR_END]);
+        output.extend_from_slice(&chunk);
+        output.extend_from_slice(&png[IHDR_END..]);
+        output
+    }
+}
+
+// === PAGE LAYOUT ===
+
+/// Size of the canvas `arrange_grid` builds for images of `sizes`, `columns` per row
+pub fn grid_canvas_size(sizes: &[(u32, u32)], columns: u32) -> (u64, u64) {
+    let (column_widths, row_heights) = grid_cells(sizes, columns);
+    (
+        column_widths.iter().map(|&width| width as u64).sum(),
+        row_heights.iter().map(|&height| height as u64).sum(),
+    )
+}
+
+/// Width of each grid column and height of each row: the widest and tallest image in it
+fn grid_cells(sizes: &[(u32, u32)], columns: u32) -> (Vec<u32>, Vec<u32>) {
+    let columns = columns.clamp(1, sizes.len().max(1) as u32) as usize;
+    let rows = sizes.len().div_ceil(columns);
+
+    let mut column_widths = vec![0u32; columns];
+    let mut row_heights = vec![0u32; rows];
+    for (i, &(width, height)) in sizes.iter().enumerate() {
+        column_widths[i % columns] = column_widths[i % columns].max(width);
+        row_heights[i / columns] = row_heights[i / columns].max(height);
     }
-}
\ No newline at end of file
+    (column_widths, row_heights)
+}
+
+// === EXIF ORIENTATION ===
+
+/// Decode an uploaded image and rotate/flip it upright according to its EXIF Orientation tag.
+/// Re-encoded output carries no EXIF, so the stale tag is dropped along the way.
+pub fn load_image(content: &[u8]) -> Result<DynamicImage, ConversionError> {
+    let img = image::load_from_memory(content)?;
+    match metadata::exif_orientation(content) {
+        Some(orientation) if orientation != 1 => {
+            log::info!("Applying EXIF orientation {}", orientation);
+            Ok(apply_orientation(img, orientation))
+        }
+        _ => Ok(img),
+    }
+}
+
+/// Rotate/flip an image so that EXIF orientation `orientation` (1-8) becomes 1
+pub fn apply_orientation(img: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+// === MULTI-FRAME INPUT ===
+
+/// Raster MIME types accepted as image uploads
+const IMAGE_MIME_TYPES: [&str; 10] = [
+    "image/jpeg", "image/jpg", "image/png", "image/webp", "image/tiff",
+    "image/tif", "image/bmp", "image/x-ms-bmp", "image/x-bmp", "image/gif",
+];
+
+pub fn is_supported_image(mime_type: &str) -> bool {
+    IMAGE_MIME_TYPES.contains(&mime_type)
+}
+
+/// Decode every frame of an upload, upright: each page of a (multi-page) TIFF, otherwise the
+/// single image, or the first frame of an animated GIF
+pub fn load_frames(content: &[u8]) -> Result<Vec<DynamicImage>, ConversionError> {
+    if image::guess_format(content).ok() != Some(ImageFormat::Tiff) {
+        return Ok(vec![load_image(content)?]);
+    }
+
+    let mut decoder = TiffDecoder::new(Cursor::new(content)).map_err(tiff_error)?;
+    let mut frames = vec![tiff_frame(&mut decoder)?];
+    while decoder.more_images() {
+        decoder.next_image().map_err(tiff_error)?;
+        frames.push(tiff_frame(&mut decoder)?);
+    }
+    log::info!("Decoded {} TIFF frame(s)", frames.len());
+    Ok(frames)
+}
+
+/// The TIFF decoder's current frame, in the sample layouts `image` itself decodes from TIFF
+fn tiff_frame(decoder: &mut TiffDecoder<Cursor<&[u8]>>) -> Result<DynamicImage, ConversionError> {
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    let color_type = decoder.colortype().map_err(tiff_error)?;
+    let orientation = decoder.find_tag_unsigned::<u16>(Tag::Orientation).ok().flatten();
+
+    let frame = match (color_type, decoder.read_image().map_err(tiff_error)?) {
+        (TiffColor::Gray(8), DecodingResult::U8(data)) => image::GrayImage::from_raw(width, height, data).map(DynamicImage::ImageLuma8),
+        (TiffColor::Gray(16), DecodingResult::U16(data)) => image::ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16),
+        (TiffColor::GrayA(8), DecodingResult::U8(data)) => image::GrayAlphaImage::from_raw(width, height, data).map(DynamicImage::ImageLumaA8),
+        (TiffColor::GrayA(16), DecodingResult::U16(data)) => image::ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA16),
+        (TiffColor::RGB(8), DecodingResult::U8(data)) => RgbImage::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+        (TiffColor::RGB(16), DecodingResult::U16(data)) => image::ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16),
+        (TiffColor::RGBA(8), DecodingResult::U8(data)) => RgbaImage::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+        (TiffColor::RGBA(16), DecodingResult::U16(data)) => image::ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16),
+        (TiffColor::CMYK(8), DecodingResult::U8(data)) => {
+            let rgb = data
+                .chunks_exact(4)
+                .flat_map(|cmyk| {
+                    let black = 255 - cmyk[3] as u16;
+                    [0, 1, 2].map(|channel| ((255 - cmyk[channel] as u16) * black / 255) as u8)
+                })
+                .collect();
+            RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+        }
+        (color_type, _) => {
+            return Err(ConversionError::UnsupportedFormat {
+                format: format!("TIFF with {:?} pixels", color_type),
+            })
+        }
+    }
+    .ok_or_else(|| ConversionError::InvalidContent {
+        message: format!("TIFF frame data does not fill its {}x{} size", width, height),
+    })?;
+
+    Ok(match orientation {
+        Some(orientation) if orientation != 1 => apply_orientation(frame, orientation),
+        _ => frame,
+    })
+}
+
+fn tiff_error(error: tiff::TiffError) -> ConversionError {
+    ConversionError::InvalidContent {
+        message: format!("Unreadable TIFF: {}", error),
+    }
+}
@@ -0,0 +1,337 @@
+use crate::converter::{DocumentConverter, ProgressListener};
+use crate::types::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Finished jobs are forgotten this long after they end
+const JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// Updates a slow event subscriber may fall behind by before it misses some
+const JOB_UPDATE_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobState::Completed | JobState::Failed | JobState::Cancelled)
+    }
+}
+
+/// Status of one uploaded file within a job
+#[derive(Debug, Clone, Serialize)]
+pub struct FileStatus {
+    pub name: String,
+    pub state: JobState,
+    /// Outputs of the target formats finished so far
+    pub results: Vec<ConvertedFile>,
+    pub error: Option<String>,
+}
+
+/// Snapshot of a job, as returned by `GET /jobs/{id}`
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub job_id: String,
+    pub state: JobState,
+    /// Share of the conversion steps done, from 0.0 to 1.0
+    pub progress: f32,
+    pub files: Vec<FileStatus>,
+    /// Combined PDF of a merge request
+    pub merged: Option<ConvertedFile>,
+    pub error: Option<String>,
+    pub created_at: String,
+}
+
+/// Update pushed to the subscribers of a job's events
+#[derive(Debug, Clone)]
+pub enum JobUpdate {
+    Progress(ProgressEvent),
+    /// Final status; no updates follow it
+    Finished(JobStatus),
+}
+
+/// Mutable part of a job, updated by its worker and read by status requests
+struct JobProgress {
+    status: JobStatus,
+    steps_done: usize,
+    finished_at: Option<Instant>,
+}
+
+struct Job {
+    progress: Mutex<JobProgress>,
+    cancelled: AtomicBool,
+    updates: broadcast::Sender<JobUpdate>,
+    /// Conversion steps per file: one per target format converted on its own
+    steps_per_file: usize,
+    /// Steps in the whole job, the merge included
+    total_steps: usize,
+}
+
+impl Job {
+    fn new(request: &ConvertRequest, uploads: &[Upload]) -> Self {
+        let merge = request.options.merge && !uploads.is_empty();
+        let steps_per_file = request.target_formats
+            .iter()
+            .filter(|format| !(request.options.merge && format.eq_ignore_ascii_case("PDF")))
+            .count();
+        let status = JobStatus {
+            job_id: Uuid::new_v4().to_string(),
+            state: JobState::Queued,
+            progress: 0.0,
+            files: uploads
+                .iter()
+                .map(|upload| FileStatus {
+                    name: upload.name.clone(),
+                    state: JobState::Queued,
+                    results: Vec::new(),
+                    error: None,
+                })
+                .collect(),
+            merged: None,
+            error: None,
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        Self {
+            progress: Mutex::new(JobProgress { status, steps_done: 0, finished_at: None }),
+            cancelled: AtomicBool::new(false),
+            updates: broadcast::channel(JOB_UPDATE_BUFFER).0,
+            steps_per_file,
+            total_steps: uploads.len() * steps_per_file + merge as usize,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, JobProgress> {
+        self.progress.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn status(&self) -> JobStatus {
+        self.lock().status.clone()
+    }
+
+    /// Current status and a receiver for every update after it
+    fn subscribe(&self) -> (JobStatus, broadcast::Receiver<JobUpdate>) {
+        // Taken under the lock, so no update falls between the snapshot and the subscription
+        let progress = self.lock();
+        (progress.status.clone(), self.updates.subscribe())
+    }
+
+    /// Move a queued job to running; false when it already finished, e.g. cancelled while queued
+    fn start(&self) -> bool {
+        let mut progress = self.lock();
+        if progress.status.state != JobState::Queued {
+            return false;
+        }
+        progress.status.state = JobState::Running;
+        true
+    }
+
+    /// Cancel the job if no worker has started it, checked and changed under the one lock `start`
+    /// takes, so a running job is never reported cancelled
+    fn cancel_queued(&self) {
+        let mut progress = self.lock();
+        if progress.status.state == JobState::Queued {
+            self.record_outcome(&mut progress, Err(ConversionError::Cancelled));
+        }
+    }
+
+    /// Record how the conversion ended
+    fn finish(&self, result: Result<Vec<ConvertedFile>, ConversionError>) {
+        self.record_outcome(&mut self.lock(), result);
+    }
+
+    /// Files the conversion never reached share the job's outcome. Only the first outcome counts,
+    /// so listeners see a single `Finished` update.
+    fn record_outcome(&self, progress: &mut JobProgress, result: Result<Vec<ConvertedFile>, ConversionError>) {
+        if progress.status.state.is_finished() {
+            return;
+        }
+        let (state, error) = match result {
+            Ok(_) => (JobState::Completed, None),
+            Err(ConversionError::Cancelled) => (JobState::Cancelled, None),
+            Err(e) => (JobState::Failed, Some(e.to_string())),
+        };
+        for file in &mut progress.status.files {
+            if !file.state.is_finished() {
+                file.state = state;
+            }
+        }
+        if state == JobState::Completed {
+            progress.status.progress = 1.0;
+        }
+        progress.status.state = state;
+        progress.status.error = error;
+        progress.finished_at = Some(Instant::now());
+        // Nobody may be listening
+        let _ = self.updates.send(JobUpdate::Finished(progress.status.clone()));
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        self.lock().finished_at.is_some_and(|finished| now.duration_since(finished) > JOB_RETENTION)
+    }
+}
+
+impl ProgressListener for Job {
+    fn on_event(&self, event: ProgressEvent) {
+        let mut progress = self.lock();
+        let progress = &mut *progress;
+        let files = &mut progress.status.files;
+        let update = JobUpdate::Progress(event.clone());
+        match event {
+            ProgressEvent::FileStarted { file } => files[file].state = JobState::Running,
+            ProgressEvent::Stage { .. } => {}
+            ProgressEvent::FileSkipped { file, reason } => {
+                files[file].state = JobState::Failed;
+                files[file].error = Some(reason);
+                progress.steps_done += self.steps_per_file;
+            }
+            ProgressEvent::FormatFinished { file, format, results } => {
+                log::info!("Job {}: {} finished for {}", progress.status.job_id, format, files[file].name);
+                files[file].results.extend(results);
+                progress.steps_done += 1;
+            }
+            ProgressEvent::FileFinished { file } => files[file].state = JobState::Completed,
+            ProgressEvent::MergeFinished { result } => {
+                progress.status.merged = Some(result);
+                progress.steps_done += 1;
+            }
+        }
+        progress.status.progress = (progress.steps_done as f32 / self.total_steps.max(1) as f32).min(1.0);
+        let _ = self.updates.send(update);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+struct QueuedJob {
+    job: Arc<Job>,
+    request: ConvertRequest,
+    uploads: Vec<Upload>,
+}
+
+/// Conversion jobs run by a fixed pool of worker threads, away from the HTTP workers
+pub struct JobQueue {
+    jobs: Mutex<HashMap<String, Arc<Job>>>,
+    sender: SyncSender<QueuedJob>,
+    receiver: Arc<Mutex<Receiver<QueuedJob>>>,
+    converter: DocumentConverter,
+    workers: AtomicUsize,
+}
+
+impl JobQueue {
+    /// Queue converting with `converter`; at most `capacity` jobs wait for a worker. Jobs only
+    /// run once `start_workers` has been called.
+    pub fn new(converter: DocumentConverter, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            sender,
+            receiver: Arc::new(Mutex::new(receiver)),
+            converter,
+            workers: AtomicUsize::new(0),
+        }
+    }
+
+    /// Start `count` more worker threads taking jobs off the queue
+    pub fn start_workers(&self, count: usize) {
+        for _ in 0..count {
+            let worker = self.workers.fetch_add(1, Ordering::Relaxed);
+            let receiver = Arc::clone(&self.receiver);
+            let converter = self.converter.clone();
+            std::thread::Builder::new()
+                .name(format!("conversion-worker-{}", worker))
+                .spawn(move || run_worker(&receiver, &converter))
+                .expect("failed to start conversion worker");
+        }
+    }
+
+    /// Queue a conversion; fails with `QueueFull` when every waiting slot is taken and with
+    /// `WorkersStopped` when no worker is left to run it
+    pub fn submit(&self, request: ConvertRequest, uploads: Vec<Upload>) -> Result<JobStatus, ConversionError> {
+        let job = Arc::new(Job::new(&request, &uploads));
+        let status = job.status();
+
+        let mut jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        jobs.retain(|_, job| !job.expired(now));
+        match self.sender.try_send(QueuedJob { job: Arc::clone(&job), request, uploads }) {
+            Ok(()) => {
+                log::info!("Queued job {} ({} files)", status.job_id, status.files.len());
+                jobs.insert(status.job_id.clone(), job);
+                Ok(status)
+            }
+            Err(TrySendError::Full(_)) => Err(ConversionError::QueueFull),
+            Err(TrySendError::Disconnected(_)) => {
+                log::error!("Conversion workers have stopped; rejecting job {}", status.job_id);
+                Err(ConversionError::WorkersStopped)
+            }
+        }
+    }
+
+    pub fn status(&self, job_id: &str) -> Option<JobStatus> {
+        let jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        jobs.get(job_id).map(|job| job.status())
+    }
+
+    /// Current status of a job and a receiver for its later updates
+    pub fn subscribe(&self, job_id: &str) -> Option<(JobStatus, broadcast::Receiver<JobUpdate>)> {
+        let jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        jobs.get(job_id).map(|job| job.subscribe())
+    }
+
+    /// Ask a job to stop. A queued job is cancelled at once; a running one stops before its next
+    /// conversion step.
+    pub fn cancel(&self, job_id: &str) -> Option<JobStatus> {
+        let jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        let job = jobs.get(job_id)?;
+        job.cancelled.store(true, Ordering::Relaxed);
+        job.cancel_queued();
+        log::info!("Cancellation requested for job {}", job_id);
+        Some(job.status())
+    }
+}
+
+fn run_worker(receiver: &Mutex<Receiver<QueuedJob>>, converter: &DocumentConverter) {
+    let runtime = match tokio::runtime::Builder::new_current_thread().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::error!("Conversion worker could not start its runtime: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        let next = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+        let Ok(QueuedJob { job, request, uploads }) = next else {
+            // The queue was dropped
+            return;
+        };
+        let job_id = job.status().job_id;
+        if !job.start() {
+            log::info!("Skipping job {}, which finished while queued", job_id);
+            continue;
+        }
+
+        log::info!("Starting job {}", job_id);
+        let result = runtime.block_on(converter.convert_uploads_with_progress(&request, uploads, Arc::clone(&job) as Arc<dyn ProgressListener>));
+        match &result {
+            Ok(files) => log::info!("Job {} finished with {} file(s)", job_id, files.len()),
+            Err(e) => log::warn!("Job {} ended: {}", job_id, e),
+        }
+        job.finish(result);
+    }
+}
@@ -3,8 +3,20 @@
 //! This library provides document conversion capabilities for competitive exam applications.
 //! It supports converting between various formats (PDF, JPEG, PNG, DOCX) with size optimization.
 
+pub mod background;
 pub mod converter;
+pub mod document_scan;
+pub mod face_detect;
+pub mod image_processor;
+pub mod jobs;
+pub mod metadata;
+pub mod pdf_processor;
+pub mod pdf_renderer;
+pub mod sniff;
+pub mod storage;
 pub mod types;
+pub mod upload;
+pub mod webp;
 
 pub use converter::DocumentConverter;
 pub use types::*;
@@ -19,4 +31,1068 @@ mod tests {
         // Basic test to ensure converter can be created
         assert!(true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_render_page_draws_vector_content() {
+        use pdf_writer::{Content, Finish, Pdf, Rect, Ref};
+
+        let mut content = Content::new();
+        content.set_fill_rgb(1.0, 0.0, 0.0);
+        content.rect(100.0, 100.0, 200.0, 100.0);
+        content.fill_nonzero();
+
+        let mut pdf = Pdf::new();
+        pdf.catalog(Ref::new(1)).pages(Ref::new(2));
+        pdf.pages(Ref::new(2)).kids([Ref::new(3)]).count(1);
+        let mut page = pdf.page(Ref::new(3));
+        page.parent(Ref::new(2));
+        page.media_box(Rect::new(0.0, 0.0, 400.0, 300.0));
+        page.contents(Ref::new(4));
+        page.finish();
+        pdf.stream(Ref::new(4), &content.finish());
+
+        let doc = lopdf::Document::load_mem(&pdf.finish()).unwrap();
+        let page = pdf_renderer::PdfRenderer::with_dpi(144.0).render_page(&doc, 1).unwrap().to_rgb8();
+
+        // 2x scale, y axis flipped: the rectangle spans x 200..600, y 200..400
+        assert_eq!(page.dimensions(), (800, 600));
+        assert_eq!(page.get_pixel(400, 300).0, [255, 0, 0]);
+        assert_eq!(page.get_pixel(50, 50).0, [255, 255, 255]);
+        assert_eq!(page.get_pixel(400, 450).0, [255, 255, 255]);
+
+        // An image declaring billions of pixels is skipped rather than allocated
+        let mut content = Content::new();
+        content.save_state();
+        content.transform([200.0, 0.0, 0.0, 100.0, 100.0, 100.0]);
+        content.x_object(pdf_writer::Name(b"Im1"));
+        content.restore_state();
+        let mut pdf = Pdf::new();
+        pdf.catalog(Ref::new(1)).pages(Ref::new(2));
+        pdf.pages(Ref::new(2)).kids([Ref::new(3)]).count(1);
+        let mut page = pdf.page(Ref::new(3));
+        page.parent(Ref::new(2));
+        page.media_box(Rect::new(0.0, 0.0, 400.0, 300.0));
+        page.contents(Ref::new(4));
+        page.resources().x_objects().pair(pdf_writer::Name(b"Im1"), Ref::new(5));
+        page.finish();
+        pdf.stream(Ref::new(4), &content.finish());
+        let mut image = pdf.image_xobject(Ref::new(5), &[0; 12]);
+        image.width(100_000).height(100_000).bits_per_component(8);
+        image.color_space().device_rgb();
+        image.finish();
+
+        let mut doc = lopdf::Document::load_mem(&pdf.finish()).unwrap();
+        let page = pdf_renderer::PdfRenderer::with_dpi(72.0).render_page(&doc, 1).unwrap().to_rgb8();
+        assert_eq!(page.get_pixel(200, 150).0, [255, 255, 255]);
+
+        // An indexed colour space based on itself falls back to gray instead of recursing forever
+        let indexed = doc.add_object(lopdf::Object::Null);
+        doc.objects.insert(
+            indexed,
+            lopdf::Object::Array(vec![
+                lopdf::Object::Name(b"Indexed".to_vec()),
+                lopdf::Object::Reference(indexed),
+                lopdf::Object::Integer(0),
+                lopdf::Object::String(vec![0, 0, 0], lopdf::StringFormat::Hexadecimal),
+            ]),
+        );
+        let image = doc.get_object_mut((5, 0)).unwrap().as_stream_mut().unwrap();
+        image.dict.set("Width", 1);
+        image.dict.set("Height", 1);
+        image.dict.set("ColorSpace", lopdf::Object::Reference(indexed));
+        image.set_content(vec![0]);
+        pdf_renderer::PdfRenderer::with_dpi(72.0).render_page(&doc, 1).unwrap();
+
+        // A form drawing itself ten times per level stops at the page's XObject budget
+        let mut form_content = Content::new();
+        form_content.rect(0.0, 0.0, 1.0, 1.0);
+        form_content.fill_nonzero();
+        for _ in 0..10 {
+            form_content.x_object(pdf_writer::Name(b"F1"));
+        }
+        let mut pdf = Pdf::new();
+        pdf.catalog(Ref::new(1)).pages(Ref::new(2));
+        pdf.pages(Ref::new(2)).kids([Ref::new(3)]).count(1);
+        let mut page = pdf.page(Ref::new(3));
+        page.parent(Ref::new(2));
+        page.media_box(Rect::new(0.0, 0.0, 400.0, 300.0));
+        page.contents(Ref::new(4));
+        page.resources().x_objects().pair(pdf_writer::Name(b"F1"), Ref::new(5));
+        page.finish();
+        let mut content = Content::new();
+        content.x_object(pdf_writer::Name(b"F1"));
+        pdf.stream(Ref::new(4), &content.finish());
+        let form_content = form_content.finish();
+        let mut form = pdf.form_xobject(Ref::new(5), &form_content);
+        form.bbox(Rect::new(0.0, 0.0, 400.0, 300.0));
+        form.resources().x_objects().pair(pdf_writer::Name(b"F1"), Ref::new(5));
+        form.finish();
+
+        let doc = lopdf::Document::load_mem(&pdf.finish()).unwrap();
+        let page = pdf_renderer::PdfRenderer::with_dpi(72.0).render_page(&doc, 1).unwrap().to_rgb8();
+        assert_eq!(page.get_pixel(0, 299).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_page_selection_and_grid_layout() {
+        assert_eq!(PageSelection::Range { start: 2, end: 4 }.resolve(5).unwrap(), vec![2, 3, 4]);
+        assert_eq!(PageSelection::All.resolve(3).unwrap(), vec![1, 2, 3]);
+        assert!(PageSelection::Single(4).resolve(3).is_err());
+
+        let page = image::DynamicImage::new_rgb8(100, 140);
+        let processor = image_processor::ImageProcessor::new();
+        let stacked = processor.arrange_grid(&[page.clone(), page.clone()], 1);
+        assert_eq!((stacked.width(), stacked.height()), (100, 280));
+        let grid = processor.arrange_grid(&[page.clone(), page.clone(), page], 2);
+        assert_eq!((grid.width(), grid.height()), (200, 280));
+    }
+
+    #[tokio::test]
+    async fn test_merge_and_extract_pdf_pages() {
+        let processor = pdf_processor::PdfProcessor::new();
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgb8(60, 80)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .unwrap();
+        let pdf = processor.create_pdf_from_decoded_image(&image_processor::load_image(&png).unwrap(), None).await.unwrap();
+        let two_page_pdf = processor
+            .merge_documents(&[document("a.pdf", &pdf, "application/pdf"), document("b.pdf", &pdf, "application/pdf")], u64::MAX)
+            .await
+            .unwrap();
+
+        let parts = [
+            document("scan.png", &png, "image/png"),
+            document("marksheets.pdf", &two_page_pdf, "application/pdf"),
+        ];
+        let merged = processor.merge_documents(&parts, u64::MAX).await.unwrap();
+        assert_eq!(lopdf::Document::load_mem(&merged).unwrap().get_pages().len(), 3);
+        assert!(processor.merge_documents(&parts, 100).await.is_err());
+
+        let page_count = |pdf: &[u8]| lopdf::Document::load_mem(pdf).unwrap().get_pages().len();
+        let dropped = processor.extract_pages(&merged, &PageExtraction::Drop(vec![PageSelection::Single(2)])).await.unwrap();
+        assert_eq!(dropped.iter().map(|pdf| page_count(pdf)).collect::<Vec<_>>(), vec![2]);
+        let split = processor.extract_pages(&merged, &PageExtraction::Split(vec![])).await.unwrap();
+        assert_eq!(split.iter().map(|pdf| page_count(pdf)).collect::<Vec<_>>(), vec![1, 1, 1]);
+        assert!(PageExtraction::Keep(vec![PageSelection::Single(4)]).page_groups(3).is_err());
+
+        // Pages render at the requested resolution; resolutions out of range are refused
+        let at_dpi = |render_dpi: f32| ConversionOptions { render_dpi: Some(render_dpi), ..Default::default() };
+        let limits = SizeLimits { min_size: 0, max_size: u64::MAX, min_dimensions: None };
+        let low = processor.pdf_to_images(&pdf, image::ImageFormat::Png, limits, &at_dpi(72.0)).await.unwrap();
+        let high = processor.pdf_to_images(&pdf, image::ImageFormat::Png, limits, &at_dpi(144.0)).await.unwrap();
+        assert_eq!(high[0].width, low[0].width * 2);
+        assert!(high[0].height.abs_diff(low[0].height * 2) <= 1);
+        assert!(processor.pdf_to_images(&pdf, image::ImageFormat::Png, limits, &at_dpi(5000.0)).await.is_err());
+
+        // Wide and tall pages side by side would need a huge grid canvas, and large pages add up
+        // past the pixel budget; both are refused before any page is rendered
+        let mut sheets = pdf_writer::Pdf::new();
+        sheets.catalog(pdf_writer::Ref::new(1)).pages(pdf_writer::Ref::new(2));
+        let boxes = [(14_400.0, 200.0), (200.0, 14_400.0), (200.0, 14_400.0), (14_400.0, 200.0), (14_400.0, 14_400.0), (14_400.0, 14_400.0), (14_400.0, 14_400.0), (14_400.0, 14_400.0)];
+        sheets.pages(pdf_writer::Ref::new(2)).kids((3..3 + boxes.len() as i32).map(pdf_writer::Ref::new)).count(boxes.len() as i32);
+        for (i, (width, height)) in boxes.into_iter().enumerate() {
+            let mut page = sheets.page(pdf_writer::Ref::new(3 + i as i32));
+            page.parent(pdf_writer::Ref::new(2));
+            page.media_box(pdf_writer::Rect::new(0.0, 0.0, width, height));
+        }
+        let sheets = sheets.finish();
+        let grid = ConversionOptions {
+            pages: PageSelection::Range { start: 1, end: 4 },
+            page_layout: PageLayout::Grid { columns: 2 },
+            ..Default::default()
+        };
+        let large = ConversionOptions { pages: PageSelection::Range { start: 5, end: 8 }, ..Default::default() };
+        for options in [grid, large] {
+            match processor.pdf_to_images(&sheets, image::ImageFormat::Png, limits, &options).await {
+                Err(ConversionError::InvalidContent { message }) => assert!(message.contains("over the limit"), "{}", message),
+                other => panic!("expected the pixel budget to be enforced, got {:?}", other.map(|images| images.len())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_optimize_pdf_strategies_reach_budget() {
+        let processor = pdf_processor::PdfProcessor::new();
+        // A smooth, photo-like scan that Flate stores poorly
+        let scan = image::RgbImage::from_fn(900, 1200, |x, y| {
+            let v = ((x as f32 / 23.0).sin() * (y as f32 / 31.0).cos() * 100.0 + 128.0 + ((x ^ y) % 5) as f32) as u8;
+            image::Rgb([v, 255 - v, v / 2 + 60])
+        });
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(scan)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .unwrap();
+        let pdf = processor.create_pdf_from_decoded_image(&image_processor::load_image(&png).unwrap(), None).await.unwrap();
+
+        let budget = pdf.len() as u64 / 10;
+        let (optimized, strategy) = processor.optimize_pdf_to_size(&pdf, budget).await.unwrap();
+        assert!(optimized.len() as u64 <= budget);
+        assert_eq!(strategy, PdfStrategy::ImageDownsampling);
+        let doc = lopdf::Document::load_mem(&optimized).unwrap();
+        assert!(pdf_renderer::PdfRenderer::new().render_page(&doc, 1).is_ok());
+
+        // Repacking a merged document into object streams alone is enough to save a few bytes
+        let merged = processor
+            .merge_documents(&[document("a.pdf", &optimized, "application/pdf"), document("b.pdf", &optimized, "application/pdf")], u64::MAX)
+            .await
+            .unwrap();
+        let (packed, strategy) = processor.optimize_pdf_to_size(&merged, merged.len() as u64 - 1).await.unwrap();
+        assert_eq!(strategy, PdfStrategy::ObjectStreams);
+        let doc = lopdf::Document::load_mem(&packed).unwrap();
+        assert_eq!(doc.get_pages().len(), 2);
+        assert!(pdf_renderer::PdfRenderer::new().render_page(&doc, 2).is_ok());
+
+        // A PDF part over the budget on its own is optimized along with the merged document
+        let mut photo = Vec::new();
+        image::DynamicImage::new_rgb8(60, 80).write_to(&mut std::io::Cursor::new(&mut photo), image::ImageOutputFormat::Png).unwrap();
+        let parts = [document("scan.pdf", &pdf, "application/pdf"), document("photo.png", &photo, "image/png")];
+        let merged = processor.merge_documents(&parts, budget * 2).await.unwrap();
+        assert!(merged.len() as u64 <= budget * 2);
+        assert_eq!(lopdf::Document::load_mem(&merged).unwrap().get_pages().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_image_downsampling_follows_drawn_size() {
+        use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref};
+
+        let mut seed = 7u32;
+        let mut noise = |len: usize| -> Vec<u8> {
+            (0..len).map(|_| { seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345); (seed >> 16) as u8 }).collect()
+        };
+        // A 1200 px logo drawn one inch wide, and a colour-keyed image across the page
+        let mut content = Content::new();
+        content.save_state();
+        content.transform([612.0, 0.0, 0.0, 792.0, 0.0, 0.0]);
+        content.x_object(Name(b"Key"));
+        content.restore_state();
+        content.save_state();
+        content.transform([72.0, 0.0, 0.0, 72.0, 100.0, 100.0]);
+        content.x_object(Name(b"Logo"));
+        content.restore_state();
+        content.x_object(Name(b"Odd"));
+        let mut pdf = Pdf::new();
+        pdf.catalog(Ref::new(1)).pages(Ref::new(2));
+        pdf.pages(Ref::new(2)).kids([Ref::new(3)]).count(1);
+        let mut page = pdf.page(Ref::new(3));
+        page.parent(Ref::new(2));
+        page.media_box(Rect::new(0.0, 0.0, 612.0, 792.0));
+        page.contents(Ref::new(4));
+        page.resources().x_objects().pair(Name(b"Logo"), Ref::new(5)).pair(Name(b"Key"), Ref::new(6)).pair(Name(b"Odd"), Ref::new(7));
+        page.finish();
+        pdf.stream(Ref::new(4), &content.finish());
+        let logo_samples = noise(1200 * 1200 * 3);
+        let mut logo = pdf.image_xobject(Ref::new(5), &logo_samples);
+        logo.width(1200).height(1200).bits_per_component(8);
+        logo.color_space().device_rgb();
+        logo.finish();
+        let key_samples = noise(40 * 40 * 3);
+        let mut key = pdf.image_xobject(Ref::new(6), &key_samples);
+        key.width(40).height(40).bits_per_component(8);
+        key.color_space().device_rgb();
+        key.color_mask([0, 0, 0, 0, 0, 0]);
+        key.finish();
+        let mut odd = pdf.image_xobject(Ref::new(7), &[0]);
+        odd.width(1).height(1).bits_per_component(8);
+        odd.finish();
+
+        // The last image's colour space is an indexed space based on itself
+        let mut doc = lopdf::Document::load_mem(&pdf.finish()).unwrap();
+        let indexed = doc.add_object(lopdf::Object::Null);
+        doc.objects.insert(
+            indexed,
+            lopdf::Object::Array(vec![
+                lopdf::Object::Name(b"Indexed".to_vec()),
+                lopdf::Object::Reference(indexed),
+                lopdf::Object::Integer(0),
+                lopdf::Object::String(vec![0, 0, 0], lopdf::StringFormat::Hexadecimal),
+            ]),
+        );
+        doc.get_object_mut((7, 0)).unwrap().as_stream_mut().unwrap().dict.set("ColorSpace", lopdf::Object::Reference(indexed));
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).unwrap();
+
+        let processor = pdf_processor::PdfProcessor::new();
+        let (optimized, strategy) = processor.optimize_pdf_to_size(&pdf, pdf.len() as u64 / 20).await.unwrap();
+        assert_eq!(strategy, PdfStrategy::ImageDownsampling);
+        let doc = lopdf::Document::load_mem(&optimized).unwrap();
+        let images: Vec<&lopdf::Dictionary> = doc
+            .objects
+            .values()
+            .filter_map(|object| object.as_stream().ok())
+            .map(|stream| &stream.dict)
+            .filter(|dict| dict.get(b"Subtype").and_then(lopdf::Object::as_name).ok() == Some(b"Image"))
+            .collect();
+        let width = |dict: &lopdf::Dictionary| dict.get(b"Width").and_then(lopdf::Object::as_i64).unwrap();
+        let filter = |dict: &lopdf::Dictionary| dict.get(b"Filter").and_then(lopdf::Object::as_name).ok().map(<[u8]>::to_vec);
+        // One inch at the first step's 150 DPI
+        assert!(images.iter().any(|dict| width(dict) == 150 && filter(dict).as_deref() == Some(b"DCTDecode".as_slice())));
+        // Colour-keyed images keep their original samples
+        let key = images.iter().find(|dict| dict.has(b"Mask")).unwrap();
+        assert!(width(key) == 40 && filter(key).as_deref() != Some(b"DCTDecode".as_slice()));
+    }
+
+    #[tokio::test]
+    async fn test_jpeg_size_fitting_prefers_resolution_then_quality() {
+        let processor = image_processor::ImageProcessor::new();
+        let photo = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(600, 400, |x, y| {
+            image::Rgb([(x % 251) as u8, ((x * y) % 241) as u8, (y % 239) as u8])
+        }));
+
+        let within = |max_size: u64| SizeLimits { min_size: 0, max_size, min_dimensions: None };
+
+        let full = processor.encode_image(&photo, image::ImageFormat::Jpeg, within(u64::MAX), None).await.unwrap();
+        assert_eq!((full.quality, full.width, full.height), (Some(85), 600, 400));
+
+        let budget = full.data.len() as u64 / 2;
+        let fitted = processor.encode_image(&photo, image::ImageFormat::Jpeg, within(budget), None).await.unwrap();
+        assert!(fitted.data.len() as u64 <= budget);
+        assert!(fitted.quality.unwrap() >= 50);
+
+        let tiny = processor.encode_image(&photo, image::ImageFormat::Jpeg, within(4_000), None).await.unwrap();
+        assert!(tiny.data.len() <= 4_000 && tiny.width < 600);
+        assert!((tiny.width as f32 / tiny.height as f32 - 1.5).abs() < 0.05);
+    }
+
+    #[tokio::test]
+    async fn test_exact_dimensions_crop_and_pad() {
+        let processor = image_processor::ImageProcessor::new();
+        let landscape = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(400, 200, image::Rgb([10, 20, 200])));
+
+        let photo = DimensionSpec::pixels(200, 230);
+        let limits = SizeLimits { min_size: 0, max_size: 20_000, min_dimensions: None };
+        let encoded = processor.encode_image(&landscape, image::ImageFormat::Jpeg, limits, Some(&photo)).await.unwrap();
+        assert_eq!((encoded.width, encoded.height), (200, 230));
+        assert!(encoded.data.len() <= 20_000);
+
+        let padded = DimensionSpec { fit: FitPolicy::Pad, background: [255, 255, 0], ..DimensionSpec::centimeters(3.5, 4.5) };
+        assert_eq!(padded.pixel_size(), (413, 531));
+        let fitted = processor.fit_to_dimensions(&landscape, &padded).to_rgb8();
+        assert_eq!(fitted.dimensions(), (413, 531));
+        assert_eq!(fitted.get_pixel(200, 5).0, [255, 255, 0]);
+        assert_eq!(fitted.get_pixel(200, 265).0, [10, 20, 200]);
+
+        // Sizes a client could use to exhaust memory, or that have no aspect ratio, are refused
+        assert!(padded.validate().is_ok());
+        let huge = DimensionSpec::pixels(100_000, 100_000);
+        let unusable = [
+            huge.clone(),
+            DimensionSpec::pixels(0, 230),
+            DimensionSpec { height: -4.5, ..DimensionSpec::centimeters(3.5, 4.5) },
+            DimensionSpec { dpi: f32::NAN, ..DimensionSpec::centimeters(3.5, 4.5) },
+            DimensionSpec { width: f32::INFINITY, ..DimensionSpec::centimeters(3.5, 4.5) },
+        ];
+        for spec in unusable {
+            assert!(matches!(spec.validate(), Err(ConversionError::InvalidContent { .. })), "{:?}", spec);
+        }
+        let options = ConversionOptions { dimensions: Some(huge), ..Default::default() };
+        assert!(options.dimensions().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_minimum_size_and_dimensions() {
+        let processor = image_processor::ImageProcessor::new();
+        let small = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(60, 40, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 6) as u8, ((x + y) * 3) as u8])
+        }));
+
+        let limits = SizeLimits {
+            min_size: 10 * 1024,
+            max_size: 100 * 1024,
+            min_dimensions: Some(MinDimensions { width: 150, height: 100 }),
+        };
+        let encoded = processor.encode_image(&small, image::ImageFormat::Jpeg, limits, None).await.unwrap();
+        assert!(encoded.width >= 150 && encoded.height >= 100);
+        assert!((10 * 1024..=100 * 1024).contains(&(encoded.data.len() as u64)));
+
+        // A fixed pixel size below the minimum cannot be satisfied
+        let too_small = DimensionSpec::pixels(100, 80);
+        let result = processor.encode_image(&small, image::ImageFormat::Jpeg, limits, Some(&too_small)).await;
+        assert!(matches!(result, Err(ConversionError::MinimumNotMet { .. })));
+
+        // Minimums that would need a huge image are refused instead of allocated
+        let sliver = image::DynamicImage::new_rgb8(4, 1);
+        let tall = SizeLimits { min_size: 0, max_size: u64::MAX, min_dimensions: Some(MinDimensions { width: 1, height: 40_000 }) };
+        let result = processor.encode_image(&sliver, image::ImageFormat::Png, tall, None).await;
+        assert!(matches!(result, Err(ConversionError::MinimumNotMet { .. })));
+        let blank = image::DynamicImage::new_rgb8(4100, 2500);
+        let heavy = SizeLimits { min_size: u64::MAX / 2, max_size: u64::MAX, min_dimensions: None };
+        match processor.encode_image(&blank, image::ImageFormat::Png, heavy, None).await {
+            Err(ConversionError::MinimumNotMet { message }) => assert!(message.contains("pixel limit"), "{}", message),
+            other => panic!("expected the pixel limit to stop upscaling, got {:?}", other.map(|encoded| encoded.width)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_output_dpi_metadata() {
+        let processor = image_processor::ImageProcessor::new().with_output_dpi(Some(300));
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(32, 32, image::Rgb([90, 120, 150])));
+        let limits = SizeLimits { min_size: 0, max_size: 100_000, min_dimensions: None };
+
+        let jpeg = processor.encode_image(&img, image::ImageFormat::Jpeg, limits, None).await.unwrap().data;
+        assert_eq!(&jpeg[6..11], b"JFIF\0");
+        assert_eq!(jpeg[13], 1); // dots per inch
+        assert_eq!(&jpeg[14..18], &[1, 44, 1, 44]);
+
+        let png = processor.encode_image(&img, image::ImageFormat::Png, limits, None).await.unwrap().data;
+        assert_eq!(&png[37..41], b"pHYs");
+        assert_eq!(&png[41..45], &11811u32.to_be_bytes());
+        assert_eq!(image::load_from_memory(&png).unwrap().width(), 32);
+
+        let options = ConversionOptions { dimensions: Some(DimensionSpec::centimeters(3.5, 4.5)), ..Default::default() };
+        assert_eq!(options.output_dpi(), Some(300));
+    }
+
+    #[test]
+    fn test_exif_orientation_applied_on_load() {
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(40, 20, |x, _| {
+            if x < 20 { image::Rgb([255, 0, 0]) } else { image::Rgb([0, 0, 255]) }
+        }));
+        let mut jpeg = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut jpeg), image::ImageOutputFormat::Jpeg(90)).unwrap();
+
+        // Big-endian EXIF with a single IFD0 entry: Orientation = 6 (rotate 90° clockwise)
+        let mut app1 = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        app1.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0]);
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(&app1);
+        jpeg.splice(2..2, segment);
+
+        let upright = image_processor::load_image(&jpeg).unwrap().to_rgb8();
+        assert_eq!(upright.dimensions(), (20, 40));
+        // The left (red) half of the stored image ends up on top
+        assert!(upright.get_pixel(10, 5).0[0] > 200);
+        assert!(upright.get_pixel(10, 35).0[2] > 200);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_scrub_reports_removed_fields() {
+        use base64::Engine as _;
+
+        let mut jpeg = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(64, 48, image::Rgb([40, 160, 90])))
+            .write_to(&mut std::io::Cursor::new(&mut jpeg), image::ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let mut jpeg = metadata::embed_icc_profile(jpeg, b"not a real profile");
+        // Little-endian EXIF whose IFD0 holds only a GPS directory pointer
+        let exif = b"Exif\0\0II\x2a\0\x08\0\0\0\x01\0\x25\x88\x04\0\x01\0\0\0\x1a\0\0\0\0\0\0\0";
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        app1.extend_from_slice(exif);
+        jpeg.splice(2..2, app1);
+        assert_eq!(metadata::scan(&jpeg), vec![MetadataField::Exif, MetadataField::Gps, MetadataField::IccProfile]);
+
+        for keep_icc_profile in [false, true] {
+            let converter = DocumentConverter::new();
+            let request: ConvertRequest = serde_json::from_value(serde_json::json!({
+                "files": [{ "name": "photo.jpg", "content": base64::engine::general_purpose::STANDARD.encode(&jpeg), "mime_type": "image/jpeg" }],
+                "exam_type": "neet",
+                "target_formats": ["JPEG"],
+                "max_sizes": {},
+                "keep_icc_profile": keep_icc_profile,
+            }))
+            .unwrap();
+            let file = converter.convert_documents(&request).await.unwrap().remove(0);
+            let output = converter.get_stored_file(file.download_url.rsplit('/').next().unwrap()).unwrap();
+
+            assert!(file.metadata_removed.starts_with(&[MetadataField::Exif, MetadataField::Gps]));
+            assert_eq!(file.metadata_removed.contains(&MetadataField::IccProfile), !keep_icc_profile);
+            assert_eq!(metadata::icc_profile(&output).is_some(), keep_icc_profile);
+            assert!(metadata::exif_orientation(&output).is_none() && !metadata::scan(&output).contains(&MetadataField::Exif));
+        }
+
+        let processor = pdf_processor::PdfProcessor::new();
+        let pdf = processor.create_pdf_from_decoded_image(&image_processor::load_image(&jpeg).unwrap(), None).await.unwrap();
+        let mut doc = lopdf::Document::load_mem(&pdf).unwrap();
+        let mut info = lopdf::Dictionary::new();
+        info.set("Author", lopdf::Object::string_literal("Student Name"));
+        let info_id = doc.add_object(info);
+        doc.trailer.set("Info", info_id);
+        let mut with_info = Vec::new();
+        doc.save_to(&mut with_info).unwrap();
+
+        let (scrubbed, removed) = processor.scrub_metadata(&with_info).await.unwrap();
+        assert_eq!(removed, vec![MetadataField::DocumentInfo]);
+        assert!(!lopdf::Document::load_mem(&scrubbed).unwrap().trailer.has(b"Info"));
+    }
+
+    #[test]
+    fn test_scan_document_flattens_photographed_page() {
+        let corners = [(150.0, 70.0), (610.0, 120.0), (570.0, 500.0), (110.0, 470.0)];
+        let inside = |x: f32, y: f32| {
+            (0..4).all(|i| {
+                let ((ax, ay), (bx, by)) = (corners[i], corners[(i + 1) % 4]);
+                (bx - ax) * (y - ay) - (by - ay) * (x - ax) >= 0.0
+            })
+        };
+        // Light paper with ruled text lines on a dark, slightly noisy table
+        let photo = image::RgbImage::from_fn(720, 560, |x, y| {
+            if inside(x as f32, y as f32) {
+                let line = y % 24 < 3 && x % 50 < 40;
+                if line { image::Rgb([40, 40, 50]) } else { image::Rgb([235, 232, 225]) }
+            } else {
+                let noise = ((x * 7 + y * 13) % 17) as u8;
+                image::Rgb([60 + noise, 45 + noise, 35 + noise])
+            }
+        });
+        let processor = image_processor::ImageProcessor::new();
+
+        let page = processor.scan_document(&image::DynamicImage::ImageRgb8(photo)).to_rgb8();
+        let (width, height) = page.dimensions();
+        assert!((440..=480).contains(&width) && (370..=410).contains(&height), "{}x{}", width, height);
+        // No table left along the borders
+        let border: Vec<u32> = (0..width)
+            .flat_map(|x| [(x, 0), (x, height - 1)])
+            .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]))
+            .map(|(x, y)| page.get_pixel(x, y).0[0] as u32)
+            .collect();
+        let border_mean = border.iter().sum::<u32>() / border.len() as u32;
+        assert!(border_mean > 200, "border mean {}", border_mean);
+
+        // A page that already fills the frame is left alone
+        let flat = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(300, 400, |_, y| {
+            if y % 20 < 2 { image::Rgb([0, 0, 0]) } else { image::Rgb([250, 250, 250]) }
+        }));
+        assert_eq!(processor.scan_document(&flat).to_rgb8().dimensions(), (300, 400));
+    }
+
+    #[test]
+    fn test_enhance_document_whitens_and_deskews() {
+        // Ruled text tilted by 3° under uneven, noisy lighting
+        let (sin, cos) = 3f32.to_radians().sin_cos();
+        let photo = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(400, 500, |x, y| {
+            let (dx, dy) = (x as f32 - 200.0, y as f32 - 250.0);
+            let (u, v) = (dx * cos + dy * sin + 200.0, -dx * sin + dy * cos + 250.0);
+            let text = (40.0..360.0).contains(&u) && (40.0..460.0).contains(&v) && (v as u32) % 30 < 4 && (u as u32) % 37 < 29;
+            let noise = ((x * 31 + y * 17) ^ (x * y)) % 23;
+            let value = if text { 50.0 } else { 230.0 - x as f32 * 0.15 - y as f32 * 0.08 } + noise as f32 - 11.0;
+            image::Rgb([value as u8, value as u8, value as u8])
+        }));
+        let processor = image_processor::ImageProcessor::new();
+        let jpeg_size = |img: &image::DynamicImage| {
+            let mut data = Vec::new();
+            img.write_to(&mut std::io::Cursor::new(&mut data), image::ImageOutputFormat::Jpeg(85)).unwrap();
+            data.len()
+        };
+
+        let clean = processor.enhance_document(&photo, &Enhancement::default());
+        assert!(jpeg_size(&clean) * 10 < jpeg_size(&photo) * 7);
+
+        let binary = processor.enhance_document(&photo, &Enhancement { binarize: true, ..Enhancement::default() }).to_luma8();
+        assert!(binary.pixels().all(|pixel| pixel.0[0] == 0 || pixel.0[0] == 255));
+        // Straightened lines put most of a text line's ink into single rows
+        let densest_row = (0..binary.height())
+            .map(|y| (0..binary.width()).filter(|&x| binary.get_pixel(x, y).0[0] == 0).count())
+            .max()
+            .unwrap();
+        assert!(densest_row * 100 > binary.width() as usize * 45, "{}", densest_row);
+    }
+
+    #[tokio::test]
+    async fn test_signature_cleanup_isolates_ink() {
+        // Blue strokes on off-white, ruled paper
+        let photo = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(450, 250, |x, y| {
+            let (xf, yf) = (x as f32, y as f32);
+            let curve = 125.0 + 30.0 * ((xf - 100.0) / 22.0).sin();
+            if (100.0..350.0).contains(&xf) && (yf - curve).abs() < 2.0 {
+                image::Rgb([40, 50, 140])
+            } else if y % 30 < 2 {
+                image::Rgb([170, 185, 215])
+            } else {
+                let light = 225.0 - xf * 0.05;
+                image::Rgb([light as u8, (light * 0.98) as u8, (light * 0.9) as u8])
+            }
+        }));
+        let processor = image_processor::ImageProcessor::new();
+        let limits = SizeLimits { min_size: 0, max_size: 50_000, min_dimensions: None };
+
+        let jpeg = processor.encode_signature(&photo, image::ImageFormat::Jpeg, limits, None, &SignatureCleanup::default()).await.unwrap();
+        let cleaned = image::load_from_memory(&jpeg.data).unwrap().to_rgb8();
+        assert!(cleaned.width() < 320 && cleaned.height() < 120, "{}x{}", cleaned.width(), cleaned.height());
+        assert!(cleaned.get_pixel(1, 1).0.iter().all(|&value| value > 245));
+        assert!(cleaned.pixels().any(|pixel| pixel.0[2] < 100));
+
+        let transparent = SignatureCleanup { transparent: true, ..SignatureCleanup::default() };
+        let png = processor
+            .encode_signature(&photo, image::ImageFormat::Png, limits, Some(&DimensionSpec::pixels(140, 60)), &transparent)
+            .await
+            .unwrap();
+        let fitted = image::load_from_memory(&png.data).unwrap().to_rgba8();
+        assert_eq!(fitted.dimensions(), (140, 60));
+        assert_eq!(fitted.get_pixel(0, 0).0[3], 0);
+        assert!(fitted.pixels().any(|pixel| pixel.0[3] == 255));
+
+        let blank = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(200, 100, image::Rgb([230, 225, 210])));
+        let result = processor.encode_signature(&blank, image::ImageFormat::Png, limits, None, &SignatureCleanup::default()).await;
+        assert!(matches!(result, Err(ConversionError::InvalidContent { .. })));
+    }
+
+    #[test]
+    fn test_passport_photo_framing() {
+        // Cartoon portraits: skin-toned head with eyes and mouth, dark hair and a neck
+        let portrait = |width: u32, height: u32, faces: &[(f32, f32)], eyes: bool| {
+            let faces = faces.to_vec();
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, move |x, y| {
+                let (x, y) = (x as f32, y as f32);
+                for &(cx, cy) in &faces {
+                    let inside = |ex: f32, ey: f32, rx: f32, ry: f32| ((x - ex) / rx).powi(2) + ((y - ey) / ry).powi(2) <= 1.0;
+                    if eyes && (inside(cx - 35.0, cy - 30.0, 14.0, 7.0) || inside(cx + 35.0, cy - 30.0, 14.0, 7.0)) {
+                        return image::Rgb([40, 30, 30]);
+                    }
+                    if inside(cx, cy, 90.0, 120.0) {
+                        return image::Rgb([220, 170, 140]);
+                    }
+                    if inside(cx, cy - 30.0, 100.0, 125.0) && y < cy - 30.0 {
+                        return image::Rgb([50, 35, 25]);
+                    }
+                    if (x - cx).abs() < 40.0 && y > cy && y < cy + 170.0 {
+                        return image::Rgb([210, 160, 130]);
+                    }
+                }
+                image::Rgb([205, 215, 230])
+            }))
+        };
+        let processor = image_processor::ImageProcessor::new();
+
+        let single = portrait(600, 800, &[(280.0, 330.0)], true);
+        let faces = face_detect::detect_faces(&single.to_rgb8());
+        assert_eq!(faces.len(), 1);
+        let (cx, cy) = faces[0].center();
+        assert!((cx - 280.0).abs() < 10.0 && (cy - 330.0).abs() < 30.0, "{:?}", faces[0]);
+
+        let (framed, warning) = processor.crop_passport_photo(&single, 3.5 / 4.5, 0.55);
+        assert!(warning.is_none());
+        assert!((framed.width() as f32 / framed.height() as f32 - 3.5 / 4.5).abs() < 0.01);
+        assert!((faces[0].height / framed.height() as f64 - 0.55).abs() < 0.02);
+
+        // Face filling the photo: the frame shrinks to the whole image and must not overshoot it
+        for (width, height) in [(200, 250), (197, 251), (203, 249), (193, 247)] {
+            let tight = portrait(width, height, &[(width as f32 / 2.0, height as f32 / 2.0)], true);
+            assert_eq!(face_detect::detect_faces(&tight.to_rgb8()).len(), 1, "{}x{}", width, height);
+            let (framed, _) = processor.crop_passport_photo(&tight, 3.5 / 4.5, 0.9);
+            assert!(framed.width() <= width && framed.height() <= height);
+            assert!(framed.width() == width || framed.height() == height);
+        }
+
+        let pair = portrait(900, 600, &[(250.0, 250.0), (650.0, 260.0)], true);
+        let (_, warning) = processor.crop_passport_photo(&pair, 3.5 / 4.5, 0.55);
+        assert!(warning.unwrap().starts_with("2 faces"));
+
+        // A featureless skin-toned blob is not a face
+        assert!(face_detect::detect_faces(&portrait(600, 800, &[(280.0, 330.0)], false).to_rgb8()).is_empty());
+        let empty = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(300, 400, image::Rgb([205, 215, 230])));
+        let (unchanged, warning) = processor.crop_passport_photo(&empty, 3.5 / 4.5, 0.55);
+        assert_eq!((unchanged.width(), unchanged.height()), (300, 400));
+        assert!(warning.is_some());
+    }
+
+    #[test]
+    fn test_background_replacement() {
+        // Head and shoulders on a slightly graded blue backdrop
+        let photo = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(600, 800, |x, y| {
+            let (fx, fy) = (x as f32, y as f32);
+            let inside = |ex: f32, ey: f32, rx: f32, ry: f32| ((fx - ex) / rx).powi(2) + ((fy - ey) / ry).powi(2) <= 1.0;
+            if inside(265.0, 270.0, 14.0, 7.0) || inside(335.0, 270.0, 14.0, 7.0) {
+                image::Rgb([40, 30, 30])
+            } else if inside(300.0, 300.0, 90.0, 120.0) {
+                image::Rgb([220, 170, 140])
+            } else if inside(300.0, 270.0, 100.0, 125.0) && fy < 270.0 {
+                image::Rgb([50, 35, 25])
+            } else if fy > 560.0 && (fx - 300.0).abs() < 100.0 + (fy - 560.0) {
+                image::Rgb([30, 50, 90])
+            } else {
+                image::Rgb([120, 150, (200.0 - fy / 40.0) as u8])
+            }
+        }));
+        let processor = image_processor::ImageProcessor::new();
+        let replacement = types::BackgroundReplacement::default();
+
+        let (replaced, warning) = processor.replace_background(&photo, &replacement);
+        assert!(warning.is_none());
+        let replaced = replaced.to_rgb8();
+        for (x, y) in [(10, 10), (590, 10), (20, 500), (580, 700)] {
+            assert_eq!(replaced.get_pixel(x, y).0, [255, 255, 255], "backdrop at ({}, {})", x, y);
+        }
+        assert_eq!(replaced.get_pixel(300, 330).0, [220, 170, 140]);
+        assert_eq!(replaced.get_pixel(300, 170).0, [50, 35, 25]);
+        assert_eq!(replaced.get_pixel(300, 750).0, [30, 50, 90]);
+
+        // A busy scene has no backdrop to replace
+        let busy = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(400, 400, |x, y| {
+            image::Rgb([((x * 37) % 256) as u8, ((y * 53) % 256) as u8, (((x + y) * 11) % 256) as u8])
+        }));
+        let (unchanged, warning) = processor.replace_background(&busy, &replacement);
+        assert!(warning.is_some());
+        assert_eq!(unchanged.to_rgb8(), busy.to_rgb8());
+    }
+
+    #[tokio::test]
+    async fn test_webp_output_lossy_and_lossless() {
+        let photo = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(301, 203, |x, y| {
+            let (fx, fy) = (x as f32, y as f32);
+            image::Rgb([(128.0 + 100.0 * (fx / 17.0).sin() * (fy / 23.0).cos()) as u8, ((x + y) / 2) as u8, if (x / 20 + y / 20) % 2 == 0 { 220 } else { 40 }])
+        }));
+        let processor = image_processor::ImageProcessor::new();
+        let limits = SizeLimits { min_size: 0, max_size: u64::MAX, min_dimensions: None };
+
+        let lossy = processor.encode_image(&photo, image::ImageFormat::WebP, limits, None).await.unwrap();
+        assert_eq!(lossy.quality, Some(85));
+        let decoded = image::load_from_memory_with_format(&lossy.data, image::ImageFormat::WebP).unwrap().to_rgb8();
+        assert_eq!(decoded.dimensions(), (301, 203));
+        let error = decoded.pixels().zip(photo.to_rgb8().pixels())
+            .map(|(a, b)| a.0.iter().zip(b.0).map(|(&a, b)| (a as f64 - b as f64).abs()).sum::<f64>())
+            .sum::<f64>() / (301.0 * 203.0 * 3.0);
+        assert!(error < 6.0, "mean error {}", error);
+
+        let jpeg = processor.encode_image(&photo, image::ImageFormat::Jpeg, limits, None).await.unwrap();
+        assert!(lossy.data.len() < jpeg.data.len());
+
+        // The JPEG size search applies: quality first, then resolution
+        let budget = lossy.data.len() as u64 / 2;
+        let fitted = processor.encode_image(&photo, image::ImageFormat::WebP, SizeLimits { max_size: budget, ..limits }, None).await.unwrap();
+        assert!(fitted.data.len() as u64 <= budget && fitted.quality.unwrap() < 85);
+
+        let lossless = processor.with_lossless_webp(true).encode_image(&photo, image::ImageFormat::WebP, limits, None).await.unwrap();
+        assert_eq!(lossless.quality, None);
+        assert_eq!(image::load_from_memory(&lossless.data).unwrap().to_rgb8(), photo.to_rgb8());
+
+        // Transparent signatures keep their alpha channel in lossy WebP
+        let ink = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(40, 30, |x, _| image::Rgba([20, 20, 80, if x < 20 { 255 } else { 0 }])));
+        let encoded = webp::encode(&ink, Some(80)).unwrap();
+        let decoded = image::load_from_memory(&encoded).unwrap().to_rgba8();
+        assert!(decoded.pixels().zip(ink.to_rgba8().pixels()).all(|(a, b)| a.0[3] == b.0[3]));
+    }
+
+    #[tokio::test]
+    async fn test_tiff_bmp_and_gif_inputs() {
+        use base64::Engine as _;
+        use image::GenericImageView;
+        use tiff::encoder::{colortype, TiffEncoder};
+
+        // Two-page scan: a colour page and a greyscale page
+        let mut tiff = Vec::new();
+        let mut encoder = TiffEncoder::new(std::io::Cursor::new(&mut tiff)).unwrap();
+        encoder.write_image::<colortype::RGB8>(60, 80, &[200u8; 60 * 80 * 3]).unwrap();
+        encoder.write_image::<colortype::Gray8>(80, 60, &[90u8; 80 * 60]).unwrap();
+        let frames = image_processor::load_frames(&tiff).unwrap();
+        assert_eq!(frames.iter().map(|frame| frame.dimensions()).collect::<Vec<_>>(), vec![(60, 80), (80, 60)]);
+
+        let converter = DocumentConverter::new();
+        let request: ConvertRequest = serde_json::from_value(serde_json::json!({
+            "files": [{ "name": "scan.tiff", "content": base64::engine::general_purpose::STANDARD.encode(&tiff), "mime_type": "image/tiff" }],
+            "exam_type": "neet",
+            "target_formats": ["PDF", "JPEG"],
+            "max_sizes": {},
+        }))
+        .unwrap();
+        let files = converter.convert_documents(&request).await.unwrap();
+        let names: Vec<&str> = files.iter().map(|file| file.converted_name.as_str()).collect();
+        assert_eq!(names, ["scan.pdf", "scan_1.jpeg", "scan_2.jpeg"]);
+        let pdf = converter.get_stored_file(files[0].download_url.rsplit('/').next().unwrap()).unwrap();
+        assert_eq!(lopdf::Document::load_mem(&pdf).unwrap().get_pages().len(), 2);
+
+        // BMP and the first frame of a GIF go through the same paths
+        let picture = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(32, 24, image::Rgb([10, 120, 200])));
+        for (format, mime) in [(image::ImageOutputFormat::Bmp, "image/bmp"), (image::ImageOutputFormat::Gif, "image/gif")] {
+            let mut content = Vec::new();
+            picture.write_to(&mut std::io::Cursor::new(&mut content), format).unwrap();
+            let request: ConvertRequest = serde_json::from_value(serde_json::json!({
+                "files": [{ "name": "picture", "content": base64::engine::general_purpose::STANDARD.encode(&content), "mime_type": mime }],
+                "exam_type": "neet",
+                "target_formats": ["PDF", "PNG"],
+                "max_sizes": {},
+            }))
+            .unwrap();
+            let files = converter.convert_documents(&request).await.unwrap();
+            let output = |index: usize| converter.get_stored_file(files[index].download_url.rsplit('/').next().unwrap()).unwrap();
+            assert_eq!(lopdf::Document::load_mem(&output(0)).unwrap().get_pages().len(), 1);
+            assert_eq!(image::load_from_memory(&output(1)).unwrap().dimensions(), (32, 24));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_format_sniffing_overrides_declared_type() {
+        use base64::Engine as _;
+
+        let mut jpeg = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(40, 30, image::Rgb([200, 60, 60])))
+            .write_to(&mut std::io::Cursor::new(&mut jpeg), image::ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        assert_eq!(sniff::detect_mime_type(&jpeg), Some("image/jpeg"));
+        assert_eq!(sniff::detect_mime_type(b"\n%PDF-1.4\n"), Some("application/pdf"));
+        assert_eq!(sniff::detect_mime_type("Roll no. 42\r\nName: Zoë\n".as_bytes()), Some("text/plain"));
+        assert_eq!(sniff::detect_mime_type(&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1, 0]), Some("application/x-ole-storage"));
+        assert_eq!(sniff::detect_mime_type(&[0, 1, 2, 3]), None);
+
+        // A JPEG whose comment segment mentions a PDF header is still a JPEG
+        let comment = b"scanned from %PDF-1.7 original";
+        let mut commented = jpeg[..2].to_vec();
+        commented.extend_from_slice(&[0xFF, 0xFE, 0, comment.len() as u8 + 2]);
+        commented.extend_from_slice(comment);
+        commented.extend_from_slice(&jpeg[2..]);
+        assert_eq!(sniff::detect_mime_type(&commented), Some("image/jpeg"));
+        assert!(image_processor::load_image(&commented).is_ok());
+
+        // A JPEG renamed to .png, and one a browser sent as a generic download
+        let converter = DocumentConverter::new();
+        let content = base64::engine::general_purpose::STANDARD.encode(&jpeg);
+        let request: ConvertRequest = serde_json::from_value(serde_json::json!({
+            "files": [
+                { "name": "photo.png", "content": content, "mime_type": "image/png" },
+                { "name": "upload", "content": content, "mime_type": "application/octet-stream" },
+            ],
+            "exam_type": "neet",
+            "target_formats": ["PDF"],
+            "max_sizes": {},
+        }))
+        .unwrap();
+        let files = converter.convert_documents(&request).await.unwrap();
+        assert!(files.iter().all(|file| file.size > 0));
+        assert_eq!(files[0].warnings, ["photo.png was declared as image/png but its content is image/jpeg, which was used instead"]);
+        assert!(files[1].warnings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_multipart_convert_form() {
+        use actix_web::http::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(40, 30, image::Rgb([30, 90, 200])))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .unwrap();
+        let part = |disposition: &str, content_type: &str, body: &[u8]| {
+            let mut part = format!("--XYZ\r\nContent-Disposition: form-data; {}\r\nContent-Type: {}\r\n\r\n", disposition, content_type).into_bytes();
+            part.extend_from_slice(body);
+            part.extend_from_slice(b"\r\n");
+            part
+        };
+        let form = |parts: Vec<Vec<u8>>| {
+            let mut body = parts.concat();
+            body.extend_from_slice(b"--XYZ--\r\n");
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("multipart/form-data; boundary=XYZ"));
+            let stream = futures_util::stream::once(async move { Ok::<_, actix_web::error::PayloadError>(bytes::Bytes::from(body)) });
+            actix_multipart::Multipart::new(&headers, stream)
+        };
+        let settings = br#"{ "exam_type": "neet", "target_formats": ["PNG"], "max_sizes": {} }"#;
+        let parts = vec![
+            part("name=\"request\"", "application/json", settings),
+            part("name=\"options\"", "application/json", br#"{ "dimensions": { "width": 20, "height": 15 } }"#),
+            part("name=\"files\"; filename=\"small.png\"", "image/png", &png),
+            part("name=\"files\"; filename=\"full.png\"", "image/png", &png),
+        ];
+
+        let (request, uploads) = upload::read_convert_form(form(parts.clone()), upload::FormLimits::default()).await.unwrap();
+        assert_eq!(uploads.iter().map(|upload| upload.name.as_str()).collect::<Vec<_>>(), ["small.png", "full.png"]);
+        assert!(uploads[0].options.is_some() && uploads[1].options.is_none());
+
+        // The per-file options apply only to the file part that follows them
+        let files = DocumentConverter::new().convert_uploads(&request, uploads).await.unwrap();
+        let sizes: Vec<_> = files.iter().map(|file| (file.width, file.height)).collect();
+        assert_eq!(sizes, [(Some(20), Some(15)), (Some(40), Some(30))]);
+
+        // File parts are cut off at the limit while streaming
+        let limits = upload::FormLimits { max_file_size: png.len() - 1, ..Default::default() };
+        match upload::read_convert_form(form(parts.clone()), limits).await {
+            Err(ConversionError::SizeLimit { limit, .. }) => assert_eq!(limit, png.len() as u64 - 1),
+            other => panic!("expected a size limit error, got {:?}", other.map(|(_, uploads)| uploads.len())),
+        }
+        // So is the form as a whole, even when each part fits, and so are forms with too many parts
+        let limits = upload::FormLimits { max_form_size: png.len() * 3 / 2, ..Default::default() };
+        match upload::read_convert_form(form(parts.clone()), limits).await {
+            Err(ConversionError::FormTooLarge { message }) => assert_eq!(message, format!("more than {} bytes", png.len() * 3 / 2)),
+            other => panic!("expected a form size error, got {:?}", other.map(|(_, uploads)| uploads.len())),
+        }
+        let limits = upload::FormLimits { max_parts: parts.len() - 1, ..Default::default() };
+        match upload::read_convert_form(form(parts), limits).await {
+            Err(ConversionError::FormTooLarge { message }) => assert_eq!(message, format!("more than {} parts", limits.max_parts)),
+            other => panic!("expected a part count error, got {:?}", other.map(|(_, uploads)| uploads.len())),
+        }
+    }
+
+    #[test]
+    fn test_job_queue_progress_and_cancellation() {
+        use converter::ProgressListener;
+        use jobs::{JobQueue, JobState};
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let wait_for = |queue: &JobQueue, job_id: &str, state: JobState| {
+            for _ in 0..500 {
+                let status = queue.status(job_id).unwrap();
+                if status.state == state {
+                    return status;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            panic!("job {} never reached {:?}", job_id, state);
+        };
+        let request: ConvertRequest = serde_json::from_value(serde_json::json!({
+            "exam_type": "neet",
+            "target_formats": ["PDF", "JPEG"],
+            "max_sizes": {},
+        }))
+        .unwrap();
+        let upload = |name: &str, content: &[u8]| Upload {
+            name: name.to_string(),
+            content: content.to_vec(),
+            mime_type: "text/plain".to_string(),
+            options: None,
+        };
+
+        let converter = DocumentConverter::new();
+        let queue = JobQueue::new(converter.clone(), 1);
+        queue.start_workers(1);
+        let job = queue.submit(request.clone(), vec![upload("empty.txt", b""), upload("notes.txt", b"Roll no. 42")]).unwrap();
+        assert_eq!(job.state, JobState::Queued);
+        let done = wait_for(&queue, &job.job_id, JobState::Completed);
+        assert_eq!(done.progress, 1.0);
+        assert_eq!((done.files[0].state, done.files[0].error.as_deref()), (JobState::Failed, Some("File is empty")));
+        // Text converts to PDF; JPEG output of text is reported as a failed entry
+        let names: Vec<&str> = done.files[1].results.iter().map(|file| file.converted_name.as_str()).collect();
+        assert_eq!((done.files[1].state, names), (JobState::Completed, vec!["notes.pdf", "ERROR_notes.jpeg"]));
+        assert!(converter.get_stored_file(done.files[1].results[0].download_url.rsplit('/').next().unwrap()).is_some());
+
+        // Before any worker starts, jobs wait until the queue is full
+        let idle = JobQueue::new(converter.clone(), 2);
+        let waiting = idle.submit(request.clone(), vec![upload("b.txt", b"b")]).unwrap();
+        let next = idle.submit(request.clone(), vec![upload("c.txt", b"c")]).unwrap();
+        assert!(matches!(idle.submit(request.clone(), vec![upload("d.txt", b"d")]), Err(ConversionError::QueueFull)));
+        let cancelled = idle.cancel(&waiting.job_id).unwrap();
+        assert!(cancelled.state == JobState::Cancelled && cancelled.files[0].state == JobState::Cancelled);
+        let (_, mut updates) = idle.subscribe(&waiting.job_id).unwrap();
+        // A worker skips the cancelled job and takes the next one
+        idle.start_workers(1);
+        wait_for(&idle, &next.job_id, JobState::Completed);
+        let skipped = idle.status(&waiting.job_id).unwrap();
+        assert!(skipped.state == JobState::Cancelled && skipped.files[0].results.is_empty());
+        assert!(updates.try_recv().is_err(), "a job cancelled while queued must not run or finish twice");
+        assert!(idle.status("no-such-job").is_none());
+
+        // A running conversion stops before its next step once cancelled; one step at a time so
+        // the second has not started when the first finishes
+        struct CancelAfterFirstFormat(AtomicBool);
+        impl ProgressListener for CancelAfterFirstFormat {
+            fn on_event(&self, event: ProgressEvent) {
+                if matches!(event, ProgressEvent::FormatFinished { .. }) {
+                    self.0.store(true, Ordering::Relaxed);
+                }
+            }
+            fn is_cancelled(&self) -> bool {
+                self.0.load(Ordering::Relaxed)
+            }
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let listener = Arc::new(CancelAfterFirstFormat(AtomicBool::new(false)));
+        let sequential = converter.with_max_parallel_tasks(1);
+        let result = runtime.block_on(sequential.convert_uploads_with_progress(&request, vec![upload("a.txt", b"a")], listener));
+        assert!(matches!(result, Err(ConversionError::Cancelled)));
+    }
+
+    #[test]
+    fn test_job_events_report_conversion_stages() {
+        use jobs::{JobQueue, JobState, JobUpdate};
+
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(320, 240, |x, y| image::Rgb([(x % 256) as u8, (y % 256) as u8, 128])))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .unwrap();
+        let request: ConvertRequest = serde_json::from_value(serde_json::json!({
+            "exam_type": "neet",
+            "target_formats": ["JPEG"],
+            "max_sizes": {},
+        }))
+        .unwrap();
+        let upload = Upload { name: "photo.png".to_string(), content: png, mime_type: "image/png".to_string(), options: None };
+
+        // Start the worker once the subscription is in place
+        let queue = JobQueue::new(DocumentConverter::new(), 1);
+        let job = queue.submit(request, vec![upload]).unwrap();
+        let (status, mut updates) = queue.subscribe(&job.job_id).unwrap();
+        queue.start_workers(1);
+        assert!(!status.state.is_finished());
+
+        let mut events = Vec::new();
+        let finished = loop {
+            match updates.blocking_recv().unwrap() {
+                JobUpdate::Progress(event) => events.push(event),
+                JobUpdate::Finished(status) => break status,
+            }
+        };
+        assert_eq!(finished.state, JobState::Completed);
+
+        let stages: Vec<ConversionStage> = events
+            .iter()
+            .filter_map(|event| match event {
+                ProgressEvent::Stage { file: 0, format, stage } if format == "JPEG" => Some(*stage),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stages.first(), Some(&ConversionStage::Decoded));
+        assert_eq!(stages.get(1), Some(&ConversionStage::Compressing { iteration: 1 }));
+        assert_eq!(stages.last(), Some(&ConversionStage::Stored));
+        let iterations: Vec<u32> = stages
+            .iter()
+            .filter_map(|stage| match stage {
+                ConversionStage::Compressing { iteration } => Some(*iteration),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(iterations, (1..=iterations.len() as u32).collect::<Vec<_>>());
+        // Stages come before the format's results, which come before the file is done
+        let position = |wanted: fn(&ProgressEvent) -> bool| events.iter().position(wanted).unwrap();
+        let stored = position(|event| matches!(event, ProgressEvent::Stage { stage: ConversionStage::Stored, .. }));
+        let format_finished = position(|event| matches!(event, ProgressEvent::FormatFinished { .. }));
+        let file_finished = position(|event| matches!(event, ProgressEvent::FileFinished { .. }));
+        assert!(stored < format_finished && format_finished < file_finished);
+        assert_eq!(
+            serde_json::to_value(&events[stored]).unwrap(),
+            serde_json::json!({ "event": "stage", "file": 0, "format": "JPEG", "stage": "stored" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parallel_batch_keeps_result_order() {
+        use base64::Engine as _;
+
+        // Images of different sizes finish in a different order than they start
+        let files: Vec<serde_json::Value> = [(360, 240), (40, 30), (200, 160), (60, 80)]
+            .iter()
+            .enumerate()
+            .map(|(index, &(width, height))| {
+                let mut png = Vec::new();
+                image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| image::Rgb([(x * 7 % 256) as u8, (y * 3 % 256) as u8, index as u8])))
+                    .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+                    .unwrap();
+                serde_json::json!({ "name": format!("page{}.png", index), "content": base64::engine::general_purpose::STANDARD.encode(&png), "mime_type": "image/png" })
+            })
+            .collect();
+        let request: ConvertRequest = serde_json::from_value(serde_json::json!({
+            "files": files,
+            "exam_type": "neet",
+            "target_formats": ["JPEG", "PDF", "WEBP"],
+            "max_sizes": { "JPEG": 20000 },
+        }))
+        .unwrap();
+
+        let names = |files: Vec<ConvertedFile>| files.into_iter().map(|file| file.converted_name).collect::<Vec<_>>();
+        let converter = DocumentConverter::new();
+        let sequential = names(converter.with_max_parallel_tasks(1).convert_documents(&request).await.unwrap());
+        let expected: Vec<String> = (0..4)
+            .flat_map(|index| ["jpeg", "pdf", "webp"].map(|extension| format!("page{}.{}", index, extension)))
+            .collect();
+        assert_eq!(sequential, expected);
+        for limit in [2, 12] {
+            assert_eq!(names(converter.with_max_parallel_tasks(limit).convert_documents(&request).await.unwrap()), expected);
+        }
+    }
+
+    fn document(name: &str, content: &[u8], mime_type: &str) -> DocumentInfo {
+        DocumentInfo {
+            name: name.to_string(),
+            content: content.to_vec(),
+            mime_type: mime_type.to_string(),
+            size: content.len() as u64,
+        }
+    }
+}
@@ -1,17 +1,39 @@
-use actix_web::{web, App, HttpServer, Result, HttpResponse, middleware::Logger};
+use actix_web::{guard, http::header, web, App, HttpRequest, HttpServer, Result, HttpResponse, middleware::Logger};
 use actix_cors::Cors;
-use std::sync::Mutex;
+use actix_multipart::Multipart;
+use futures_util::{stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
 
+mod background;
 mod converter;
+mod document_scan;
+mod face_detect;
 mod types;
 mod image_processor;
+mod jobs;
+mod metadata;
 mod pdf_processor;
+mod pdf_renderer;
+mod sniff;
+mod storage;
+mod upload;
+mod webp;
 
 use converter::DocumentConverter;
+use jobs::{JobQueue, JobUpdate};
 use types::*;
 
-// Global converter instance with thread-safe access
-type ConverterState = web::Data<Mutex<DocumentConverter>>;
+// Converter shared by every request handler and job worker
+type ConverterState = web::Data<DocumentConverter>;
+
+/// Jobs that may wait for a conversion worker before new ones are turned away
+const JOB_QUEUE_CAPACITY: usize = 64;
+
+/// Overrides how many files and formats of one batch are converted at once
+const PARALLEL_TASKS_VAR: &str = "CONVERTER_PARALLEL_TASKS";
+
+/// Name of the job status route, used to build the `Location` of newly queued jobs
+const JOB_ROUTE: &str = "job";
 
 async fn health() -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().json(serde_json::json!({
@@ -19,7 +41,7 @@ async fn health() -> Result<HttpResponse> {
         "service": "rust-converter",
         "version": "1.0.0",
         "capabilities": {
-            "image_formats": ["JPEG", "PNG", "WebP"],
+            "image_formats": ["JPEG", "PNG", "WebP", "TIFF", "BMP", "GIF"],
             "document_formats": ["PDF", "DOCX", "TXT"],
             "operations": ["compression", "format_conversion", "optimization"]
         },
@@ -29,27 +51,58 @@ async fn health() -> Result<HttpResponse> {
 
 async fn convert_documents(
     req: web::Json<ConvertRequest>,
-    converter_state: ConverterState,
+    converter: ConverterState,
 ) -> Result<HttpResponse> {
     log::info!("🚀 Conversion request received:");
     log::info!("  - Files: {}", req.files.len());
     log::info!("  - Exam type: {}", req.exam_type);
     log::info!("  - Target formats: {:?}", req.target_formats);
     log::info!("  - Size limits: {:?}", req.max_sizes);
-    
-    let mut converter = match converter_state.lock() {
-        Ok(conv) => conv,
-        Err(e) => {
-            log::error!("Failed to acquire converter lock: {}", e);
-            return Ok(HttpResponse::InternalServerError().json(ConvertResponse {
-                success: false,
-                files: vec![],
-                error: Some("Service temporarily unavailable".to_string()),
-            }));
-        }
+
+    Ok(conversion_response(converter.convert_documents(&req).await))
+}
+
+/// Multipart variant of `/convert`: the same settings as JSON in a `request` part, with the
+/// files streamed as raw parts instead of base64
+async fn convert_multipart(
+    form: Multipart,
+    converter: ConverterState,
+) -> Result<HttpResponse> {
+    log::info!("🚀 Multipart conversion request received");
+    let (request, uploads) = match upload::read_convert_form(form, upload::FormLimits::default()).await {
+        Ok(form) => form,
+        Err(e) => return Ok(form_rejection(e)),
     };
-    
-    match converter.convert_documents(&req).await {
+    log::info!("  - Files: {}", uploads.len());
+    log::info!("  - Exam type: {}", request.exam_type);
+    log::info!("  - Target formats: {:?}", request.target_formats);
+    log::info!("  - Size limits: {:?}", request.max_sizes);
+
+    Ok(conversion_response(converter.convert_uploads(&request, uploads).await))
+}
+
+/// Response for a multipart form that could not be read
+fn form_rejection(e: ConversionError) -> HttpResponse {
+    log::error!("❌ Rejected multipart upload: {}", e);
+    let mut response = match e {
+        ConversionError::SizeLimit { .. } | ConversionError::FormTooLarge { .. } => HttpResponse::PayloadTooLarge(),
+        _ => HttpResponse::BadRequest(),
+    };
+    response.json(ConvertResponse {
+        success: false,
+        files: vec![],
+        error: Some(e.to_string()),
+    })
+}
+
+fn is_multipart(ctx: &guard::GuardContext) -> bool {
+    ctx.header::<header::ContentType>()
+        .is_some_and(|content_type| content_type.0.essence_str() == "multipart/form-data")
+}
+
+/// `ConvertResponse` for the outcome of a conversion
+fn conversion_response(result: std::result::Result<Vec<ConvertedFile>, ConversionError>) -> HttpResponse {
+    match result {
         Ok(converted_files) => {
             let successful_conversions = converted_files.iter()
                 .filter(|f| !f.download_url.is_empty())
@@ -58,32 +111,151 @@ async fn convert_documents(
             log::info!("✅ Conversion completed: {}/{} files successful", 
                 successful_conversions, converted_files.len());
             
-            Ok(HttpResponse::Ok().json(ConvertResponse {
+            HttpResponse::Ok().json(ConvertResponse {
                 success: true,
                 files: converted_files,
                 error: None,
-            }))
+            })
         }
         Err(e) => {
             log::error!("❌ Conversion failed: {}", e);
-            Ok(HttpResponse::InternalServerError().json(ConvertResponse {
+            HttpResponse::InternalServerError().json(ConvertResponse {
                 success: false,
                 files: vec![],
                 error: Some(e.to_string()),
-            }))
+            })
+        }
+    }
+}
+
+/// Queue a JSON conversion request and return its job ID straight away
+async fn submit_job(
+    http_req: HttpRequest,
+    req: web::Json<ConvertRequest>,
+    job_queue: web::Data<JobQueue>,
+) -> Result<HttpResponse> {
+    let request = req.into_inner();
+    let uploads = match DocumentConverter::decode_uploads(&request) {
+        Ok(uploads) => uploads,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e.to_string() })));
+        }
+    };
+    Ok(queue_job(&http_req, &job_queue, request, uploads))
+}
+
+/// Multipart variant of `POST /jobs`, read like the multipart `/convert`
+async fn submit_multipart_job(
+    http_req: HttpRequest,
+    form: Multipart,
+    job_queue: web::Data<JobQueue>,
+) -> Result<HttpResponse> {
+    match upload::read_convert_form(form, upload::FormLimits::default()).await {
+        Ok((request, uploads)) => Ok(queue_job(&http_req, &job_queue, request, uploads)),
+        Err(e) => Ok(form_rejection(e)),
+    }
+}
+
+fn queue_job(http_req: &HttpRequest, job_queue: &JobQueue, request: ConvertRequest, uploads: Vec<Upload>) -> HttpResponse {
+    match job_queue.submit(request, uploads) {
+        Ok(status) => {
+            log::info!("📨 Job {} queued with {} files", status.job_id, status.files.len());
+            let mut response = HttpResponse::Accepted();
+            // Point at the status route as registered, so the header follows any change to it
+            match http_req.url_for(JOB_ROUTE, [&status.job_id]) {
+                Ok(url) => {
+                    response.append_header((header::LOCATION, url.path()));
+                }
+                Err(e) => log::error!("Cannot build the location of job {}: {:?}", status.job_id, e),
+            }
+            response.json(status)
+        }
+        Err(e) => {
+            log::warn!("❌ Job rejected: {}", e);
+            let mut response = match e {
+                ConversionError::QueueFull => HttpResponse::ServiceUnavailable(),
+                _ => HttpResponse::InternalServerError(),
+            };
+            response.json(serde_json::json!({ "error": e.to_string() }))
         }
     }
 }
 
+async fn get_job(path: web::Path<String>, job_queue: web::Data<JobQueue>) -> Result<HttpResponse> {
+    let job_id = path.into_inner();
+    match job_queue.status(&job_id) {
+        Some(status) => Ok(HttpResponse::Ok().json(status)),
+        None => Ok(job_not_found(&job_id)),
+    }
+}
+
+/// Server-sent events for a job: a `status` snapshot, then one `progress` event per conversion
+/// event and a final `finished` status, after which the stream ends
+async fn job_events(path: web::Path<String>, job_queue: web::Data<JobQueue>) -> Result<HttpResponse> {
+    let job_id = path.into_inner();
+    let Some((status, updates)) = job_queue.subscribe(&job_id) else {
+        return Ok(job_not_found(&job_id));
+    };
+    log::info!("📡 Streaming events of job {}", job_id);
+
+    let first = if status.state.is_finished() {
+        sse_frame("finished", &status)
+    } else {
+        sse_frame("status", &status)
+    };
+    let updates = (!status.state.is_finished()).then_some((updates, job_queue, job_id));
+    let rest = stream::unfold(updates, |state| async move {
+        let (mut updates, job_queue, job_id) = state?;
+        let frame = match updates.recv().await {
+            Ok(JobUpdate::Progress(event)) => sse_frame("progress", &event),
+            Ok(JobUpdate::Finished(status)) => return Some((sse_frame("finished", &status), None)),
+            Err(RecvError::Lagged(missed)) => {
+                // Too slow to see every event; catch up from the current status instead
+                log::warn!("⚠️ Event subscriber of job {} missed {} updates", job_id, missed);
+                let status = job_queue.status(&job_id)?;
+                sse_frame("status", &status)
+            }
+            Err(RecvError::Closed) => return None,
+        };
+        Some((frame, Some((updates, job_queue, job_id))))
+    });
+    let events = stream::once(async { first }).chain(rest).map(Ok::<_, actix_web::Error>);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .append_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events))
+}
+
+fn sse_frame(event: &str, data: &impl serde::Serialize) -> web::Bytes {
+    let data = serde_json::to_string(data).unwrap_or_default();
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+/// Cancel a job; a running job stops before its next conversion step
+async fn cancel_job(path: web::Path<String>, job_queue: web::Data<JobQueue>) -> Result<HttpResponse> {
+    let job_id = path.into_inner();
+    match job_queue.cancel(&job_id) {
+        Some(status) => Ok(HttpResponse::Ok().json(status)),
+        None => Ok(job_not_found(&job_id)),
+    }
+}
+
+fn job_not_found(job_id: &str) -> HttpResponse {
+    log::warn!("❌ Job not found: {}", job_id);
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": "Job not found",
+        "job_id": job_id
+    }))
+}
+
 async fn download_file(
     path: web::Path<String>,
-    converter_state: ConverterState,
+    converter: ConverterState,
 ) -> Result<HttpResponse> {
     let file_id = path.into_inner();
     log::info!("📥 Download requested for file ID: {}", file_id);
     
-    let converter = converter_state.lock().unwrap();
-    
     match converter.get_stored_file(&file_id) {
         Some(file_content) => {
             log::info!("✅ File found, serving {} bytes", file_content.len());
@@ -91,7 +263,7 @@ async fn download_file(
                 .content_type("application/octet-stream")
                 .append_header(("Content-Disposition", "attachment"))
                 .append_header(("Cache-Control", "no-cache"))
-                .body(file_content.clone()))
+                .body(file_content.to_vec()))
         }
         None => {
             log::warn!("❌ File not found: {}", file_id);
@@ -117,17 +289,54 @@ async fn get_exam_config(path: web::Path<String>) -> Result<HttpResponse> {
                 map.insert("JPEG".to_string(), 500 * 1024); // 500KB
                 map
             },
+            min_sizes: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("JPEG".to_string(), 10 * 1024); // 10KB
+                map
+            },
+            dimensions: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), DimensionSpec::centimeters(3.5, 4.5));
+                map.insert("signature".to_string(), DimensionSpec::centimeters(3.5, 1.5));
+                map
+            },
+            min_dimensions: std::collections::HashMap::new(),
+            dpi: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), 300);
+                map.insert("signature".to_string(), 200);
+                map
+            },
         },
         "jee" => ExamConfig {
             name: "JEE".to_string(),
             formats: vec!["PDF".to_string(), "JPEG".to_string(), "PNG".to_string()],
             max_sizes: {
                 let mut map = std::collections::HashMap::new();
-                map.insert("PDF".to_string(), 1 * 1024 * 1024); // 1MB
+                map.insert("PDF".to_string(), 1024 * 1024); // 1MB
                 map.insert("JPEG".to_string(), 300 * 1024); // 300KB
                 map.insert("PNG".to_string(), 300 * 1024); // 300KB
                 map
             },
+            min_sizes: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("JPEG".to_string(), 10 * 1024); // 10KB
+                map.insert("PNG".to_string(), 10 * 1024); // 10KB
+                map
+            },
+            dimensions: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), DimensionSpec::centimeters(3.5, 4.5));
+                map.insert("signature".to_string(), DimensionSpec::centimeters(3.5, 1.5));
+                map
+            },
+            min_dimensions: std::collections::HashMap::new(),
+            dpi: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), 300);
+                map.insert("signature".to_string(), 200);
+                map
+            },
         },
         "upsc" => ExamConfig {
             name: "UPSC".to_string(),
@@ -135,8 +344,31 @@ async fn get_exam_config(path: web::Path<String>) -> Result<HttpResponse> {
             max_sizes: {
                 let mut map = std::collections::HashMap::new();
                 map.insert("PDF".to_string(), 3 * 1024 * 1024); // 3MB
-                map.insert("JPEG".to_string(), 1 * 1024 * 1024); // 1MB
-                map.insert("PNG".to_string(), 1 * 1024 * 1024); // 1MB
+                map.insert("JPEG".to_string(), 1024 * 1024); // 1MB
+                map.insert("PNG".to_string(), 1024 * 1024); // 1MB
+                map
+            },
+            min_sizes: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("JPEG".to_string(), 10 * 1024); // 10KB
+                map.insert("PNG".to_string(), 10 * 1024); // 10KB
+                map
+            },
+            dimensions: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), DimensionSpec::pixels(350, 350));
+                map.insert("signature".to_string(), DimensionSpec::pixels(350, 350));
+                map
+            },
+            min_dimensions: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), MinDimensions { width: 350, height: 350 });
+                map
+            },
+            dpi: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), 200);
+                map.insert("signature".to_string(), 200);
                 map
             },
         },
@@ -149,6 +381,24 @@ async fn get_exam_config(path: web::Path<String>) -> Result<HttpResponse> {
                 map.insert("JPEG".to_string(), 400 * 1024); // 400KB
                 map
             },
+            min_sizes: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("JPEG".to_string(), 10 * 1024); // 10KB
+                map
+            },
+            dimensions: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), DimensionSpec::pixels(200, 230));
+                map.insert("signature".to_string(), DimensionSpec::pixels(140, 60));
+                map
+            },
+            min_dimensions: std::collections::HashMap::new(),
+            dpi: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), 200);
+                map.insert("signature".to_string(), 200);
+                map
+            },
         },
         "gate" => ExamConfig {
             name: "GATE".to_string(),
@@ -160,6 +410,29 @@ async fn get_exam_config(path: web::Path<String>) -> Result<HttpResponse> {
                 map.insert("PNG".to_string(), 500 * 1024); // 500KB
                 map
             },
+            min_sizes: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("JPEG".to_string(), 10 * 1024); // 10KB
+                map.insert("PNG".to_string(), 10 * 1024); // 10KB
+                map
+            },
+            dimensions: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), DimensionSpec::pixels(480, 640));
+                map.insert("signature".to_string(), DimensionSpec::pixels(320, 160));
+                map
+            },
+            min_dimensions: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), MinDimensions { width: 240, height: 320 });
+                map
+            },
+            dpi: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("photo".to_string(), 200);
+                map.insert("signature".to_string(), 200);
+                map
+            },
         },
         _ => {
             log::warn!("Unknown exam type requested: {}", exam_type);
@@ -175,8 +448,7 @@ async fn get_exam_config(path: web::Path<String>) -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().json(config))
 }
 
-async fn get_conversion_stats(converter_state: ConverterState) -> Result<HttpResponse> {
-    let converter = converter_state.lock().unwrap();
+async fn get_conversion_stats(converter: ConverterState) -> Result<HttpResponse> {
     let (file_count, total_size) = converter.get_storage_stats();
     
     Ok(HttpResponse::Ok().json(serde_json::json!({
@@ -184,8 +456,8 @@ async fn get_conversion_stats(converter_state: ConverterState) -> Result<HttpRes
         "temp_storage_size": total_size,
         "service_status": "running",
         "supported_formats": {
-            "input": ["PDF", "JPEG", "JPG", "PNG", "WEBP", "DOCX", "DOC", "TXT"],
-            "output": ["PDF", "JPEG", "PNG", "DOCX"]
+            "input": ["PDF", "JPEG", "JPG", "PNG", "WEBP", "TIFF", "BMP", "GIF", "DOCX", "DOC", "TXT"],
+            "output": ["PDF", "JPEG", "PNG", "WEBP", "DOCX"]
         },
         "max_file_size": "10MB",
         "compression_capabilities": {
@@ -196,10 +468,8 @@ async fn get_conversion_stats(converter_state: ConverterState) -> Result<HttpRes
     })))
 }
 
-async fn cleanup_temp_files(converter_state: ConverterState) -> Result<HttpResponse> {
-    let mut converter = converter_state.lock().unwrap();
-    let (count, size) = converter.get_storage_stats();
-    converter.cleanup_temp_files();
+async fn cleanup_temp_files(converter: ConverterState) -> Result<HttpResponse> {
+    let (count, size) = converter.cleanup_temp_files();
     
     log::info!("🧹 Cleaned up {} files ({} bytes)", count, size);
     
@@ -216,11 +486,21 @@ async fn main() -> std::io::Result<()> {
     log::info!("🦀 Starting Rust Document Converter Service");
     log::info!("📍 Port: 8002");
     log::info!("🔧 Features: Image compression, PDF optimization, Format conversion");
-    log::info!("📊 Supported input formats: PDF, JPEG, PNG, WEBP, DOCX, DOC, TXT");
-    log::info!("📤 Supported output formats: PDF, JPEG, PNG, DOCX");
+    log::info!("📊 Supported input formats: PDF, JPEG, PNG, WEBP, TIFF, BMP, GIF, DOCX, DOC, TXT");
+    log::info!("📤 Supported output formats: PDF, JPEG, PNG, WEBP, DOCX");
     
-    // Initialize converter state
-    let converter_state = web::Data::new(Mutex::new(DocumentConverter::new()));
+    // Initialize converter state, shared by the request handlers and the job workers
+    let workers = std::thread::available_parallelism().map_or(2, usize::from);
+    let parallel_tasks = std::env::var(PARALLEL_TASKS_VAR)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(workers);
+    let converter = DocumentConverter::new().with_max_parallel_tasks(parallel_tasks);
+    log::info!("⚡ Parallel conversions per batch: {}", parallel_tasks);
+    let job_queue = web::Data::new(JobQueue::new(converter.clone(), JOB_QUEUE_CAPACITY));
+    job_queue.start_workers(workers);
+    let converter_state = web::Data::new(converter);
+    log::info!("🧵 Job workers: {}", workers);
     
     HttpServer::new(move || {
         let cors = Cors::default()
@@ -231,10 +511,21 @@ async fn main() -> std::io::Result<()> {
             
         App::new()
             .app_data(converter_state.clone())
+            .app_data(job_queue.clone())
             .wrap(Logger::default())
             .wrap(cors)
             .route("/health", web::get().to(health))
+            .route("/convert", web::post().guard(guard::fn_guard(is_multipart)).to(convert_multipart))
             .route("/convert", web::post().to(convert_documents))
+            .route("/jobs", web::post().guard(guard::fn_guard(is_multipart)).to(submit_multipart_job))
+            .route("/jobs", web::post().to(submit_job))
+            .service(
+                web::resource("/jobs/{job_id}")
+                    .name(JOB_ROUTE)
+                    .route(web::get().to(get_job))
+                    .route(web::delete().to(cancel_job)),
+            )
+            .route("/jobs/{job_id}/events", web::get().to(job_events))
             .route("/download/{file_id}", web::get().to(download_file))
             .route("/exam-config/{exam_type}", web::get().to(get_exam_config))
             .route("/stats", web::get().to(get_conversion_stats))
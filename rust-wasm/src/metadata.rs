@@ -0,0 +1,320 @@
+use crate::types::MetadataField;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use std::io::{Read, Write};
+use std::ops::Range;
+
+/// Largest ICC payload that fits in one JPEG APP2 segment
+const ICC_JPEG_CHUNK: usize = 65_519;
+
+/// Image containers whose metadata segments can be located and removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Jpeg,
+    Png,
+    WebP,
+}
+
+impl Container {
+    fn detect(content: &[u8]) -> Option<Self> {
+        if content.starts_with(&[0xFF, 0xD8]) {
+            Some(Container::Jpeg)
+        } else if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Container::Png)
+        } else if content.len() >= 12 && &content[..4] == b"RIFF" && &content[8..12] == b"WEBP" {
+            Some(Container::WebP)
+        } else {
+            None
+        }
+    }
+}
+
+/// A JPEG marker segment, PNG chunk or RIFF chunk
+struct Segment<'a> {
+    /// Marker byte for JPEG, chunk type for PNG and WebP
+    kind: [u8; 4],
+    /// Bytes of the whole segment, headers and trailers included
+    range: Range<usize>,
+    data: &'a [u8],
+}
+
+/// Walk the segments of an image up to its pixel data
+fn segments(content: &[u8], container: Container) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    match container {
+        Container::Jpeg => {
+            let mut pos = 2;
+            while pos + 4 <= content.len() && content[pos] == 0xFF {
+                let marker = content[pos + 1];
+                if marker == 0xFF {
+                    pos += 1;
+                    continue;
+                }
+                // Pixel data follows start of scan
+                if marker == 0xDA || marker == 0xD9 {
+                    break;
+                }
+                let length = u16::from_be_bytes([content[pos + 2], content[pos + 3]]) as usize;
+                let Some(data) = content.get(pos + 4..pos + 2 + length) else { break };
+                segments.push(Segment { kind: [marker, 0, 0, 0], range: pos..pos + 2 + length, data });
+                pos += 2 + length;
+            }
+        }
+        Container::Png => {
+            let mut pos = 8;
+            while pos + 12 <= content.len() {
+                let length = u32::from_be_bytes(content[pos..pos + 4].try_into().unwrap()) as usize;
+                let Some(data) = content.get(pos + 8..pos + 8 + length) else { break };
+                let kind: [u8; 4] = content[pos + 4..pos + 8].try_into().unwrap();
+                segments.push(Segment { kind, range: pos..pos + 12 + length, data });
+                pos += 12 + length;
+            }
+        }
+        Container::WebP => {
+            let mut pos = 12;
+            while pos + 8 <= content.len() {
+                let length = u32::from_le_bytes(content[pos + 4..pos + 8].try_into().unwrap()) as usize;
+                let Some(data) = content.get(pos + 8..pos + 8 + length) else { break };
+                let kind: [u8; 4] = content[pos..pos + 4].try_into().unwrap();
+                // Chunks are padded to an even size
+                let end = (pos + 8 + length + (length & 1)).min(content.len());
+                segments.push(Segment { kind, range: pos..end, data });
+                pos = end;
+            }
+        }
+    }
+    segments
+}
+
+/// Privacy-relevant metadata carried by one segment, if any
+fn classify(segment: &Segment, container: Container) -> Vec<MetadataField> {
+    let data = segment.data;
+    let field = match (container, &segment.kind) {
+        (Container::Jpeg, [0xE1, ..]) if data.starts_with(b"Exif\0\0") => return exif_fields(&data[6..]),
+        (Container::Jpeg, [0xE1, ..]) if data.starts_with(b"http://ns.adobe.com/") => MetadataField::Xmp,
+        (Container::Jpeg, [0xE2, ..]) if data.starts_with(b"ICC_PROFILE\0") => MetadataField::IccProfile,
+        (Container::Jpeg, [0xED, ..]) => MetadataField::Iptc,
+        (Container::Jpeg, [0xFE, ..]) => MetadataField::Text,
+        (Container::Png, b"eXIf") => return exif_fields(data),
+        (Container::Png, b"iCCP") => MetadataField::IccProfile,
+        (Container::Png, b"iTXt") if data.starts_with(b"XML:com.adobe.xmp\0") => MetadataField::Xmp,
+        (Container::Png, b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") => MetadataField::Text,
+        (Container::WebP, b"EXIF") => return exif_fields(data.strip_prefix(b"Exif\0\0").unwrap_or(data)),
+        (Container::WebP, b"XMP ") => MetadataField::Xmp,
+        (Container::WebP, b"ICCP") => MetadataField::IccProfile,
+        _ => return Vec::new(),
+    };
+    vec![field]
+}
+
+/// EXIF is always reported; GPS separately when IFD0 points at a GPS directory
+fn exif_fields(tiff: &[u8]) -> Vec<MetadataField> {
+    let mut fields = vec![MetadataField::Exif];
+    if Tiff::new(tiff).and_then(|tiff| tiff.ifd0_entry(0x8825)).is_some() {
+        fields.push(MetadataField::Gps);
+    }
+    fields
+}
+
+/// Metadata present in an image file; empty for anything that is not JPEG, PNG or WebP
+pub fn scan(content: &[u8]) -> Vec<MetadataField> {
+    let Some(container) = Container::detect(content) else { return Vec::new() };
+    let mut fields: Vec<MetadataField> = segments(content, container)
+        .iter()
+        .flat_map(|segment| classify(segment, container))
+        .collect();
+    fields.sort();
+    fields.dedup();
+    fields
+}
+
+/// Copy of the image without its EXIF, XMP, IPTC, text and (unless `keep_icc`) ICC segments,
+/// together with the kinds of metadata that were dropped
+pub fn strip(content: &[u8], keep_icc: bool) -> (Vec<u8>, Vec<MetadataField>) {
+    let Some(container) = Container::detect(content) else { return (content.to_vec(), Vec::new()) };
+
+    let mut removed = Vec::new();
+    let mut output = Vec::with_capacity(content.len());
+    let mut copied_to = 0;
+    for segment in segments(content, container) {
+        let fields = classify(&segment, container);
+        if fields.is_empty() || (keep_icc && fields == [MetadataField::IccProfile]) {
+            continue;
+        }
+        output.extend_from_slice(&content[copied_to..segment.range.start]);
+        copied_to = segment.range.end;
+        removed.extend(fields);
+    }
+    if removed.is_empty() {
+        return (content.to_vec(), removed);
+    }
+    output.extend_from_slice(&content[copied_to..]);
+    if container == Container::WebP {
+        fix_webp_header(&mut output);
+    }
+
+    removed.sort();
+    removed.dedup();
+    (output, removed)
+}
+
+/// Update the RIFF size and the VP8X feature flags after chunks were removed
+fn fix_webp_header(webp: &mut [u8]) {
+    let riff_size = (webp.len() - 8) as u32;
+    webp[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+    let present: Vec<[u8; 4]> = segments(webp, Container::WebP).iter().map(|segment| segment.kind).collect();
+    if webp.len() > 20 && &webp[12..16] == b"VP8X" {
+        for (flag, kind) in [(0x20, b"ICCP"), (0x08, b"EXIF"), (0x04, b"XMP ")] {
+            if !present.contains(kind) {
+                webp[20] &= !flag;
+            }
+        }
+    }
+}
+
+/// The embedded ICC profile of a JPEG, PNG or WebP image
+pub fn icc_profile(content: &[u8]) -> Option<Vec<u8>> {
+    let container = Container::detect(content)?;
+    let segments = segments(content, container);
+    match container {
+        Container::Jpeg => {
+            // Profiles larger than one segment are split and numbered from 1
+            let mut chunks: Vec<(u8, &[u8])> = segments
+                .iter()
+                .filter(|segment| segment.kind[0] == 0xE2 && segment.data.len() > 14 && segment.data.starts_with(b"ICC_PROFILE\0"))
+                .map(|segment| (segment.data[12], &segment.data[14..]))
+                .collect();
+            chunks.sort_by_key(|(sequence, _)| *sequence);
+            let profile: Vec<u8> = chunks.into_iter().flat_map(|(_, data)| data.iter().copied()).collect();
+            (!profile.is_empty()).then_some(profile)
+        }
+        Container::Png => {
+            let chunk = segments.iter().find(|segment| &segment.kind == b"iCCP")?;
+            // Profile name, NUL, compression method, then the zlib stream
+            let name_end = chunk.data.iter().position(|&b| b == 0)?;
+            let mut profile = Vec::new();
+            ZlibDecoder::new(chunk.data.get(name_end + 2..)?).read_to_end(&mut profile).ok()?;
+            Some(profile)
+        }
+        Container::WebP => segments
+            .iter()
+            .find(|segment| &segment.kind == b"ICCP")
+            .map(|segment| segment.data.to_vec()),
+    }
+}
+
+/// Insert an ICC profile into a freshly encoded JPEG or PNG; other formats are returned unchanged
+pub fn embed_icc_profile(content: Vec<u8>, profile: &[u8]) -> Vec<u8> {
+    match Container::detect(&content) {
+        Some(Container::Jpeg) => {
+            // Keep the JFIF APP0 segment first
+            let insert_at = segments(&content, Container::Jpeg)
+                .first()
+                .filter(|segment| segment.kind[0] == 0xE0)
+                .map_or(2, |segment| segment.range.end);
+            let count = profile.len().div_ceil(ICC_JPEG_CHUNK);
+            let mut icc = Vec::with_capacity(profile.len() + count * 18);
+            for (index, chunk) in profile.chunks(ICC_JPEG_CHUNK).enumerate() {
+                icc.extend_from_slice(&[0xFF, 0xE2]);
+                icc.extend_from_slice(&((chunk.len() + 16) as u16).to_be_bytes());
+                icc.extend_from_slice(b"ICC_PROFILE\0");
+                icc.extend_from_slice(&[index as u8 + 1, count as u8]);
+                icc.extend_from_slice(chunk);
+            }
+            splice(content, insert_at, &icc)
+        }
+        Some(Container::Png) => {
+            let mut data = b"ICC Profile\0\0".to_vec();
+            let mut encoder = ZlibEncoder::new(data, Compression::best());
+            if encoder.write_all(profile).is_err() {
+                return content;
+            }
+            data = match encoder.finish() {
+                Ok(data) => data,
+                Err(_) => return content,
+            };
+            // Directly after IHDR, ahead of PLTE and IDAT as the spec requires
+            splice(content, 8 + 25, &png_chunk(b"iCCP", &data))
+        }
+        _ => content,
+    }
+}
+
+/// Serialize a PNG chunk with its length and CRC
+pub(crate) fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    let mut crc = Crc::new();
+    crc.update(&chunk[4..]);
+    chunk.extend_from_slice(&crc.sum().to_be_bytes());
+    chunk
+}
+
+fn splice(content: Vec<u8>, at: usize, insert: &[u8]) -> Vec<u8> {
+    if at > content.len() {
+        return content;
+    }
+    let mut output = Vec::with_capacity(content.len() + insert.len());
+    output.extend_from_slice(&content[..at]);
+    output.extend_from_slice(insert);
+    output.extend_from_slice(&content[at..]);
+    output
+}
+
+/// EXIF Orientation tag (1-8) of a JPEG, PNG or WebP image
+pub fn exif_orientation(content: &[u8]) -> Option<u16> {
+    let container = Container::detect(content)?;
+    let segments = segments(content, container);
+    let exif = segments.iter().find_map(|segment| match (container, &segment.kind) {
+        (Container::Jpeg, [0xE1, ..]) => segment.data.strip_prefix(b"Exif\0\0"),
+        (Container::Png, b"eXIf") => Some(segment.data),
+        // WebP writers disagree on whether the "Exif\0\0" header is kept
+        (Container::WebP, b"EXIF") => Some(segment.data.strip_prefix(b"Exif\0\0").unwrap_or(segment.data)),
+        _ => None,
+    })?;
+
+    let tiff = Tiff::new(exif)?;
+    tiff.ifd0_entry(0x0112)
+        .and_then(|entry| tiff.u16_at(entry + 8))
+        .filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// Minimal reader for the TIFF structure inside an EXIF block
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Self { data, little_endian })
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    /// Offset of the 12-byte IFD0 entry for `tag`
+    fn ifd0_entry(&self, tag: u16) -> Option<usize> {
+        let ifd = self.u32_at(4)? as usize;
+        let entries = self.u16_at(ifd)? as usize;
+        (0..entries)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| self.u16_at(entry) == Some(tag))
+    }
+}
@@ -1,14 +1,62 @@
 use crate::types::*;
-use lopdf::{Document as PdfDocument, Object, Stream};
-use pdf_writer::{Pdf, Ref, writers::PageTreeWriter};
-use image::DynamicImage;
-use std::io::Cursor;
+use crate::image_processor::{self, ImageProcessor};
+use crate::pdf_renderer::{self, PdfRenderer};
+use flate2::{write::ZlibEncoder, Compression};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use lopdf::{Dictionary, Document as PdfDocument, Object};
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref};
+use std::io::{Cursor, Write};
 
-pub struct PdfProcessor;
+/// Upper bound on pages rasterized for a single conversion
+const MAX_RENDERED_PAGES: usize = 50;
+
+/// Upper bound on pixels rendered for a single conversion, summed over its pages, and on the
+/// canvas they are stitched onto; both are held in memory at once
+const MAX_RENDERED_PIXELS: u64 = 120_000_000;
+
+/// (DPI, JPEG quality) steps for recompressing embedded images, mildest first
+const IMAGE_DOWNSAMPLE_STEPS: [(f32, u8); 5] = [(150.0, 80), (120.0, 70), (96.0, 60), (72.0, 50), (60.0, 40)];
+
+/// A source of pages in a merged PDF
+enum MergePage<'a> {
+    Pdf(&'a DocumentInfo),
+    /// Decoded image, re-encoded as a JPEG page on each attempt
+    Image(DynamicImage),
+}
+
+#[derive(Clone)]
+pub struct PdfProcessor {
+    renderer: PdfRenderer,
+    on_attempt: Option<AttemptHook>,
+}
+
+impl Default for PdfProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl PdfProcessor {
     pub fn new() -> Self {
-        Self
+        Self {
+            renderer: PdfRenderer::new(),
+            on_attempt: None,
+        }
+    }
+
+    /// Copy of this processor that calls `on_attempt` before every save or encode of its size
+    /// searches
+    pub fn with_attempt_hook(&self, on_attempt: Option<AttemptHook>) -> Self {
+        Self {
+            renderer: self.renderer.clone(),
+            on_attempt,
+        }
+    }
+
+    fn report_attempt(&self) {
+        if let Some(on_attempt) = &self.on_attempt {
+            on_attempt();
+        }
     }
 
     /// Optimize existing PDF by removing unnecessary elements and compressing
@@ -41,60 +89,158 @@ impl PdfProcessor {
         }
     }
 
-    /// Create PDF from image with proper sizing
-    pub async fn create_pdf_from_image(&self, image_content: &[u8], target_size: Option<u64>) -> Result<Vec<u8>, ConversionError> {
-        let img = image::load_from_memory(image_content)?;
+    /// Optimize an existing PDF with increasingly aggressive strategies until it fits within
+    /// `max_size`, returning the strategy that got it there
+    pub async fn optimize_pdf_to_size(&self, content: &[u8], max_size: u64) -> Result<(Vec<u8>, PdfStrategy), ConversionError> {
+        let optimized = self.optimize_pdf(content).await?;
+        if optimized.len() as u64 <= max_size {
+            return Ok((optimized, PdfStrategy::StreamCompression));
+        }
+
+        let mut doc = PdfDocument::load_mem(&optimized)
+            .map_err(|e| ConversionError::Pdf(format!("Failed to load PDF: {}", e)))?;
+        if doc.is_encrypted() {
+            return Err(ConversionError::CompressionFailed {
+                message: format!("Encrypted PDF of {} bytes cannot be reduced below {} bytes", optimized.len(), max_size),
+            });
+        }
+
+        let mut smallest = optimized.len();
+        if let Some(output) = self.save_within(&doc, max_size, &mut smallest)? {
+            return Ok(Self::reached(output, PdfStrategy::ObjectStreams, max_size));
+        }
+
+        self.remove_metadata(&mut doc)?;
+        if let Some(output) = self.save_within(&doc, max_size, &mut smallest)? {
+            return Ok(Self::reached(output, PdfStrategy::MetadataRemoval, max_size));
+        }
+
+        for (grayscale, strategy) in [(false, PdfStrategy::ImageDownsampling), (true, PdfStrategy::Grayscale)] {
+            for (dpi, quality) in IMAGE_DOWNSAMPLE_STEPS {
+                // Always start from the original images to avoid compounding JPEG losses
+                let mut attempt = doc.clone();
+                if self.downsample_images(&mut attempt, dpi, quality, grayscale)? == 0 {
+                    break;
+                }
+                self.remove_unused_objects(&mut attempt)?;
+                if let Some(output) = self.save_within(&attempt, max_size, &mut smallest)? {
+                    return Ok(Self::reached(output, strategy, max_size));
+                }
+            }
+        }
+
+        Err(ConversionError::CompressionFailed {
+            message: format!("PDF is still {} bytes after every optimization strategy, over the {} byte limit", smallest, max_size),
+        })
+    }
+
+    fn reached(output: Vec<u8>, strategy: PdfStrategy, max_size: u64) -> (Vec<u8>, PdfStrategy) {
+        log::info!("PDF fits the {} byte limit after {:?}: {} bytes", max_size, strategy, output.len());
+        (output, strategy)
+    }
+
+    /// Save with object streams, returning the bytes only if they fit within `max_size`
+    fn save_within(&self, doc: &PdfDocument, max_size: u64, smallest: &mut usize) -> Result<Option<Vec<u8>>, ConversionError> {
+        self.report_attempt();
+        let output = save_with_object_streams(doc)?;
+        *smallest = (*smallest).min(output.len());
+        Ok((output.len() as u64 <= max_size).then_some(output))
+    }
+
+    /// Metadata an uploaded PDF carries: its Info dictionary and XMP streams
+    pub async fn metadata_fields(&self, content: &[u8]) -> Vec<MetadataField> {
+        match PdfDocument::load_mem(content) {
+            Ok(doc) => Self::metadata_in(&doc),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Clear the Info dictionary and every XMP metadata stream, returning the content unchanged
+    /// when there is nothing to remove
+    pub async fn scrub_metadata(&self, content: &[u8]) -> Result<(Vec<u8>, Vec<MetadataField>), ConversionError> {
+        let mut doc = PdfDocument::load_mem(content)
+            .map_err(|e| ConversionError::Pdf(format!("Failed to load PDF: {}", e)))?;
+        let found = Self::metadata_in(&doc);
+        if found.is_empty() {
+            return Ok((content.to_vec(), found));
+        }
+        if doc.is_encrypted() {
+            log::warn!("Leaving metadata of encrypted PDF in place");
+            return Ok((content.to_vec(), Vec::new()));
+        }
+
+        doc.trailer.remove(b"Info");
+        for object in doc.objects.values_mut() {
+            match object {
+                Object::Dictionary(dict) => dict.remove(b"Metadata"),
+                Object::Stream(stream) => stream.dict.remove(b"Metadata"),
+                _ => None,
+            };
+        }
+        self.remove_unused_objects(&mut doc)?;
+
+        let output = save_with_object_streams(&doc)?;
+        log::info!("Scrubbed PDF metadata {:?}: {} -> {} bytes", found, content.len(), output.len());
+        Ok((output, found))
+    }
+
+    fn metadata_in(doc: &PdfDocument) -> Vec<MetadataField> {
+        let mut found = Vec::new();
+        if doc.trailer.has(b"Info") {
+            found.push(MetadataField::DocumentInfo);
+        }
+        let has_xmp = doc.objects.values().any(|object| match object {
+            Object::Dictionary(dict) => dict.has(b"Metadata"),
+            Object::Stream(stream) => stream.dict.has(b"Metadata"),
+            _ => false,
+        });
+        if has_xmp {
+            found.push(MetadataField::Xmp);
+        }
+        found
+    }
+
+    /// Cut pages out of a PDF, producing one PDF per page group of the extraction
+    pub async fn extract_pages(&self, content: &[u8], extraction: &PageExtraction) -> Result<Vec<Vec<u8>>, ConversionError> {
+        let doc = PdfDocument::load_mem(content)
+            .map_err(|e| ConversionError::Pdf(format!("Failed to load PDF: {}", e)))?;
+        let page_count = doc.get_pages().len() as u32;
+        let groups = extraction.page_groups(page_count)?;
+
+        let mut outputs = Vec::with_capacity(groups.len());
+        for group in &groups {
+            let mut part = doc.clone();
+            let removed: Vec<u32> = (1..=page_count).filter(|page| !group.contains(page)).collect();
+            part.delete_pages(&removed);
+            self.remove_unused_objects(&mut part)?;
+
+            let mut output = Vec::new();
+            part.save_to(&mut output)
+                .map_err(|e| ConversionError::Pdf(format!("Failed to save extracted pages: {}", e)))?;
+            outputs.push(output);
+        }
+
+        log::info!("Extracted page groups {:?} from {}-page PDF", groups, page_count);
+        Ok(outputs)
+    }
+
+    /// Create PDF from an already decoded image
+    pub async fn create_pdf_from_decoded_image(&self, img: &DynamicImage, target_size: Option<u64>) -> Result<Vec<u8>, ConversionError> {
         let (width, height) = img.dimensions();
         
-        // Convert to RGB for PDF embedding
+        // Embed losslessly as Flate-compressed RGB first
         let rgb_img = img.to_rgb8();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(rgb_img.as_raw())?;
+        let image_data = encoder.finish()?;
         
-        // Create new PDF document
-        let mut pdf = Pdf::new();
-        
-        // Calculate page size (A4 proportions or image proportions)
-        let (page_width, page_height) = self.calculate_page_size(width, height);
-        
-        // Create page
-        let page_id = pdf.alloc_ref();
-        let mut page = pdf.page(page_id);
-        page.media_box([0.0, 0.0, page_width, page_height]);
-        page.parent(pdf.pages_id());
-        
-        // Create image XObject
-        let image_id = pdf.alloc_ref();
-        let mut image_obj = pdf.image_xobject(image_id);
-        image_obj.width(width as i32);
-        image_obj.height(height as i32);
-        image_obj.color_space().device_rgb();
-        image_obj.bits_per_component(8);
-        image_obj.data(rgb_img.as_raw());
-        image_obj.finish();
-        
-        // Create content stream
-        let content_id = pdf.alloc_ref();
-        page.contents(content_id);
-        page.finish();
-        
-        let mut content = pdf.content_stream(content_id);
-        content.save_state();
-        content.transform([page_width, 0.0, 0.0, page_height, 0.0, 0.0]);
-        content.x_object(image_id);
-        content.restore_state();
-        content.finish();
-        
-        // Create page tree
-        let mut page_tree = PageTreeWriter::new(&mut pdf);
-        page_tree.add_page(page_id);
-        page_tree.finish();
-        
-        let pdf_bytes = pdf.finish();
+        let pdf_bytes = self.write_image_pdf(width, height, &image_data, Filter::FlateDecode);
         
         // Check size constraint if specified
         if let Some(max_size) = target_size {
             if pdf_bytes.len() as u64 > max_size {
                 // Try with compressed image
-                return self.create_compressed_pdf_from_image(image_content, max_size).await;
+                return self.create_compressed_pdf_from_image(img, max_size).await;
             }
         }
         
@@ -103,18 +249,20 @@ impl PdfProcessor {
     }
 
     /// Create PDF with compressed image to meet size requirements
-    async fn create_compressed_pdf_from_image(&self, image_content: &[u8], max_size: u64) -> Result<Vec<u8>, ConversionError> {
-        let img = image::load_from_memory(image_content)?;
+    async fn create_compressed_pdf_from_image(&self, img: &DynamicImage, max_size: u64) -> Result<Vec<u8>, ConversionError> {
+        let (width, height) = img.dimensions();
+        let rgb_img = DynamicImage::ImageRgb8(img.to_rgb8());
         let mut quality = 85u8;
         
         for _ in 0..5 {
+            self.report_attempt();
             // Compress image first
             let mut compressed_img = Vec::new();
             let mut cursor = Cursor::new(&mut compressed_img);
-            img.write_to(&mut cursor, image::ImageOutputFormat::Jpeg(quality))?;
+            rgb_img.write_to(&mut cursor, image::ImageOutputFormat::Jpeg(quality))?;
             
-            // Create PDF with compressed image
-            let pdf_result = self.create_pdf_from_image(&compressed_img, None).await?;
+            // Create PDF with the JPEG embedded as-is
+            let pdf_result = self.write_image_pdf(width, height, &compressed_img, Filter::DctDecode);
             
             if pdf_result.len() as u64 <= max_size {
                 log::info!("Created compressed PDF: {} bytes with {}% JPEG quality", pdf_result.len(), quality);
@@ -129,49 +277,360 @@ impl PdfProcessor {
         })
     }
 
-    /// Extract first page of PDF as image
-    pub async fn pdf_to_image(&self, content: &[u8], format: ImageFormat, max_size: u64) -> Result<Vec<u8>, ConversionError> {
-        // This is a simplified implementation
-        // In production, you'd use a proper PDF rendering library like pdf2image
-        
-        match PdfDocument::load_mem(content) {
-            Ok(doc) => {
-                // For now, create a placeholder image representing the PDF
-                let placeholder = self.create_pdf_placeholder_image()?;
-                
-                match format {
-                    ImageFormat::Jpeg => {
-                        let mut output = Vec::new();
-                        let mut cursor = Cursor::new(&mut output);
-                        placeholder.write_to(&mut cursor, image::ImageOutputFormat::Jpeg(85))?;
-                        
-                        if output.len() as u64 <= max_size {
-                            Ok(output)
-                        } else {
-                            // Use image processor to compress further
-                            let processor = crate::image_processor::ImageProcessor::new();
-                            processor.compress_jpeg_to_size(&output, max_size).await
-                        }
+    /// Render the selected PDF pages and encode them according to the page layout
+    pub async fn pdf_to_images(
+        &self,
+        content: &[u8],
+        format: ImageFormat,
+        limits: SizeLimits,
+        options: &ConversionOptions,
+    ) -> Result<Vec<EncodedImage>, ConversionError> {
+        let doc = PdfDocument::load_mem(content)
+            .map_err(|e| ConversionError::Pdf(format!("Failed to load PDF: {}", e)))?;
+        let page_numbers = options.pages.resolve(doc.get_pages().len() as u32)?;
+        if page_numbers.len() > MAX_RENDERED_PAGES {
+            return Err(ConversionError::InvalidContent {
+                message: format!("Cannot render {} pages, the limit is {}", page_numbers.len(), MAX_RENDERED_PAGES),
+            });
+        }
+
+        let dimensions = options.dimensions()?;
+        let renderer = match options.render_dpi()? {
+            Some(dpi) => PdfRenderer::with_dpi(dpi),
+            None => self.renderer.clone(),
+        };
+        let sizes = page_numbers
+            .iter()
+            .map(|page_number| renderer.page_dimensions(&doc, *page_number))
+            .collect::<Result<Vec<_>, _>>()?;
+        let page_pixels: u64 = sizes.iter().map(|&(width, height)| width as u64 * height as u64).sum();
+        let (canvas_width, canvas_height) = match options.page_layout {
+            PageLayout::Separate => (0, 0),
+            PageLayout::Stacked => image_processor::grid_canvas_size(&sizes, 1),
+            PageLayout::Grid { columns } => image_processor::grid_canvas_size(&sizes, columns),
+        };
+        let canvas_pixels = canvas_width * canvas_height;
+        if page_pixels.max(canvas_pixels) > MAX_RENDERED_PIXELS {
+            return Err(ConversionError::InvalidContent {
+                message: format!(
+                    "Rendering pages {:?} needs {} pixels, over the limit of {}; select fewer pages or a lower DPI",
+                    page_numbers, page_pixels.max(canvas_pixels), MAX_RENDERED_PIXELS
+                ),
+            });
+        }
+
+        let mut rendered = Vec::with_capacity(page_numbers.len());
+        for page_number in &page_numbers {
+            rendered.push(renderer.render_page(&doc, *page_number)?);
+        }
+
+        // Hand the rendered pages to the image processor for stitching and size fitting
+        let processor = ImageProcessor::new()
+            .with_attempt_hook(self.on_attempt.clone())
+            .with_output_dpi(options.output_dpi())
+            .with_lossless_webp(options.lossless_webp);
+        let images = match options.page_layout {
+            PageLayout::Separate => rendered,
+            PageLayout::Stacked => vec![processor.arrange_grid(&rendered, 1)],
+            PageLayout::Grid { columns } => vec![processor.arrange_grid(&rendered, columns)],
+        };
+
+        let mut outputs = Vec::with_capacity(images.len());
+        for image in &images {
+            outputs.push(processor.encode_image(image, format, limits, dimensions).await?);
+        }
+
+        log::info!("Rendered pages {:?} of PDF into {} {:?} image(s)", page_numbers, outputs.len(), format);
+        Ok(outputs)
+    }
+
+    /// Merge images and PDFs, in order, into one PDF that fits within `max_size`
+    pub async fn merge_documents(&self, parts: &[DocumentInfo], max_size: u64) -> Result<Vec<u8>, ConversionError> {
+        // Decode images once; only their JPEG quality and scale change between attempts
+        let mut pages = Vec::new();
+        for part in parts {
+            match part.mime_type.as_str() {
+                "application/pdf" => pages.push(MergePage::Pdf(part)),
+                mime if mime.starts_with("image/") => {
+                    pages.extend(image_processor::load_frames(&part.content)?.into_iter().map(MergePage::Image));
+                }
+                _ => return Err(ConversionError::UnsupportedFormat {
+                    format: format!("{} in merged PDF", part.mime_type),
+                }),
+            }
+        }
+        match self.merge_pages(&pages, max_size) {
+            Err(ConversionError::CompressionFailed { message }) => {
+                // The PDF parts alone are too large; shrink the whole document as a single PDF would be
+                log::info!("{}; optimizing the merged PDF", message);
+                let merged = self.merge_pages(&pages, u64::MAX)?;
+                let (optimized, strategy) = self.optimize_pdf_to_size(&merged, max_size).await?;
+                log::info!("Merged {} pages into {} bytes after {:?}", pages.len(), optimized.len(), strategy);
+                Ok(optimized)
+            }
+            merged => merged,
+        }
+    }
+
+    /// PDF with one page per image, such as the frames of a multi-page TIFF
+    pub async fn create_pdf_from_decoded_images(&self, images: Vec<DynamicImage>, target_size: Option<u64>) -> Result<Vec<u8>, ConversionError> {
+        if let [img] = images.as_slice() {
+            return self.create_pdf_from_decoded_image(img, target_size).await;
+        }
+
+        // Lossless pages first, as for a single image
+        let mut sources = Vec::with_capacity(images.len());
+        for img in &images {
+            let page_pdf = self.create_pdf_from_decoded_image(img, None).await?;
+            sources.push(PdfDocument::load_mem(&page_pdf).map_err(|e| ConversionError::Pdf(e.to_string()))?);
+        }
+        let merged = self.merge_pdfs(sources)?;
+        let max_size = target_size.unwrap_or(u64::MAX);
+        if merged.len() as u64 <= max_size {
+            log::info!("Created {}-page PDF from images: {} bytes", images.len(), merged.len());
+            return Ok(merged);
+        }
+
+        let pages: Vec<MergePage> = images.into_iter().map(MergePage::Image).collect();
+        self.merge_pages(&pages, max_size)
+    }
+
+    /// Merge pages into one PDF, recompressing the image pages until it fits within `max_size`
+    fn merge_pages(&self, pages: &[MergePage], max_size: u64) -> Result<Vec<u8>, ConversionError> {
+        let has_images = pages.iter().any(|page| matches!(page, MergePage::Image(_)));
+
+        // (JPEG quality, image scale) steps, from best looking to smallest
+        const MERGE_STEPS: [(u8, f32); 7] = [(85, 1.0), (70, 1.0), (55, 1.0), (55, 0.75), (45, 0.6), (35, 0.45), (30, 0.3)];
+        let mut smallest = u64::MAX;
+        for (quality, scale) in MERGE_STEPS {
+            self.report_attempt();
+            let mut sources = Vec::with_capacity(pages.len());
+            for page in pages {
+                let source = match page {
+                    MergePage::Image(img) => {
+                        let page_pdf = self.jpeg_page_pdf(img, quality, scale)?;
+                        PdfDocument::load_mem(&page_pdf).map_err(|e| ConversionError::Pdf(format!("Failed to load image page: {}", e)))
                     }
-                    ImageFormat::Png => {
-                        let mut output = Vec::new();
-                        let mut cursor = Cursor::new(&mut output);
-                        placeholder.write_to(&mut cursor, image::ImageOutputFormat::Png)?;
-                        
-                        if output.len() as u64 <= max_size {
-                            Ok(output)
-                        } else {
-                            let processor = crate::image_processor::ImageProcessor::new();
-                            processor.compress_png_to_size(&output, max_size).await
-                        }
+                    MergePage::Pdf(part) => PdfDocument::load_mem(&part.content)
+                        .map_err(|e| ConversionError::Pdf(format!("Failed to load {}: {}", part.name, e))),
+                };
+                sources.push(source?);
+            }
+
+            let merged = self.merge_pdfs(sources)?;
+            if merged.len() as u64 <= max_size {
+                log::info!("Merged {} pages into {} bytes (JPEG quality {}, scale {:.2})", 
+                    pages.len(), merged.len(), quality, scale);
+                return Ok(merged);
+            }
+            smallest = smallest.min(merged.len() as u64);
+
+            // Without images there is nothing left to shrink
+            if !has_images {
+                break;
+            }
+        }
+
+        Err(ConversionError::CompressionFailed {
+            message: format!("Merged PDF is at least {} bytes, over the {} byte limit", smallest, max_size),
+        })
+    }
+
+    /// Single-page PDF holding an image re-encoded as JPEG
+    fn jpeg_page_pdf(&self, img: &DynamicImage, quality: u8, scale: f32) -> Result<Vec<u8>, ConversionError> {
+        let (width, height) = img.dimensions();
+        let scaled = if scale < 1.0 {
+            let new_width = ((width as f32 * scale) as u32).max(1);
+            let new_height = ((height as f32 * scale) as u32).max(1);
+            img.resize_exact(new_width, new_height, image::imageops::FilterType::Lanczos3)
+        } else {
+            img.clone()
+        };
+
+        let rgb_img = DynamicImage::ImageRgb8(scaled.to_rgb8());
+        let mut jpeg = Vec::new();
+        rgb_img.write_to(&mut Cursor::new(&mut jpeg), image::ImageOutputFormat::Jpeg(quality))?;
+        Ok(self.write_image_pdf(rgb_img.width(), rgb_img.height(), &jpeg, Filter::DctDecode))
+    }
+
+    /// Copy every page of the source documents, in order, into a new PDF
+    fn merge_pdfs(&self, sources: Vec<PdfDocument>) -> Result<Vec<u8>, ConversionError> {
+        let mut merged = PdfDocument::with_version("1.5");
+        let pages_id = merged.new_object_id();
+        let mut kids = Vec::new();
+
+        for mut source in sources {
+            source.renumber_objects_with(merged.max_id + 1);
+
+            let page_ids: Vec<_> = source.get_pages().into_values().collect();
+            for page_id in &page_ids {
+                // Attributes inherited from the source page tree must live on the page itself
+                let inherited = Self::inherited_page_attributes(&source, *page_id);
+                let page = source
+                    .get_object_mut(*page_id)
+                    .and_then(Object::as_dict_mut)
+                    .map_err(|e| ConversionError::Pdf(format!("Invalid page object: {}", e)))?;
+                for (key, value) in inherited {
+                    page.set(key, value);
+                }
+                page.set("Parent", pages_id);
+                kids.push(Object::Reference(*page_id));
+            }
+
+            // Copy everything except the source's own catalog and page tree nodes
+            for (id, object) in source.objects {
+                let skip = match object.type_name() {
+                    Ok("Catalog") | Ok("Pages") => true,
+                    Ok("Page") => !page_ids.contains(&id),
+                    _ => false,
+                };
+                if !skip {
+                    merged.objects.insert(id, object);
+                }
+            }
+            merged.max_id = merged.max_id.max(source.max_id);
+        }
+
+        let count = kids.len() as i64;
+        let mut pages = Dictionary::new();
+        pages.set("Type", "Pages");
+        pages.set("Kids", kids);
+        pages.set("Count", count);
+        merged.objects.insert(pages_id, Object::Dictionary(pages));
+
+        let mut catalog = Dictionary::new();
+        catalog.set("Type", "Catalog");
+        catalog.set("Pages", pages_id);
+        let catalog_id = merged.add_object(catalog);
+        merged.trailer.set("Root", catalog_id);
+
+        self.remove_unused_objects(&mut merged)?;
+        self.compress_streams(&mut merged)?;
+
+        let mut output = Vec::new();
+        merged.save_to(&mut output)
+            .map_err(|e| ConversionError::Pdf(format!("Failed to save merged PDF: {}", e)))?;
+        Ok(output)
+    }
+
+    /// Page attributes a page may inherit from its ancestors in the page tree
+    fn inherited_page_attributes(doc: &PdfDocument, page_id: lopdf::ObjectId) -> Vec<(&'static str, Object)> {
+        let mut attributes = Vec::new();
+        for key in ["MediaBox", "CropBox", "Resources", "Rotate"] {
+            let mut node_id = Some(page_id);
+            // Bounded walk up the tree in case of reference cycles
+            for _ in 0..32 {
+                let Some(node) = node_id.and_then(|id| doc.get_dictionary(id).ok()) else {
+                    break;
+                };
+                if let Ok(value) = node.get(key.as_bytes()) {
+                    if node_id != Some(page_id) {
+                        attributes.push((key, value.clone()));
                     }
-                    _ => Err(ConversionError::UnsupportedFormat {
-                        format: format!("PDF to {:?}", format),
-                    }),
+                    break;
                 }
+                node_id = node.get(b"Parent").and_then(Object::as_reference).ok();
+            }
+        }
+        attributes
+    }
+
+    /// Re-encode image XObjects as JPEG, no larger than their largest drawn size at `dpi`, optionally
+    /// in grayscale. Images the page content never draws are bounded by the biggest page instead.
+    /// Returns how many images were replaced.
+    fn downsample_images(&self, doc: &mut PdfDocument, dpi: f32, quality: u8, grayscale: bool) -> Result<usize, ConversionError> {
+        // An image is never shown larger than the biggest page, so that bounds its useful resolution
+        let longest_side = doc
+            .get_pages()
+            .values()
+            .filter_map(|id| pdf_renderer::page_size(doc, *id))
+            .map(|(width, height)| width.max(height))
+            .fold(0.0f32, f32::max);
+        let longest_side = if longest_side > 0.0 { longest_side } else { 842.0 };
+        let display_sizes = pdf_renderer::image_display_sizes(doc);
+
+        let image_ids: Vec<_> = doc
+            .objects
+            .iter()
+            .filter(|(_, object)| matches!(object, Object::Stream(stream)
+                if stream.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image")))
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut replaced = 0;
+        for id in image_ids {
+            let Ok(Object::Stream(stream)) = doc.get_object(id) else {
+                continue;
+            };
+            // Colour-key masks name ranges of the original samples, which a JPEG no longer matches
+            if matches!(stream.dict.get(b"Mask"), Ok(Object::Array(_))) {
+                continue;
+            }
+            // Skip image masks and anything the renderer cannot decode (JBIG2, CCITT, ...)
+            let Some(image) = pdf_renderer::decode_image_xobject(doc, stream) else {
+                continue;
+            };
+
+            let shown = display_sizes.get(&id).map_or(longest_side, |size| size.min(longest_side));
+            let max_pixels = ((shown / 72.0 * dpi).round() as u32).max(1);
+            let (width, height) = image.dimensions();
+            let image = if width.max(height) > max_pixels {
+                let scale = max_pixels as f32 / width.max(height) as f32;
+                let new_width = ((width as f32 * scale).round() as u32).max(1);
+                let new_height = ((height as f32 * scale).round() as u32).max(1);
+                image.resize_exact(new_width, new_height, image::imageops::FilterType::Triangle)
+            } else {
+                image
+            };
+            let image = if grayscale {
+                DynamicImage::ImageLuma8(image.to_luma8())
+            } else {
+                image
+            };
+
+            let mut jpeg = Vec::new();
+            image.write_to(&mut Cursor::new(&mut jpeg), image::ImageOutputFormat::Jpeg(quality))?;
+            if jpeg.len() >= stream.content.len() {
+                continue;
             }
-            Err(e) => Err(ConversionError::Pdf(format!("Failed to load PDF: {}", e))),
+
+            let color_space = match image {
+                DynamicImage::ImageLuma8(_) => "DeviceGray",
+                _ => "DeviceRGB",
+            };
+            let Ok(Object::Stream(stream)) = doc.get_object_mut(id) else {
+                continue;
+            };
+            for key in [b"DecodeParms".as_slice(), b"Decode", b"Intent"] {
+                stream.dict.remove(key);
+            }
+            stream.dict.set("Filter", Object::Name(b"DCTDecode".to_vec()));
+            stream.dict.set("ColorSpace", Object::Name(color_space.as_bytes().to_vec()));
+            stream.dict.set("BitsPerComponent", 8);
+            stream.dict.set("Width", image.width() as i64);
+            stream.dict.set("Height", image.height() as i64);
+            stream.set_content(jpeg);
+            replaced += 1;
         }
+
+        log::info!("Re-encoded {} embedded images at {} DPI, quality {} (grayscale: {})", replaced, dpi, quality, grayscale);
+        Ok(replaced)
+    }
+
+    /// Drop the document info dictionary, XMP metadata, private application data and thumbnails
+    fn remove_metadata(&self, doc: &mut PdfDocument) -> Result<(), ConversionError> {
+        doc.trailer.remove(b"Info");
+        for object in doc.objects.values_mut() {
+            let dict = match object {
+                Object::Dictionary(dict) => dict,
+                Object::Stream(stream) => &mut stream.dict,
+                _ => continue,
+            };
+            for key in [b"Metadata".as_slice(), b"PieceInfo", b"Thumb"] {
+                dict.remove(key);
+            }
+        }
+        self.remove_unused_objects(doc)
     }
 
     /// Remove unused objects from PDF to reduce size
@@ -189,7 +648,8 @@ impl PdfProcessor {
                 // Apply compression if not already compressed
                 if !stream.dict.has(b"Filter") {
                     // Add FlateDecode filter for compression
-                    stream.compress();
+                    stream.compress()
+                        .map_err(|e| ConversionError::Pdf(format!("Failed to compress stream: {}", e)))?;
                 }
             }
         }
@@ -214,42 +674,210 @@ impl PdfProcessor {
         }
     }
 
-    /// Create placeholder image for PDF content
-    fn create_pdf_placeholder_image(&self) -> Result<DynamicImage, ConversionError> {
-        use image::{Rgb, RgbImage};
+    /// Write a single-page PDF showing one RGB image XObject scaled to the page
+    fn write_image_pdf(&self, width: u32, height: u32, image_data: &[u8], filter: Filter) -> Vec<u8> {
+        let catalog_id = Ref::new(1);
+        let page_tree_id = Ref::new(2);
+        let page_id = Ref::new(3);
+        let image_id = Ref::new(4);
+        let content_id = Ref::new(5);
+        let image_name = Name(b"Im1");
         
-        let width = 800;
-        let height = 600;
-        let mut img = RgbImage::new(width, height);
+        // Calculate page size (A4 proportions or image proportions)
+        let (page_width, page_height) = self.calculate_page_size(width, height);
         
-        // Fill with light gray background
-        for pixel in img.pixels_mut() {
-            *pixel = Rgb([240, 240, 240]);
-        }
+        let mut pdf = Pdf::new();
+        pdf.catalog(catalog_id).pages(page_tree_id);
+        pdf.pages(page_tree_id).kids([page_id]).count(1);
         
-        // Add border
-        for x in 0..width {
-            img.put_pixel(x, 0, Rgb([100, 100, 100]));
-            img.put_pixel(x, height - 1, Rgb([100, 100, 100]));
-        }
-        for y in 0..height {
-            img.put_pixel(0, y, Rgb([100, 100, 100]));
-            img.put_pixel(width - 1, y, Rgb([100, 100, 100]));
-        }
+        let mut page = pdf.page(page_id);
+        page.media_box(Rect::new(0.0, 0.0, page_width, page_height));
+        page.parent(page_tree_id);
+        page.contents(content_id);
+        page.resources().x_objects().pair(image_name, image_id);
+        page.finish();
+        
+        let mut image = pdf.image_xobject(image_id, image_data);
+        image.filter(filter);
+        image.width(width as i32);
+        image.height(height as i32);
+        image.color_space().device_rgb();
+        image.bits_per_component(8);
+        image.finish();
         
-        // Add "PDF" text representation (simplified)
-        let center_x = width / 2;
-        let center_y = height / 2;
+        let mut content = Content::new();
+        content.save_state();
+        content.transform([page_width, 0.0, 0.0, page_height, 0.0, 0.0]);
+        content.x_object(image_name);
+        content.restore_state();
+        pdf.stream(content_id, &content.finish());
         
-        // Draw simple "PDF" indicator
-        for x in (center_x - 50)..(center_x + 50) {
-            for y in (center_y - 20)..(center_y + 20) {
-                if x < width && y < height {
-                    img.put_pixel(x, y, Rgb([200, 200, 200]));
+        pdf.finish()
+    }
+}
+
+// === OBJECT STREAM WRITER ===
+
+/// Non-stream objects packed into each object stream
+const OBJECTS_PER_STREAM: usize = 100;
+
+enum XrefEntry {
+    Offset(usize, u16),
+    Compressed(u32, u16),
+}
+
+/// Serialize a document as PDF 1.5 with non-stream objects packed into compressed object
+/// streams and a cross-reference stream; `lopdf` 0.32 only writes objects individually
+fn save_with_object_streams(doc: &PdfDocument) -> Result<Vec<u8>, ConversionError> {
+    let skipped = |object: &Object| matches!(object.type_name(), Ok("ObjStm") | Ok("XRef"));
+    let (packable, direct): (Vec<_>, Vec<_>) = doc
+        .objects
+        .iter()
+        .filter(|(_, object)| !skipped(object))
+        .partition(|((_, generation), object)| *generation == 0 && !matches!(object, Object::Stream(_)));
+
+    let version = if doc.version.as_str() < "1.5" { "1.5" } else { doc.version.as_str() };
+    let mut output = format!("%PDF-{}\n", version).into_bytes();
+    output.extend_from_slice(b"%\xE2\xE3\xCF\xD3\n");
+    let mut entries = std::collections::BTreeMap::new();
+    let mut next_id = doc.max_id + 1;
+
+    for ((id, generation), object) in direct {
+        entries.insert(*id, XrefEntry::Offset(output.len(), *generation));
+        write_indirect(&mut output, *id, *generation, object)?;
+    }
+
+    for chunk in packable.chunks(OBJECTS_PER_STREAM) {
+        let stream_id = next_id;
+        next_id += 1;
+
+        let mut header = Vec::new();
+        let mut body = Vec::new();
+        for (index, ((id, _), object)) in chunk.iter().enumerate() {
+            entries.insert(*id, XrefEntry::Compressed(stream_id, index as u16));
+            write!(header, "{} {} ", id, body.len())?;
+            write_object(&mut body, object)?;
+            body.push(b'\n');
+        }
+
+        let mut dict = Dictionary::new();
+        dict.set("Type", "ObjStm");
+        dict.set("N", chunk.len() as i64);
+        dict.set("First", header.len() as i64);
+        dict.set("Filter", "FlateDecode");
+        header.extend_from_slice(&body);
+        let stream = Object::Stream(lopdf::Stream::new(dict, deflate(&header)?));
+
+        entries.insert(stream_id, XrefEntry::Offset(output.len(), 0));
+        write_indirect(&mut output, stream_id, 0, &stream)?;
+    }
+
+    // Cross-reference stream: type (1 byte), offset or object stream number (4), generation or index (2)
+    let xref_id = next_id;
+    let xref_offset = output.len();
+    entries.insert(xref_id, XrefEntry::Offset(xref_offset, 0));
+    let size = xref_id + 1;
+    let mut rows = Vec::with_capacity(size as usize * 7);
+    for id in 0..size {
+        let (kind, field2, field3) = match entries.get(&id) {
+            Some(XrefEntry::Offset(offset, generation)) => (1u8, *offset as u32, *generation),
+            Some(XrefEntry::Compressed(stream_id, index)) => (2u8, *stream_id, *index),
+            None => (0u8, 0, if id == 0 { 65535 } else { 0 }),
+        };
+        rows.push(kind);
+        rows.extend_from_slice(&field2.to_be_bytes());
+        rows.extend_from_slice(&field3.to_be_bytes());
+    }
+
+    let mut dict = Dictionary::new();
+    dict.set("Type", "XRef");
+    dict.set("Size", size as i64);
+    dict.set("W", vec![Object::Integer(1), Object::Integer(4), Object::Integer(2)]);
+    dict.set("Filter", "FlateDecode");
+    for key in [b"Root".as_slice(), b"Info", b"ID"] {
+        if let Ok(value) = doc.trailer.get(key) {
+            dict.set(key, value.clone());
+        }
+    }
+    let xref = Object::Stream(lopdf::Stream::new(dict, deflate(&rows)?));
+    write_indirect(&mut output, xref_id, 0, &xref)?;
+    write!(output, "startxref\n{}\n%%EOF\n", xref_offset)?;
+    Ok(output)
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>, ConversionError> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn write_indirect(output: &mut Vec<u8>, id: u32, generation: u16, object: &Object) -> Result<(), ConversionError> {
+    writeln!(output, "{} {} obj", id, generation)?;
+    match object {
+        Object::Stream(stream) => {
+            let mut dict = stream.dict.clone();
+            dict.set("Length", stream.content.len() as i64);
+            write_object(output, &Object::Dictionary(dict))?;
+            output.extend_from_slice(b"\nstream\n");
+            output.extend_from_slice(&stream.content);
+            output.extend_from_slice(b"\nendstream");
+        }
+        other => write_object(output, other)?,
+    }
+    output.extend_from_slice(b"\nendobj\n");
+    Ok(())
+}
+
+fn write_object(output: &mut Vec<u8>, object: &Object) -> Result<(), ConversionError> {
+    match object {
+        Object::Null => output.extend_from_slice(b"null"),
+        Object::Boolean(value) => write!(output, "{}", value)?,
+        Object::Integer(value) => write!(output, "{}", value)?,
+        Object::Real(value) => write!(output, "{}", value)?,
+        Object::Name(name) => {
+            output.push(b'/');
+            for &byte in name {
+                if byte.is_ascii_graphic() && !b"#()<>[]{}/%".contains(&byte) {
+                    output.push(byte);
+                } else {
+                    write!(output, "#{:02X}", byte)?;
                 }
             }
         }
-        
-        Ok(DynamicImage::ImageRgb8(img))
+        Object::String(text, _) => {
+            output.push(b'(');
+            for &byte in text {
+                match byte {
+                    b'(' | b')' | b'\\' => output.extend_from_slice(&[b'\\', byte]),
+                    b'\r' => output.extend_from_slice(b"\\r"),
+                    _ => output.push(byte),
+                }
+            }
+            output.push(b')');
+        }
+        Object::Array(items) => {
+            output.push(b'[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    output.push(b' ');
+                }
+                write_object(output, item)?;
+            }
+            output.push(b']');
+        }
+        Object::Dictionary(dict) => {
+            output.extend_from_slice(b"<<");
+            for (key, value) in dict.iter() {
+                write_object(output, &Object::Name(key.clone()))?;
+                output.push(b' ');
+                write_object(output, value)?;
+            }
+            output.extend_from_slice(b">>");
+        }
+        Object::Reference((id, generation)) => write!(output, "{} {} R", id, generation)?,
+        Object::Stream(_) => {
+            return Err(ConversionError::Pdf("Streams cannot be nested inside other objects".to_string()));
+        }
     }
-}
\ No newline at end of file
+    Ok(())
+}
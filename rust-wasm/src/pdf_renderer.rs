@@ -0,0 +1,1620 @@
+//! Pure-Rust PDF page rasterizer.
+//!
+//! Interprets page content streams loaded through `lopdf` and paints them onto a
+//! `tiny_skia` pixmap: vector paths, text set in embedded TrueType/CFF fonts and
+//! image XObjects. Non-embedded fonts, Type 1 font programs, shadings and
+//! patterns are skipped.
+
+use crate::types::*;
+use image::{DynamicImage, RgbImage};
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document as PdfDocument, Object, ObjectId, Stream};
+use std::collections::HashMap;
+use std::io::Read;
+use std::rc::Rc;
+use tiny_skia::{
+    Color, FillRule, FilterQuality, IntSize, LineCap, LineJoin, Mask, Paint, Path, PathBuilder,
+    Pixmap, PixmapPaint, Stroke, StrokeDash, Transform,
+};
+
+/// Resolution used when no explicit DPI is requested
+pub const DEFAULT_RENDER_DPI: f32 = 150.0;
+
+/// Render resolutions a request may ask for
+pub const MIN_RENDER_DPI: f32 = 36.0;
+pub const MAX_RENDER_DPI: f32 = 600.0;
+
+/// Upper bound on pixels per rendered page to keep memory usage predictable
+const MAX_RENDER_PIXELS: f32 = 40_000_000.0;
+
+/// Upper bound on the declared pixels of an embedded image; larger ones are skipped unread
+const MAX_IMAGE_PIXELS: i64 = 40_000_000;
+
+/// Maximum nesting of Form XObjects before we stop descending
+const MAX_FORM_DEPTH: usize = 12;
+
+/// Upper bound on XObjects drawn and content operators run for one page, so forms that draw
+/// themselves many times over cannot multiply the work at every level of nesting
+const MAX_PAGE_XOBJECTS: usize = 10_000;
+const MAX_PAGE_OPERATIONS: usize = 2_000_000;
+
+/// Maximum nesting of colour spaces (an `Indexed` base, an alternate space) before we stop
+/// resolving them; a space that refers back to itself would otherwise never end
+const MAX_COLOR_SPACE_DEPTH: usize = 4;
+
+/// Upper bound on the CIDs a font's `W` array may assign widths to
+const MAX_CID_WIDTHS: usize = 65_536;
+
+#[derive(Clone)]
+pub struct PdfRenderer {
+    dpi: f32,
+}
+
+impl Default for PdfRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PdfRenderer {
+    pub fn new() -> Self {
+        Self {
+            dpi: DEFAULT_RENDER_DPI,
+        }
+    }
+
+    pub fn with_dpi(dpi: f32) -> Self {
+        Self {
+            dpi: dpi.clamp(MIN_RENDER_DPI, MAX_RENDER_DPI),
+        }
+    }
+
+    /// Pixel size `render_page` will produce for a page, without rendering it
+    pub fn page_dimensions(&self, doc: &PdfDocument, page_number: u32) -> Result<(u32, u32), ConversionError> {
+        let frame = self.frame_page(doc, page_number)?;
+        Ok((frame.width, frame.height))
+    }
+
+    /// Render a single page (1-based, as in `lopdf::Document::get_pages`) to an RGB image
+    pub fn render_page(&self, doc: &PdfDocument, page_number: u32) -> Result<DynamicImage, ConversionError> {
+        let PageFrame { page_id, page, width, height, device, scale } = self.frame_page(doc, page_number)?;
+
+        let mut pixmap = Pixmap::new(width, height).ok_or_else(|| ConversionError::Pdf(format!(
+            "Cannot allocate {}x{} canvas for page {}", width, height, page_number
+        )))?;
+        pixmap.fill(Color::WHITE);
+
+        let content = doc
+            .get_page_content(page_id)
+            .map_err(|e| ConversionError::Pdf(format!("Failed to read page content: {}", e)))?;
+        let operations = Content::decode(&content)
+            .map_err(|e| ConversionError::Pdf(format!("Failed to parse page content: {}", e)))?
+            .operations;
+        let resources = inherited(doc, page, b"Resources").and_then(|o| deref(doc, o).as_dict().ok());
+
+        let mut canvas = Canvas::new(doc, pixmap, device);
+        canvas.execute(&operations, resources, 0);
+
+        log::info!("Rendered PDF page {} at {:.0} DPI: {}x{} px", page_number, scale * 72.0, width, height);
+        Ok(canvas.into_image())
+    }
+
+    /// Canvas size and device transform for a page at this renderer's DPI
+    fn frame_page<'d>(&self, doc: &'d PdfDocument, page_number: u32) -> Result<PageFrame<'d>, ConversionError> {
+        let pages = doc.get_pages();
+        let page_id = *pages.get(&page_number).ok_or_else(|| ConversionError::InvalidContent {
+            message: format!("PDF has no page {} ({} pages)", page_number, pages.len()),
+        })?;
+        let page = doc
+            .get_dictionary(page_id)
+            .map_err(|e| ConversionError::Pdf(format!("Failed to read page {}: {}", page_number, e)))?;
+
+        let media_box = inherited(doc, page, b"MediaBox")
+            .and_then(|o| read_rect(doc, o))
+            .unwrap_or([0.0, 0.0, 612.0, 792.0]);
+        let crop_box = inherited(doc, page, b"CropBox")
+            .and_then(|o| read_rect(doc, o))
+            .map(|c| intersect_rect(c, media_box))
+            .unwrap_or(media_box);
+        let rotate = inherited(doc, page, b"Rotate")
+            .and_then(|o| o.as_i64().ok())
+            .unwrap_or(0)
+            .rem_euclid(360);
+
+        let [x0, y0, x1, y1] = crop_box;
+        let (page_w, page_h) = ((x1 - x0).max(1.0), (y1 - y0).max(1.0));
+        let mut scale = self.dpi / 72.0;
+        if page_w * page_h * scale * scale > MAX_RENDER_PIXELS {
+            scale = (MAX_RENDER_PIXELS / (page_w * page_h)).sqrt();
+            log::warn!("Page {} too large at {} DPI, rendering at {:.0} DPI", page_number, self.dpi, scale * 72.0);
+        }
+
+        let (width, height, device) = match rotate {
+            90 => (page_h, page_w, Transform::from_row(0.0, scale, scale, 0.0, -y0 * scale, -x0 * scale)),
+            180 => (page_w, page_h, Transform::from_row(-scale, 0.0, 0.0, scale, x1 * scale, -y0 * scale)),
+            270 => (page_h, page_w, Transform::from_row(0.0, -scale, -scale, 0.0, y1 * scale, x1 * scale)),
+            _ => (page_w, page_h, Transform::from_row(scale, 0.0, 0.0, -scale, -x0 * scale, y1 * scale)),
+        };
+        let width = ((width * scale).round() as u32).max(1);
+        let height = ((height * scale).round() as u32).max(1);
+        Ok(PageFrame { page_id, page, width, height, device, scale })
+    }
+}
+
+struct PageFrame<'d> {
+    page_id: ObjectId,
+    page: &'d Dictionary,
+    width: u32,
+    height: u32,
+    device: Transform,
+    scale: f32,
+}
+
+// === CONTENT STREAM INTERPRETER ===
+
+#[derive(Clone)]
+enum ColorSpace {
+    Gray,
+    Rgb,
+    Cmyk,
+    Indexed { base: Box<ColorSpace>, hival: usize, lookup: Rc<Vec<u8>> },
+    /// Separation/DeviceN approximated as a single darkening tint
+    Tint(usize),
+    Lab,
+    Pattern,
+}
+
+impl ColorSpace {
+    fn components(&self) -> usize {
+        match self {
+            ColorSpace::Gray | ColorSpace::Indexed { .. } | ColorSpace::Pattern => 1,
+            ColorSpace::Rgb | ColorSpace::Lab => 3,
+            ColorSpace::Cmyk => 4,
+            ColorSpace::Tint(n) => *n,
+        }
+    }
+
+    fn initial_color(&self) -> Vec<f32> {
+        match self {
+            ColorSpace::Cmyk => vec![0.0, 0.0, 0.0, 1.0],
+            ColorSpace::Tint(n) => vec![1.0; *n],
+            ColorSpace::Lab => vec![0.0, 0.0, 0.0],
+            other => vec![0.0; other.components()],
+        }
+    }
+
+    /// Convert components (already in the colour space's native range) to 8-bit RGB
+    fn to_rgb(&self, c: &[f32]) -> [u8; 3] {
+        let get = |i: usize| c.get(i).copied().unwrap_or(0.0);
+        let unit = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        match self {
+            ColorSpace::Gray | ColorSpace::Pattern => [unit(get(0)); 3],
+            ColorSpace::Rgb => [unit(get(0)), unit(get(1)), unit(get(2))],
+            ColorSpace::Cmyk => {
+                let k = 1.0 - get(3).clamp(0.0, 1.0);
+                [unit((1.0 - get(0)) * k), unit((1.0 - get(1)) * k), unit((1.0 - get(2)) * k)]
+            }
+            ColorSpace::Tint(n) => {
+                let tint = (0..*n).map(get).fold(0.0f32, f32::max);
+                [unit(1.0 - tint); 3]
+            }
+            ColorSpace::Lab => [unit(get(0) / 100.0); 3],
+            ColorSpace::Indexed { base, hival, lookup } => {
+                let index = (get(0).round().max(0.0) as usize).min(*hival);
+                let n = base.components();
+                let entry: Vec<f32> = (0..n)
+                    .map(|i| lookup.get(index * n + i).copied().unwrap_or(0) as f32 / 255.0)
+                    .map(|v| if matches!(**base, ColorSpace::Lab) { v * 100.0 } else { v })
+                    .collect();
+                base.to_rgb(&entry)
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct TextState {
+    font: Option<Rc<LoadedFont>>,
+    size: f32,
+    char_spacing: f32,
+    word_spacing: f32,
+    horizontal_scale: f32,
+    leading: f32,
+    rise: f32,
+    render_mode: i64,
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+    ctm: Transform,
+    fill_space: ColorSpace,
+    fill_color: [u8; 3],
+    stroke_space: ColorSpace,
+    stroke_color: [u8; 3],
+    fill_alpha: f32,
+    stroke_alpha: f32,
+    line_width: f32,
+    line_cap: LineCap,
+    line_join: LineJoin,
+    miter_limit: f32,
+    dash: Option<(Vec<f32>, f32)>,
+    clip: Option<Rc<Mask>>,
+    text: TextState,
+}
+
+impl GraphicsState {
+    fn new() -> Self {
+        Self {
+            ctm: Transform::identity(),
+            fill_space: ColorSpace::Gray,
+            fill_color: [0, 0, 0],
+            stroke_space: ColorSpace::Gray,
+            stroke_color: [0, 0, 0],
+            fill_alpha: 1.0,
+            stroke_alpha: 1.0,
+            line_width: 1.0,
+            line_cap: LineCap::Butt,
+            line_join: LineJoin::Miter,
+            miter_limit: 10.0,
+            dash: None,
+            clip: None,
+            text: TextState {
+                font: None,
+                size: 0.0,
+                char_spacing: 0.0,
+                word_spacing: 0.0,
+                horizontal_scale: 1.0,
+                leading: 0.0,
+                rise: 0.0,
+                render_mode: 0,
+            },
+        }
+    }
+}
+
+struct Canvas<'a> {
+    doc: &'a PdfDocument,
+    pixmap: Pixmap,
+    device: Transform,
+    state: GraphicsState,
+    stack: Vec<GraphicsState>,
+    path: PathBuilder,
+    current_point: (f32, f32),
+    pending_clip: Option<FillRule>,
+    text_matrix: Transform,
+    line_matrix: Transform,
+    fonts: HashMap<ObjectId, Rc<LoadedFont>>,
+    glyphs: HashMap<(ObjectId, u16), Option<Path>>,
+    xobjects_left: usize,
+    operations_left: usize,
+}
+
+impl<'a> Canvas<'a> {
+    fn new(doc: &'a PdfDocument, pixmap: Pixmap, device: Transform) -> Self {
+        Self {
+            doc,
+            pixmap,
+            device,
+            state: GraphicsState::new(),
+            stack: Vec::new(),
+            path: PathBuilder::new(),
+            current_point: (0.0, 0.0),
+            pending_clip: None,
+            text_matrix: Transform::identity(),
+            line_matrix: Transform::identity(),
+            fonts: HashMap::new(),
+            glyphs: HashMap::new(),
+            xobjects_left: MAX_PAGE_XOBJECTS,
+            operations_left: MAX_PAGE_OPERATIONS,
+        }
+    }
+
+    fn into_image(self) -> DynamicImage {
+        // The canvas starts opaque white, so premultiplied values equal straight RGB
+        let (width, height) = (self.pixmap.width(), self.pixmap.height());
+        let rgb: Vec<u8> = self
+            .pixmap
+            .data()
+            .chunks_exact(4)
+            .flat_map(|px| [px[0], px[1], px[2]])
+            .collect();
+        DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, rgb).expect("buffer matches canvas size"))
+    }
+
+    fn execute(&mut self, operations: &[lopdf::content::Operation], resources: Option<&'a Dictionary>, depth: usize) {
+        for op in operations {
+            if self.operations_left == 0 {
+                return;
+            }
+            self.operations_left -= 1;
+            if self.operations_left == 0 {
+                log::warn!("Page has more than {} content operators; the rest are skipped", MAX_PAGE_OPERATIONS);
+            }
+            let nums: Vec<f32> = op.operands.iter().filter_map(number).collect();
+            let num = |i: usize| nums.get(i).copied().unwrap_or(0.0);
+
+            match op.operator.as_str() {
+                // Graphics state
+                "q" => self.stack.push(self.state.clone()),
+                "Q" => {
+                    if let Some(state) = self.stack.pop() {
+                        self.state = state;
+                    }
+                }
+                "cm" if nums.len() == 6 => {
+                    let m = Transform::from_row(num(0), num(1), num(2), num(3), num(4), num(5));
+                    self.state.ctm = m.post_concat(self.state.ctm);
+                }
+                "w" => self.state.line_width = num(0),
+                "J" => self.state.line_cap = line_cap(num(0)),
+                "j" => self.state.line_join = line_join(num(0)),
+                "M" => self.state.miter_limit = num(0).max(1.0),
+                "d" => self.state.dash = dash_pattern(&op.operands),
+                "gs" => {
+                    if let Some(dict) = name_operand(op, 0).and_then(|n| self.resource(resources, b"ExtGState", n)) {
+                        self.apply_ext_gstate(dict);
+                    }
+                }
+
+                // Path construction
+                "m" => {
+                    self.path.move_to(num(0), num(1));
+                    self.current_point = (num(0), num(1));
+                }
+                "l" => {
+                    self.path.line_to(num(0), num(1));
+                    self.current_point = (num(0), num(1));
+                }
+                "c" => {
+                    self.path.cubic_to(num(0), num(1), num(2), num(3), num(4), num(5));
+                    self.current_point = (num(4), num(5));
+                }
+                "v" => {
+                    let (cx, cy) = self.current_point;
+                    self.path.cubic_to(cx, cy, num(0), num(1), num(2), num(3));
+                    self.current_point = (num(2), num(3));
+                }
+                "y" => {
+                    self.path.cubic_to(num(0), num(1), num(2), num(3), num(2), num(3));
+                    self.current_point = (num(2), num(3));
+                }
+                "h" => self.path.close(),
+                "re" => {
+                    let (x, y, w, h) = (num(0), num(1), num(2), num(3));
+                    self.path.move_to(x, y);
+                    self.path.line_to(x + w, y);
+                    self.path.line_to(x + w, y + h);
+                    self.path.line_to(x, y + h);
+                    self.path.close();
+                    self.current_point = (x, y);
+                }
+
+                // Path painting
+                "S" => self.paint_path(None, true, false),
+                "s" => {
+                    self.path.close();
+                    self.paint_path(None, true, false);
+                }
+                "f" | "F" => self.paint_path(Some(FillRule::Winding), false, false),
+                "f*" => self.paint_path(Some(FillRule::EvenOdd), false, false),
+                "B" => self.paint_path(Some(FillRule::Winding), true, false),
+                "B*" => self.paint_path(Some(FillRule::EvenOdd), true, false),
+                "b" => self.paint_path(Some(FillRule::Winding), true, true),
+                "b*" => self.paint_path(Some(FillRule::EvenOdd), true, true),
+                "n" => self.paint_path(None, false, false),
+                "W" => self.pending_clip = Some(FillRule::Winding),
+                "W*" => self.pending_clip = Some(FillRule::EvenOdd),
+
+                // Colour
+                "CS" | "cs" => {
+                    let space = name_operand(op, 0)
+                        .map(|n| self.color_space_by_name(resources, n))
+                        .unwrap_or(ColorSpace::Gray);
+                    let color = space.to_rgb(&space.initial_color());
+                    if op.operator == "CS" {
+                        self.state.stroke_space = space;
+                        self.state.stroke_color = color;
+                    } else {
+                        self.state.fill_space = space;
+                        self.state.fill_color = color;
+                    }
+                }
+                "SC" | "SCN" if !nums.is_empty() => {
+                    self.state.stroke_color = self.state.stroke_space.to_rgb(&nums);
+                }
+                "sc" | "scn" if !nums.is_empty() => {
+                    self.state.fill_color = self.state.fill_space.to_rgb(&nums);
+                }
+                "G" => self.set_color(true, ColorSpace::Gray, &nums),
+                "g" => self.set_color(false, ColorSpace::Gray, &nums),
+                "RG" => self.set_color(true, ColorSpace::Rgb, &nums),
+                "rg" => self.set_color(false, ColorSpace::Rgb, &nums),
+                "K" => self.set_color(true, ColorSpace::Cmyk, &nums),
+                "k" => self.set_color(false, ColorSpace::Cmyk, &nums),
+
+                // Text
+                "BT" => {
+                    self.text_matrix = Transform::identity();
+                    self.line_matrix = Transform::identity();
+                }
+                "ET" => {}
+                "Tf" => {
+                    self.state.text.font = name_operand(op, 0).and_then(|n| self.load_font(resources, n));
+                    self.state.text.size = num(0);
+                }
+                "Tc" => self.state.text.char_spacing = num(0),
+                "Tw" => self.state.text.word_spacing = num(0),
+                "Tz" => self.state.text.horizontal_scale = num(0) / 100.0,
+                "TL" => self.state.text.leading = num(0),
+                "Ts" => self.state.text.rise = num(0),
+                "Tr" => self.state.text.render_mode = num(0) as i64,
+                "Td" => self.next_line(num(0), num(1)),
+                "TD" => {
+                    self.state.text.leading = -num(1);
+                    self.next_line(num(0), num(1));
+                }
+                "Tm" if nums.len() == 6 => {
+                    self.line_matrix = Transform::from_row(num(0), num(1), num(2), num(3), num(4), num(5));
+                    self.text_matrix = self.line_matrix;
+                }
+                "T*" => self.next_line(0.0, -self.state.text.leading),
+                "Tj" => {
+                    if let Some(Object::String(bytes, _)) = op.operands.first() {
+                        self.show_text(bytes);
+                    }
+                }
+                "'" | "\"" => {
+                    if op.operator == "\"" {
+                        self.state.text.word_spacing = num(0);
+                        self.state.text.char_spacing = num(1);
+                    }
+                    self.next_line(0.0, -self.state.text.leading);
+                    if let Some(Object::String(bytes, _)) = op.operands.last() {
+                        self.show_text(bytes);
+                    }
+                }
+                "TJ" => {
+                    if let Some(Object::Array(items)) = op.operands.first() {
+                        for item in items {
+                            match item {
+                                Object::String(bytes, _) => self.show_text(bytes),
+                                other => {
+                                    if let Some(adjust) = number(other) {
+                                        let text = &self.state.text;
+                                        let tx = -adjust / 1000.0 * text.size * text.horizontal_scale;
+                                        self.text_matrix = self.text_matrix.pre_concat(Transform::from_translate(tx, 0.0));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                // XObjects
+                "Do" => {
+                    if let Some(name) = name_operand(op, 0) {
+                        self.draw_xobject(resources, name, depth);
+                    }
+                }
+                "sh" | "BI" | "ID" | "EI" => log::debug!("Skipping unsupported operator {}", op.operator),
+                _ => {}
+            }
+        }
+    }
+
+    // === PATHS ===
+
+    fn paint_path(&mut self, fill: Option<FillRule>, stroke: bool, close: bool) {
+        if close {
+            self.path.close();
+        }
+        let builder = std::mem::replace(&mut self.path, PathBuilder::new());
+        let clip_rule = self.pending_clip.take();
+        let Some(path) = builder.finish() else {
+            return;
+        };
+        let transform = self.state.ctm.post_concat(self.device);
+
+        if let Some(rule) = fill {
+            let paint = solid_paint(self.state.fill_color, self.state.fill_alpha);
+            self.pixmap.fill_path(&path, &paint, rule, transform, self.state.clip.as_deref());
+        }
+        if stroke {
+            self.stroke(&path, transform);
+        }
+        if let Some(rule) = clip_rule {
+            self.intersect_clip(&path, rule, transform);
+        }
+    }
+
+    fn stroke(&mut self, path: &Path, transform: Transform) {
+        let paint = solid_paint(self.state.stroke_color, self.state.stroke_alpha);
+        let stroke = Stroke {
+            width: self.state.line_width,
+            miter_limit: self.state.miter_limit,
+            line_cap: self.state.line_cap,
+            line_join: self.state.line_join,
+            dash: self
+                .state
+                .dash
+                .as_ref()
+                .and_then(|(array, phase)| StrokeDash::new(array.clone(), *phase)),
+        };
+        self.pixmap.stroke_path(path, &paint, &stroke, transform, self.state.clip.as_deref());
+    }
+
+    fn intersect_clip(&mut self, path: &Path, rule: FillRule, transform: Transform) {
+        let mask = match self.state.clip.as_deref() {
+            Some(existing) => {
+                let mut mask = existing.clone();
+                mask.intersect_path(path, rule, true, transform);
+                mask
+            }
+            None => {
+                let Some(mut mask) = Mask::new(self.pixmap.width(), self.pixmap.height()) else {
+                    return;
+                };
+                mask.fill_path(path, rule, true, transform);
+                mask
+            }
+        };
+        self.state.clip = Some(Rc::new(mask));
+    }
+
+    // === COLOUR AND STATE ===
+
+    fn set_color(&mut self, stroke: bool, space: ColorSpace, components: &[f32]) {
+        let color = space.to_rgb(components);
+        if stroke {
+            self.state.stroke_space = space;
+            self.state.stroke_color = color;
+        } else {
+            self.state.fill_space = space;
+            self.state.fill_color = color;
+        }
+    }
+
+    fn color_space_by_name(&self, resources: Option<&'a Dictionary>, name: &[u8]) -> ColorSpace {
+        match name {
+            b"DeviceGray" | b"G" | b"CalGray" => ColorSpace::Gray,
+            b"DeviceRGB" | b"RGB" | b"CalRGB" => ColorSpace::Rgb,
+            b"DeviceCMYK" | b"CMYK" => ColorSpace::Cmyk,
+            b"Pattern" => ColorSpace::Pattern,
+            _ => resources
+                .and_then(|r| r.get(b"ColorSpace").ok())
+                .and_then(|o| deref(self.doc, o).as_dict().ok())
+                .and_then(|d| d.get(name).ok())
+                .map(|o| parse_color_space(self.doc, o))
+                .unwrap_or(ColorSpace::Gray),
+        }
+    }
+
+    fn apply_ext_gstate(&mut self, dict: &Dictionary) {
+        for (key, value) in dict.iter() {
+            let value = deref(self.doc, value);
+            match key.as_slice() {
+                b"LW" => self.state.line_width = number(value).unwrap_or(self.state.line_width),
+                b"LC" => self.state.line_cap = line_cap(number(value).unwrap_or(0.0)),
+                b"LJ" => self.state.line_join = line_join(number(value).unwrap_or(0.0)),
+                b"ML" => self.state.miter_limit = number(value).unwrap_or(10.0).max(1.0),
+                b"CA" => self.state.stroke_alpha = number(value).unwrap_or(1.0).clamp(0.0, 1.0),
+                b"ca" => self.state.fill_alpha = number(value).unwrap_or(1.0).clamp(0.0, 1.0),
+                b"D" => {
+                    if let Ok(parts) = value.as_array() {
+                        self.state.dash = dash_pattern(parts);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn resource(&self, resources: Option<&'a Dictionary>, category: &[u8], name: &[u8]) -> Option<&'a Dictionary> {
+        let doc = self.doc;
+        let category = deref(doc, resources?.get(category).ok()?).as_dict().ok()?;
+        match deref(doc, category.get(name).ok()?) {
+            Object::Dictionary(dict) => Some(dict),
+            Object::Stream(stream) => Some(&stream.dict),
+            _ => None,
+        }
+    }
+
+    // === TEXT ===
+
+    fn next_line(&mut self, tx: f32, ty: f32) {
+        self.line_matrix = self.line_matrix.pre_concat(Transform::from_translate(tx, ty));
+        self.text_matrix = self.line_matrix;
+    }
+
+    fn load_font(&mut self, resources: Option<&'a Dictionary>, name: &[u8]) -> Option<Rc<LoadedFont>> {
+        let fonts = deref(self.doc, resources?.get(b"Font").ok()?).as_dict().ok()?;
+        let entry = fonts.get(name).ok()?;
+        let id = entry.as_reference().unwrap_or((0, 0));
+        if id != (0, 0) {
+            if let Some(font) = self.fonts.get(&id) {
+                return Some(font.clone());
+            }
+        }
+        let dict = deref(self.doc, entry).as_dict().ok()?;
+        let font = Rc::new(LoadedFont::load(self.doc, id, dict));
+        if id != (0, 0) {
+            self.fonts.insert(id, font.clone());
+        }
+        Some(font)
+    }
+
+    fn show_text(&mut self, bytes: &[u8]) {
+        let Some(font) = self.state.text.font.clone() else {
+            return;
+        };
+        let text = self.state.text.clone();
+        let visible = !matches!(text.render_mode, 3 | 7);
+        let program = if visible { font.program.as_ref().and_then(ParsedProgram::parse) } else { None };
+
+        let codes: Vec<u32> = if font.two_byte {
+            bytes.chunks(2).map(|c| c.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32)).collect()
+        } else {
+            bytes.iter().map(|b| *b as u32).collect()
+        };
+
+        for code in codes {
+            if let Some(program) = &program {
+                if let Some(gid) = font.glyph_id(program, code) {
+                    self.draw_glyph(&font, program, gid, &text);
+                }
+            }
+
+            let mut advance = font.width(code) / 1000.0 * text.size + text.char_spacing;
+            if code == 32 && !font.two_byte {
+                advance += text.word_spacing;
+            }
+            self.text_matrix = self
+                .text_matrix
+                .pre_concat(Transform::from_translate(advance * text.horizontal_scale, 0.0));
+        }
+    }
+
+    fn draw_glyph(&mut self, font: &LoadedFont, program: &ParsedProgram, gid: u16, text: &TextState) {
+        let key = (font.id, gid);
+        let Some(path) = self.glyphs.entry(key).or_insert_with(|| program.outline(gid)) else {
+            return;
+        };
+
+        let render = Transform::from_row(text.size * text.horizontal_scale, 0.0, 0.0, text.size, 0.0, text.rise)
+            .post_concat(self.text_matrix)
+            .post_concat(self.state.ctm)
+            .post_concat(self.device);
+        let transform = program.glyph_matrix().post_concat(render);
+
+        if matches!(text.render_mode, 0 | 2 | 4 | 6) {
+            let paint = solid_paint(self.state.fill_color, self.state.fill_alpha);
+            self.pixmap
+                .fill_path(path, &paint, FillRule::Winding, transform, self.state.clip.as_deref());
+        }
+        if matches!(text.render_mode, 1 | 2 | 5 | 6) {
+            let path = path.clone();
+            let line_width = self.state.line_width;
+            // Stroke widths are in user space, so undo the glyph scaling for the pen
+            let scale = program.glyph_matrix().sx * text.size;
+            if scale.abs() > f32::EPSILON {
+                self.state.line_width = line_width / scale;
+            }
+            self.stroke(&path, transform);
+            self.state.line_width = line_width;
+        }
+    }
+
+    // === XOBJECTS ===
+
+    fn draw_xobject(&mut self, resources: Option<&'a Dictionary>, name: &[u8], depth: usize) {
+        let Some(category) = resources
+            .and_then(|r| r.get(b"XObject").ok())
+            .and_then(|o| deref(self.doc, o).as_dict().ok())
+        else {
+            return;
+        };
+        let Some(Object::Stream(stream)) = category.get(name).ok().map(|o| deref(self.doc, o)) else {
+            return;
+        };
+        if self.xobjects_left == 0 {
+            return;
+        }
+        self.xobjects_left -= 1;
+        if self.xobjects_left == 0 {
+            log::warn!("Page draws more than {} XObjects; the rest are skipped", MAX_PAGE_XOBJECTS);
+        }
+
+        match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+            Ok(b"Image") => self.draw_image(stream),
+            Ok(b"Form") if depth < MAX_FORM_DEPTH => self.draw_form(stream, resources, depth),
+            _ => {}
+        }
+    }
+
+    fn draw_form(&mut self, stream: &'a Stream, parent_resources: Option<&'a Dictionary>, depth: usize) {
+        let content = match &stream.dict.get(b"Filter") {
+            Ok(_) => match stream.decompressed_content() {
+                Ok(content) => content,
+                Err(e) => {
+                    log::debug!("Skipping form XObject with undecodable content: {}", e);
+                    return;
+                }
+            },
+            Err(_) => stream.content.clone(),
+        };
+        let Ok(Content { operations }) = Content::decode(&content) else {
+            return;
+        };
+
+        let saved_stack_len = self.stack.len();
+        self.stack.push(self.state.clone());
+        if let Some(m) = stream.dict.get(b"Matrix").ok().and_then(|o| read_matrix(self.doc, o)) {
+            self.state.ctm = m.post_concat(self.state.ctm);
+        }
+        if let Some([x0, y0, x1, y1]) = stream.dict.get(b"BBox").ok().and_then(|o| read_rect(self.doc, o)) {
+            if let Some(rect) = tiny_skia::Rect::from_ltrb(x0, y0, x1, y1) {
+                let path = PathBuilder::from_rect(rect);
+                let transform = self.state.ctm.post_concat(self.device);
+                self.intersect_clip(&path, FillRule::Winding, transform);
+            }
+        }
+
+        let resources = stream
+            .dict
+            .get(b"Resources")
+            .ok()
+            .and_then(|o| deref(self.doc, o).as_dict().ok())
+            .or(parent_resources);
+        self.execute(&operations, resources, depth + 1);
+
+        self.stack.truncate(saved_stack_len + 1);
+        if let Some(state) = self.stack.pop() {
+            self.state = state;
+        }
+    }
+
+    fn draw_image(&mut self, stream: &Stream) {
+        let Some(image) = decode_image(self.doc, stream, self.state.fill_color) else {
+            return;
+        };
+        let (w, h) = (image.width() as f32, image.height() as f32);
+        // Images occupy the unit square with their first row at the top
+        let transform = Transform::from_row(1.0 / w, 0.0, 0.0, -1.0 / h, 0.0, 1.0)
+            .post_concat(self.state.ctm)
+            .post_concat(self.device);
+        let paint = PixmapPaint {
+            opacity: self.state.fill_alpha,
+            quality: FilterQuality::Bilinear,
+            ..PixmapPaint::default()
+        };
+        self.pixmap
+            .draw_pixmap(0, 0, image.as_ref(), &paint, transform, self.state.clip.as_deref());
+    }
+}
+
+// === FONTS ===
+
+enum FontProgram {
+    TrueType(Vec<u8>),
+    Cff(Vec<u8>),
+}
+
+enum ParsedProgram<'f> {
+    TrueType(Box<ttf_parser::Face<'f>>),
+    Cff(Box<ttf_parser::cff::Table<'f>>),
+}
+
+impl<'f> ParsedProgram<'f> {
+    fn parse(program: &'f FontProgram) -> Option<Self> {
+        match program {
+            FontProgram::TrueType(data) => ttf_parser::Face::parse(data, 0).ok().map(|face| ParsedProgram::TrueType(Box::new(face))),
+            FontProgram::Cff(data) => ttf_parser::cff::Table::parse(data).map(|table| ParsedProgram::Cff(Box::new(table))),
+        }
+    }
+
+    /// Mapping from glyph outline units to text space
+    fn glyph_matrix(&self) -> Transform {
+        match self {
+            ParsedProgram::TrueType(face) => {
+                let scale = 1.0 / face.units_per_em().max(1) as f32;
+                Transform::from_scale(scale, scale)
+            }
+            ParsedProgram::Cff(table) => {
+                let m = table.matrix();
+                Transform::from_row(m.sx, m.ky, m.kx, m.sy, m.tx, m.ty)
+            }
+        }
+    }
+
+    fn outline(&self, gid: u16) -> Option<Path> {
+        let mut builder = GlyphPath(PathBuilder::new());
+        let gid = ttf_parser::GlyphId(gid);
+        match self {
+            ParsedProgram::TrueType(face) => {
+                face.outline_glyph(gid, &mut builder)?;
+            }
+            ParsedProgram::Cff(table) => {
+                table.outline(gid, &mut builder).ok()?;
+            }
+        }
+        builder.0.finish()
+    }
+}
+
+struct GlyphPath(PathBuilder);
+
+impl ttf_parser::OutlineBuilder for GlyphPath {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.0.quad_to(x1, y1, x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.0.cubic_to(x1, y1, x2, y2, x, y);
+    }
+
+    fn close(&mut self) {
+        self.0.close();
+    }
+}
+
+enum CidToGid {
+    Identity,
+    Table(Vec<u8>),
+}
+
+struct LoadedFont {
+    id: ObjectId,
+    program: Option<FontProgram>,
+    two_byte: bool,
+    first_char: u32,
+    widths: Vec<f32>,
+    cid_widths: HashMap<u32, f32>,
+    default_width: f32,
+    differences: HashMap<u32, String>,
+    cid_to_gid: CidToGid,
+    symbolic: bool,
+}
+
+impl LoadedFont {
+    fn load(doc: &PdfDocument, id: ObjectId, dict: &Dictionary) -> Self {
+        let get = |d: &'_ Dictionary, key: &[u8]| d.get(key).ok().map(|o| deref(doc, o).clone());
+        let subtype = dict.get(b"Subtype").and_then(Object::as_name).unwrap_or(b"");
+        let two_byte = subtype == b"Type0";
+
+        let mut font = LoadedFont {
+            id,
+            program: None,
+            two_byte,
+            first_char: 0,
+            widths: Vec::new(),
+            cid_widths: HashMap::new(),
+            default_width: if two_byte { 1000.0 } else { 500.0 },
+            differences: HashMap::new(),
+            cid_to_gid: CidToGid::Identity,
+            symbolic: false,
+        };
+
+        let descendant = if two_byte {
+            get(dict, b"DescendantFonts")
+                .and_then(|o| o.as_array().ok().and_then(|a| a.first().cloned()))
+                .and_then(|o| deref(doc, &o).as_dict().ok().cloned())
+        } else {
+            None
+        };
+        let font_dict = descendant.as_ref().unwrap_or(dict);
+
+        if let Some(descriptor) = get(font_dict, b"FontDescriptor").and_then(|o| o.as_dict().ok().cloned()) {
+            font.program = load_font_program(doc, &descriptor);
+            let flags = descriptor.get(b"Flags").and_then(Object::as_i64).unwrap_or(0);
+            font.symbolic = flags & 4 != 0;
+            if !two_byte {
+                if let Some(missing) = descriptor.get(b"MissingWidth").ok().and_then(number) {
+                    font.default_width = missing;
+                }
+            }
+        }
+
+        if two_byte {
+            if let Some(dw) = font_dict.get(b"DW").ok().and_then(number) {
+                font.default_width = dw;
+            }
+            if let Some(Object::Array(w)) = get(font_dict, b"W") {
+                font.cid_widths = parse_cid_widths(doc, &w);
+            }
+            if let Some(Object::Stream(map)) = get(font_dict, b"CIDToGIDMap") {
+                let data = if map.dict.has(b"Filter") { map.decompressed_content().ok() } else { Some(map.content.clone()) };
+                if let Some(data) = data {
+                    font.cid_to_gid = CidToGid::Table(data);
+                }
+            }
+        } else {
+            font.first_char = dict.get(b"FirstChar").and_then(Object::as_i64).unwrap_or(0).max(0) as u32;
+            if let Some(Object::Array(widths)) = get(dict, b"Widths") {
+                font.widths = widths.iter().map(|w| number(deref(doc, w)).unwrap_or(0.0)).collect();
+            }
+            if let Some(Object::Dictionary(encoding)) = get(dict, b"Encoding") {
+                if let Ok(Object::Array(diffs)) = encoding.get(b"Differences").map(|o| deref(doc, o)) {
+                    let mut code = 0u32;
+                    for item in diffs {
+                        match item {
+                            Object::Integer(i) => code = (*i).max(0) as u32,
+                            Object::Name(name) => {
+                                font.differences.insert(code, String::from_utf8_lossy(name).into_owned());
+                                code += 1;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        if font.program.is_none() {
+            log::debug!(
+                "Font {} has no embedded program; its text will not be drawn",
+                String::from_utf8_lossy(dict.get(b"BaseFont").and_then(Object::as_name).unwrap_or(b"?"))
+            );
+        }
+        font
+    }
+
+    /// Glyph advance in thousandths of text space units
+    fn width(&self, code: u32) -> f32 {
+        if self.two_byte {
+            return self.cid_widths.get(&code).copied().unwrap_or(self.default_width);
+        }
+        code.checked_sub(self.first_char)
+            .and_then(|i| self.widths.get(i as usize))
+            .copied()
+            .unwrap_or(self.default_width)
+    }
+
+    fn glyph_id(&self, program: &ParsedProgram, code: u32) -> Option<u16> {
+        if self.two_byte {
+            return match program {
+                ParsedProgram::TrueType(_) => match &self.cid_to_gid {
+                    CidToGid::Identity => Some(code as u16),
+                    CidToGid::Table(map) => {
+                        let i = code as usize * 2;
+                        map.get(i..i + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+                    }
+                },
+                ParsedProgram::Cff(table) => {
+                    if table.glyph_cid(ttf_parser::GlyphId(0)).is_some() {
+                        (0..table.number_of_glyphs())
+                            .find(|gid| table.glyph_cid(ttf_parser::GlyphId(*gid)) == Some(code as u16))
+                    } else {
+                        Some(code as u16)
+                    }
+                }
+            };
+        }
+
+        let by_name = self.differences.get(&code);
+        match program {
+            ParsedProgram::TrueType(face) => {
+                if let Some(gid) = by_name.and_then(|n| face.glyph_index_by_name(n)) {
+                    return Some(gid.0);
+                }
+                let subtables = face.tables().cmap?.subtables;
+                let unicode = win_ansi_to_unicode(code);
+                let mut fallback = None;
+                for subtable in subtables {
+                    let gid = match (subtable.platform_id, subtable.encoding_id) {
+                        (ttf_parser::PlatformId::Windows, 0) => subtable
+                            .glyph_index(0xF000 + code)
+                            .or_else(|| subtable.glyph_index(code)),
+                        (ttf_parser::PlatformId::Macintosh, 0) => subtable.glyph_index(code),
+                        (ttf_parser::PlatformId::Windows, 1) | (ttf_parser::PlatformId::Unicode, _) => {
+                            subtable.glyph_index(unicode)
+                        }
+                        _ => None,
+                    };
+                    match gid {
+                        Some(gid) if gid.0 != 0 => return Some(gid.0),
+                        Some(gid) => fallback = Some(gid.0),
+                        None => {}
+                    }
+                }
+                fallback.or(if self.symbolic { Some(code as u16) } else { None })
+            }
+            ParsedProgram::Cff(table) => by_name
+                .and_then(|n| table.glyph_index_by_name(n))
+                .or_else(|| table.glyph_index(code as u8))
+                .map(|g| g.0),
+        }
+    }
+}
+
+fn load_font_program(doc: &PdfDocument, descriptor: &Dictionary) -> Option<FontProgram> {
+    for key in [b"FontFile2".as_slice(), b"FontFile3".as_slice(), b"FontFile".as_slice()] {
+        let Ok(Object::Stream(stream)) = descriptor.get(key).map(|o| deref(doc, o)) else {
+            continue;
+        };
+        let data = if stream.dict.has(b"Filter") {
+            stream.decompressed_content().ok()?
+        } else {
+            stream.content.clone()
+        };
+        return match key {
+            b"FontFile2" => Some(FontProgram::TrueType(data)),
+            b"FontFile3" => match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+                Ok(b"OpenType") => Some(FontProgram::TrueType(data)),
+                _ => Some(FontProgram::Cff(data)),
+            },
+            _ => {
+                log::debug!("Type 1 font programs are not supported");
+                None
+            }
+        };
+    }
+    None
+}
+
+fn parse_cid_widths(doc: &PdfDocument, w: &[Object]) -> HashMap<u32, f32> {
+    let mut widths = HashMap::new();
+    // Counts every assignment, so overlapping ranges cannot repeat the work without end
+    let mut entries_left = MAX_CID_WIDTHS;
+    let mut i = 0;
+    while i + 1 < w.len() && entries_left > 0 {
+        let Some(first) = number(deref(doc, &w[i])).map(|v| v as u32) else {
+            break;
+        };
+        match deref(doc, &w[i + 1]) {
+            Object::Array(list) => {
+                for (offset, width) in list.iter().take(entries_left).enumerate() {
+                    if let Some(width) = number(deref(doc, width)) {
+                        widths.insert(first.saturating_add(offset as u32), width);
+                    }
+                }
+                entries_left = entries_left.saturating_sub(list.len());
+                i += 2;
+            }
+            last => {
+                let (Some(last), Some(width)) = (number(last), w.get(i + 2).and_then(|o| number(deref(doc, o)))) else {
+                    break;
+                };
+                let last = (last as u32).min(first.saturating_add(entries_left as u32 - 1));
+                for cid in first..=last {
+                    widths.insert(cid, width);
+                }
+                entries_left = entries_left.saturating_sub((last.saturating_sub(first) as usize) + 1);
+                i += 3;
+            }
+        }
+    }
+    widths
+}
+
+/// Unicode code point for a WinAnsiEncoding byte
+fn win_ansi_to_unicode(code: u32) -> u32 {
+    const HIGH: [u32; 32] = [
+        0x20AC, 0, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160, 0x2039, 0x0152, 0, 0x017D, 0,
+        0, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014, 0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0, 0x017E,
+        0x0178,
+    ];
+    match code {
+        0x80..=0x9F => HIGH[(code - 0x80) as usize],
+        _ => code,
+    }
+}
+
+// === IMAGES ===
+
+/// Decode an image XObject to 8-bit pixels, ignoring any soft mask.
+/// Image masks yield `None`; single-channel gray images decode to `Luma8`.
+pub(crate) fn decode_image_xobject(doc: &PdfDocument, stream: &Stream) -> Option<DynamicImage> {
+    let dict = &stream.dict;
+    if dict.get(b"ImageMask").and_then(Object::as_bool).unwrap_or(false) {
+        return None;
+    }
+    let (width, height, rgba) = image_samples(doc, stream, [0, 0, 0])?;
+    let space = dict.get(b"ColorSpace").map(|o| parse_color_space(doc, o)).unwrap_or(ColorSpace::Gray);
+    if matches!(space, ColorSpace::Gray) {
+        let luma = rgba.chunks_exact(4).map(|px| px[0]).collect();
+        image::GrayImage::from_raw(width, height, luma).map(DynamicImage::ImageLuma8)
+    } else {
+        let rgb = rgba.chunks_exact(4).flat_map(|px| [px[0], px[1], px[2]]).collect();
+        RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+    }
+}
+
+/// Decode an image XObject into a premultiplied pixmap
+fn decode_image(doc: &PdfDocument, stream: &Stream, fill: [u8; 3]) -> Option<Pixmap> {
+    let dict = &stream.dict;
+    let (width, height, mut rgba) = image_samples(doc, stream, fill)?;
+
+    if let Ok(Object::Stream(smask)) = dict.get(b"SMask").map(|o| deref(doc, o)) {
+        if let Some(alpha) = decode_soft_mask(smask, width, height) {
+            for (px, a) in rgba.chunks_exact_mut(4).zip(alpha) {
+                px[3] = ((px[3] as u16 * a as u16) / 255) as u8;
+            }
+        }
+    }
+
+    for px in rgba.chunks_exact_mut(4) {
+        let a = px[3] as u16;
+        if a < 255 {
+            px[0] = ((px[0] as u16 * a) / 255) as u8;
+            px[1] = ((px[1] as u16 * a) / 255) as u8;
+            px[2] = ((px[2] as u16 * a) / 255) as u8;
+        }
+    }
+    Pixmap::from_vec(rgba, IntSize::from_wh(width, height)?)
+}
+
+/// Straight RGBA samples of an image XObject together with its dimensions
+fn image_samples(doc: &PdfDocument, stream: &Stream, fill: [u8; 3]) -> Option<(u32, u32, Vec<u8>)> {
+    let dict = &stream.dict;
+    let (width, height) = image_size(dict)?;
+    let is_mask = dict.get(b"ImageMask").and_then(Object::as_bool).unwrap_or(false);
+
+    let rgba = if let Some(jpeg) = dct_payload(stream) {
+        let decoded = image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg).ok()?;
+        if (decoded.width(), decoded.height()) != (width, height) {
+            return None;
+        }
+        decoded.to_rgba8().into_raw()
+    } else {
+        let data = decode_filters(stream)?;
+        let bpc = if is_mask { 1 } else { dict.get(b"BitsPerComponent").and_then(Object::as_i64).unwrap_or(8) as u32 };
+        let space = if is_mask {
+            ColorSpace::Gray
+        } else {
+            dict.get(b"ColorSpace").map(|o| parse_color_space(doc, o)).unwrap_or(ColorSpace::Gray)
+        };
+        let decode: Vec<f32> = dict
+            .get(b"Decode")
+            .and_then(Object::as_array)
+            .map(|a| a.iter().filter_map(number).collect())
+            .unwrap_or_default();
+        samples_to_rgba(&data, width, height, bpc, &space, &decode, is_mask.then_some(fill))?
+    };
+    Some((width, height, rgba))
+}
+
+/// `/Width` and `/Height` of an image, or `None` when they are missing or past `MAX_IMAGE_PIXELS`
+fn image_size(dict: &Dictionary) -> Option<(u32, u32)> {
+    let width = dict.get(b"Width").and_then(Object::as_i64).ok()?.max(1);
+    let height = dict.get(b"Height").and_then(Object::as_i64).ok()?.max(1);
+    if width.saturating_mul(height) > MAX_IMAGE_PIXELS {
+        log::warn!("Skipping a {}x{} image, over the {} pixel limit", width, height, MAX_IMAGE_PIXELS);
+        return None;
+    }
+    Some((width as u32, height as u32))
+}
+
+fn decode_soft_mask(smask: &Stream, width: u32, height: u32) -> Option<Vec<u8>> {
+    let (mw, mh) = image_size(&smask.dict)?;
+    let gray = if let Some(jpeg) = dct_payload(smask) {
+        image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg).ok()?.to_luma8()
+    } else {
+        let data = decode_filters(smask)?;
+        let bpc = smask.dict.get(b"BitsPerComponent").and_then(Object::as_i64).unwrap_or(8) as u32;
+        let rgba = samples_to_rgba(&data, mw, mh, bpc, &ColorSpace::Gray, &[], None)?;
+        image::GrayImage::from_raw(mw, mh, rgba.chunks_exact(4).map(|px| px[0]).collect())?
+    };
+    let gray = if gray.dimensions() != (width, height) {
+        image::imageops::resize(&gray, width, height, image::imageops::FilterType::Triangle)
+    } else {
+        gray
+    };
+    Some(gray.into_raw())
+}
+
+/// Return the JPEG bytes of a stream whose last filter is DCTDecode
+fn dct_payload(stream: &Stream) -> Option<Vec<u8>> {
+    let filters = stream.filters().ok()?;
+    if filters.last().map(String::as_str) != Some("DCTDecode") {
+        return None;
+    }
+    let mut data = stream.content.clone();
+    for filter in &filters[..filters.len() - 1] {
+        data = apply_filter(filter, &data, None)?;
+    }
+    Some(data)
+}
+
+/// Undo the non-image filters on a stream, returning raw sample data
+fn decode_filters(stream: &Stream) -> Option<Vec<u8>> {
+    let filters = stream.filters().unwrap_or_default();
+    let params = stream.dict.get(b"DecodeParms").ok().and_then(|p| match p {
+        Object::Dictionary(d) => Some(d),
+        Object::Array(a) => a.iter().find_map(|o| o.as_dict().ok()),
+        _ => None,
+    });
+    let mut data = stream.content.clone();
+    for filter in &filters {
+        data = apply_filter(filter, &data, params)?;
+    }
+    Some(data)
+}
+
+fn apply_filter(filter: &str, data: &[u8], params: Option<&Dictionary>) -> Option<Vec<u8>> {
+    match filter {
+        "FlateDecode" | "Fl" => {
+            let mut output = Vec::new();
+            let mut decoder = flate2::read::ZlibDecoder::new(data);
+            if decoder.read_to_end(&mut output).is_err() && output.is_empty() {
+                return None;
+            }
+            let predictor = params.and_then(|p| p.get(b"Predictor").and_then(Object::as_i64).ok()).unwrap_or(1);
+            if predictor >= 10 {
+                let param = |key: &[u8], default: i64| {
+                    params.and_then(|p| p.get(key).and_then(Object::as_i64).ok()).unwrap_or(default).max(1) as usize
+                };
+                let bits_per_pixel = param(b"Colors", 1) * param(b"BitsPerComponent", 8);
+                let row_bytes = (param(b"Columns", 1) * bits_per_pixel).div_ceil(8);
+                output = undo_png_predictor(&output, bits_per_pixel.div_ceil(8), row_bytes);
+            }
+            Some(output)
+        }
+        other => {
+            log::debug!("Unsupported image filter {}", other);
+            None
+        }
+    }
+}
+
+fn undo_png_predictor(data: &[u8], bpp: usize, row_bytes: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut previous = vec![0u8; row_bytes];
+    for chunk in data.chunks(row_bytes + 1) {
+        let (filter, row) = (chunk[0], &chunk[1..]);
+        let mut current = row.to_vec();
+        current.resize(row_bytes, 0);
+        for i in 0..row_bytes {
+            let left = if i >= bpp { current[i - bpp] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+            let predicted = match filter {
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => 0,
+            };
+            current[i] = current[i].wrapping_add(predicted);
+        }
+        output.extend_from_slice(&current);
+        previous = current;
+    }
+    output
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Expand packed samples into straight RGBA. `stencil` paints the given colour where a 1-bit mask is set.
+fn samples_to_rgba(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    bpc: u32,
+    space: &ColorSpace,
+    decode: &[f32],
+    stencil: Option<[u8; 3]>,
+) -> Option<Vec<u8>> {
+    if !matches!(bpc, 1 | 2 | 4 | 8 | 16) {
+        return None;
+    }
+    let components = space.components();
+    let row_bits = width as usize * components * bpc as usize;
+    let row_bytes = row_bits.div_ceil(8);
+    let data_len = row_bytes.checked_mul(height as usize)?;
+    if data.len() < data_len {
+        log::debug!("Image data truncated: {} < {}", data.len(), data_len);
+    }
+    let max = ((1u32 << bpc) - 1) as f32;
+    let indexed = matches!(space, ColorSpace::Indexed { .. });
+
+    let mut rgba = Vec::with_capacity((width as usize).checked_mul(height as usize)?.checked_mul(4)?);
+    let mut values = vec![0f32; components];
+    for y in 0..height as usize {
+        let row = data.get(y * row_bytes..).unwrap_or(&[]);
+        for x in 0..width as usize {
+            for (c, value) in values.iter_mut().enumerate() {
+                let sample_index = x * components + c;
+                let raw = read_sample(row, sample_index, bpc) as f32;
+                let (dmin, dmax) = match (decode.get(c * 2), decode.get(c * 2 + 1)) {
+                    (Some(a), Some(b)) => (*a, *b),
+                    _ if indexed => (0.0, max),
+                    _ => (0.0, 1.0),
+                };
+                *value = dmin + raw * (dmax - dmin) / max;
+            }
+
+            if let Some(color) = stencil {
+                // Sample 0 paints unless the Decode array inverts it
+                let paint = values[0] < 0.5;
+                rgba.extend_from_slice(&[color[0], color[1], color[2], if paint { 255 } else { 0 }]);
+            } else {
+                let rgb = space.to_rgb(&values);
+                rgba.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+            }
+        }
+    }
+    Some(rgba)
+}
+
+fn read_sample(row: &[u8], index: usize, bpc: u32) -> u32 {
+    match bpc {
+        8 => row.get(index).copied().unwrap_or(0) as u32,
+        16 => row
+            .get(index * 2..index * 2 + 2)
+            .map_or(0, |b| u16::from_be_bytes([b[0], b[1]]) as u32),
+        _ => {
+            let bit = index * bpc as usize;
+            let byte = row.get(bit / 8).copied().unwrap_or(0);
+            let shift = 8 - bpc as usize - bit % 8;
+            ((byte >> shift) as u32) & ((1 << bpc) - 1)
+        }
+    }
+}
+
+// === OBJECT HELPERS ===
+
+fn deref<'d>(doc: &'d PdfDocument, object: &'d Object) -> &'d Object {
+    doc.dereference(object).map(|(_, o)| o).unwrap_or(object)
+}
+
+fn number(object: &Object) -> Option<f32> {
+    match object {
+        Object::Integer(i) => Some(*i as f32),
+        Object::Real(r) => Some(*r),
+        _ => None,
+    }
+}
+
+fn name_operand(op: &lopdf::content::Operation, index: usize) -> Option<&[u8]> {
+    op.operands.get(index).and_then(|o| o.as_name().ok())
+}
+
+/// Width and height in points of a page's (possibly inherited) MediaBox
+pub(crate) fn page_size(doc: &PdfDocument, page_id: ObjectId) -> Option<(f32, f32)> {
+    let page = doc.get_dictionary(page_id).ok()?;
+    let [x0, y0, x1, y1] = inherited(doc, page, b"MediaBox").and_then(|o| read_rect(doc, o))?;
+    Some((x1 - x0, y1 - y0))
+}
+
+/// Longest side, in points, at which each image XObject is drawn by any page's content, following
+/// `cm` and form matrices. Images drawn only from annotations or patterns are absent.
+pub(crate) fn image_display_sizes(doc: &PdfDocument) -> HashMap<ObjectId, f32> {
+    let mut sizes = ImageSizes { doc, sizes: HashMap::new(), xobjects_left: 0, operations_left: 0 };
+    for page_id in doc.get_pages().into_values() {
+        let Ok(page) = doc.get_dictionary(page_id) else {
+            continue;
+        };
+        let Some(Content { operations }) = doc.get_page_content(page_id).ok().and_then(|c| Content::decode(&c).ok()) else {
+            continue;
+        };
+        let resources = inherited(doc, page, b"Resources").and_then(|o| deref(doc, o).as_dict().ok());
+        sizes.xobjects_left = MAX_PAGE_XOBJECTS;
+        sizes.operations_left = MAX_PAGE_OPERATIONS;
+        sizes.walk(&operations, resources, Transform::identity(), 0);
+    }
+    sizes.sizes
+}
+
+/// Content walk behind `image_display_sizes`, bounded like the renderer's
+struct ImageSizes<'a> {
+    doc: &'a PdfDocument,
+    sizes: HashMap<ObjectId, f32>,
+    xobjects_left: usize,
+    operations_left: usize,
+}
+
+impl<'a> ImageSizes<'a> {
+    fn walk(&mut self, operations: &[lopdf::content::Operation], resources: Option<&'a Dictionary>, mut ctm: Transform, depth: usize) {
+        let mut stack = Vec::new();
+        for op in operations {
+            if self.operations_left == 0 {
+                return;
+            }
+            self.operations_left -= 1;
+            match op.operator.as_str() {
+                "q" => stack.push(ctm),
+                "Q" => ctm = stack.pop().unwrap_or(ctm),
+                "cm" => {
+                    let m: Vec<f32> = op.operands.iter().filter_map(number).collect();
+                    if m.len() == 6 {
+                        ctm = Transform::from_row(m[0], m[1], m[2], m[3], m[4], m[5]).post_concat(ctm);
+                    }
+                }
+                "Do" if self.xobjects_left > 0 => {
+                    self.xobjects_left -= 1;
+                    let Some(id) = name_operand(op, 0).and_then(|name| {
+                        let category = resources?.get(b"XObject").ok().and_then(|o| deref(self.doc, o).as_dict().ok())?;
+                        category.get(name).and_then(Object::as_reference).ok()
+                    }) else {
+                        continue;
+                    };
+                    let Ok(Object::Stream(stream)) = self.doc.get_object(id) else {
+                        continue;
+                    };
+                    match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+                        Ok(b"Image") => {
+                            // The image fills the unit square, so the matrix columns are its sides
+                            let side = (ctm.sx.hypot(ctm.ky)).max(ctm.kx.hypot(ctm.sy));
+                            let size = self.sizes.entry(id).or_insert(0.0);
+                            *size = size.max(side);
+                        }
+                        Ok(b"Form") if depth < MAX_FORM_DEPTH => {
+                            // Unfiltered streams fail to decompress; their content is used as is
+                            let content = stream.decompressed_content().unwrap_or_else(|_| stream.content.clone());
+                            let Ok(Content { operations }) = Content::decode(&content) else {
+                                continue;
+                            };
+                            let matrix = stream.dict.get(b"Matrix").ok().and_then(|o| read_matrix(self.doc, o)).unwrap_or_default();
+                            let form_resources = stream
+                                .dict
+                                .get(b"Resources")
+                                .ok()
+                                .and_then(|o| deref(self.doc, o).as_dict().ok())
+                                .or(resources);
+                            self.walk(&operations, form_resources, matrix.post_concat(ctm), depth + 1);
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Look up a page attribute, walking up the page tree for inheritable keys
+fn inherited<'d>(doc: &'d PdfDocument, page: &'d Dictionary, key: &[u8]) -> Option<&'d Object> {
+    let mut node = page;
+    for _ in 0..32 {
+        if let Ok(value) = node.get(key) {
+            return Some(value);
+        }
+        node = node
+            .get(b"Parent")
+            .and_then(Object::as_reference)
+            .and_then(|id| doc.get_dictionary(id))
+            .ok()?;
+    }
+    None
+}
+
+fn read_rect(doc: &PdfDocument, object: &Object) -> Option<[f32; 4]> {
+    let values: Vec<f32> = deref(doc, object)
+        .as_array()
+        .ok()?
+        .iter()
+        .filter_map(|o| number(deref(doc, o)))
+        .collect();
+    if values.len() != 4 {
+        return None;
+    }
+    Some([
+        values[0].min(values[2]),
+        values[1].min(values[3]),
+        values[0].max(values[2]),
+        values[1].max(values[3]),
+    ])
+}
+
+fn intersect_rect(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    let r = [a[0].max(b[0]), a[1].max(b[1]), a[2].min(b[2]), a[3].min(b[3])];
+    if r[2] > r[0] && r[3] > r[1] {
+        r
+    } else {
+        b
+    }
+}
+
+fn read_matrix(doc: &PdfDocument, object: &Object) -> Option<Transform> {
+    let v: Vec<f32> = deref(doc, object).as_array().ok()?.iter().filter_map(number).collect();
+    (v.len() == 6).then(|| Transform::from_row(v[0], v[1], v[2], v[3], v[4], v[5]))
+}
+
+fn parse_color_space(doc: &PdfDocument, object: &Object) -> ColorSpace {
+    parse_nested_color_space(doc, object, 0)
+}
+
+fn parse_nested_color_space(doc: &PdfDocument, object: &Object, depth: usize) -> ColorSpace {
+    if depth > MAX_COLOR_SPACE_DEPTH {
+        log::warn!("Colour space nested more than {} levels deep; using DeviceGray", MAX_COLOR_SPACE_DEPTH);
+        return ColorSpace::Gray;
+    }
+    match deref(doc, object) {
+        Object::Name(name) => match name.as_slice() {
+            b"DeviceRGB" | b"CalRGB" | b"RGB" => ColorSpace::Rgb,
+            b"DeviceCMYK" | b"CMYK" => ColorSpace::Cmyk,
+            b"Pattern" => ColorSpace::Pattern,
+            _ => ColorSpace::Gray,
+        },
+        Object::Array(parts) => {
+            let family = parts.first().and_then(|o| o.as_name().ok()).unwrap_or(b"");
+            match family {
+                b"ICCBased" => {
+                    let n = parts
+                        .get(1)
+                        .and_then(|o| deref(doc, o).as_stream().ok())
+                        .and_then(|s| s.dict.get(b"N").and_then(Object::as_i64).ok())
+                        .unwrap_or(3);
+                    match n {
+                        1 => ColorSpace::Gray,
+                        4 => ColorSpace::Cmyk,
+                        _ => ColorSpace::Rgb,
+                    }
+                }
+                b"CalRGB" => ColorSpace::Rgb,
+                b"CalGray" => ColorSpace::Gray,
+                b"Lab" => ColorSpace::Lab,
+                b"Indexed" | b"I" => {
+                    let base = parts.get(1).map(|o| parse_nested_color_space(doc, o, depth + 1)).unwrap_or(ColorSpace::Rgb);
+                    let hival = parts.get(2).and_then(|o| o.as_i64().ok()).unwrap_or(255).clamp(0, 255) as usize;
+                    let lookup = match parts.get(3).map(|o| deref(doc, o)) {
+                        Some(Object::String(bytes, _)) => bytes.clone(),
+                        Some(Object::Stream(s)) => decode_filters(s).unwrap_or_default(),
+                        _ => Vec::new(),
+                    };
+                    ColorSpace::Indexed { base: Box::new(base), hival, lookup: Rc::new(lookup) }
+                }
+                b"Separation" => ColorSpace::Tint(1),
+                b"DeviceN" => {
+                    let n = parts.get(1).and_then(|o| deref(doc, o).as_array().ok()).map_or(1, |a| a.len().max(1));
+                    ColorSpace::Tint(n)
+                }
+                b"Pattern" => ColorSpace::Pattern,
+                _ => parts.get(1).map(|o| parse_nested_color_space(doc, o, depth + 1)).unwrap_or(ColorSpace::Gray),
+            }
+        }
+        _ => ColorSpace::Gray,
+    }
+}
+
+fn solid_paint(color: [u8; 3], alpha: f32) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(color[0], color[1], color[2], (alpha.clamp(0.0, 1.0) * 255.0).round() as u8);
+    paint.anti_alias = true;
+    paint
+}
+
+fn line_cap(value: f32) -> LineCap {
+    match value as i64 {
+        1 => LineCap::Round,
+        2 => LineCap::Square,
+        _ => LineCap::Butt,
+    }
+}
+
+fn line_join(value: f32) -> LineJoin {
+    match value as i64 {
+        1 => LineJoin::Round,
+        2 => LineJoin::Bevel,
+        _ => LineJoin::Miter,
+    }
+}
+
+fn dash_pattern(operands: &[Object]) -> Option<(Vec<f32>, f32)> {
+    let array: Vec<f32> = operands.first()?.as_array().ok()?.iter().filter_map(number).collect();
+    let phase = operands.get(1).and_then(number).unwrap_or(0.0);
+    // tiny-skia needs an even number of positive segments
+    if array.is_empty() || array.iter().all(|v| *v <= 0.0) {
+        return None;
+    }
+    let array = if array.len() % 2 == 1 { array.repeat(2) } else { array };
+    Some((array, phase))
+}
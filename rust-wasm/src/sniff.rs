@@ -0,0 +1,80 @@
+/// MIME type of DOCX documents
+const DOCX_MIME_TYPE: &str = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+
+/// How far into a file the PDF header may start; Acrobat accepts junk before it
+const PDF_HEADER_WINDOW: usize = 1024;
+
+/// Bytes inspected when deciding whether content is plain text
+const TEXT_SAMPLE: usize = 8192;
+
+/// Identify a file's real format from its leading bytes. Returns `None` for content that matches
+/// none of the formats the converter knows.
+pub fn detect_mime_type(content: &[u8]) -> Option<&'static str> {
+    let starts_with = |magic: &[u8]| content.starts_with(magic);
+    // Leading magic bytes win over the PDF header scan, which metadata text could satisfy
+    if starts_with(b"%PDF-") {
+        Some("application/pdf")
+    } else if starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if content.len() >= 12 && starts_with(b"RIFF") && &content[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if starts_with(b"II*\0") || starts_with(b"MM\0*") {
+        Some("image/tiff")
+    } else if starts_with(b"GIF87a") || starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if content.len() >= 14 && starts_with(b"BM") && content[6..10] == [0; 4] {
+        // The reserved header words rule out text that merely begins with "BM"
+        Some("image/bmp")
+    } else if starts_with(b"PK\x03\x04") {
+        // Word documents are ZIP packages whose main part is word/document.xml
+        Some(if contains(content, b"word/document.xml") { DOCX_MIME_TYPE } else { "application/zip" })
+    } else if starts_with(&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1]) {
+        // Legacy Office files share the OLE container; Word's has a "WordDocument" stream
+        let stream_name: Vec<u8> = "WordDocument".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        Some(if contains(content, &stream_name) { "application/msword" } else { "application/x-ole-storage" })
+    } else if content[..content.len().min(PDF_HEADER_WINDOW)].windows(5).any(|window| window == b"%PDF-") {
+        Some("application/pdf")
+    } else if is_text(content) {
+        Some("text/plain")
+    } else {
+        None
+    }
+}
+
+/// Normalize a client-declared MIME type: drop parameters and fold aliases onto the names
+/// `detect_mime_type` returns
+pub fn canonical_mime_type(declared: &str) -> String {
+    let essence = declared.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    match essence.as_str() {
+        "image/jpg" | "image/pjpeg" => "image/jpeg".to_string(),
+        "image/tif" => "image/tiff".to_string(),
+        "image/x-ms-bmp" | "image/x-bmp" => "image/bmp".to_string(),
+        "application/x-pdf" => "application/pdf".to_string(),
+        "application/x-zip-compressed" => "application/zip".to_string(),
+        mime if mime.starts_with("text/") => "text/plain".to_string(),
+        _ => essence,
+    }
+}
+
+/// Declared types that say nothing about the content, so a detected type is not a mismatch
+pub fn is_generic_mime_type(declared: &str) -> bool {
+    matches!(canonical_mime_type(declared).as_str(), "" | "application/octet-stream" | "binary/octet-stream")
+}
+
+/// UTF-8 without control characters other than whitespace, allowing for a character cut off at
+/// the end of the sample
+fn is_text(content: &[u8]) -> bool {
+    let sample = &content[..content.len().min(TEXT_SAMPLE)];
+    let sample = sample.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(sample);
+    let valid = match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(error) => error.error_len().is_none() && content.len() > TEXT_SAMPLE,
+    };
+    valid && !sample.is_empty() && sample.iter().all(|&byte| byte >= 0x20 || matches!(byte, b'\t' | b'\n' | b'\r' | 0x0C))
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
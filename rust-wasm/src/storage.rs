@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Where converted files wait until they are downloaded. Shared by every conversion running at
+/// the same time, so implementations handle their own locking.
+pub trait FileStore: Send + Sync {
+    fn insert(&self, file_id: String, content: Vec<u8>);
+
+    fn get(&self, file_id: &str) -> Option<Arc<Vec<u8>>>;
+
+    /// Remove every file, returning how many there were and their total size in bytes
+    fn clear(&self) -> (usize, u64);
+
+    /// Number of stored files and their total size in bytes
+    fn stats(&self) -> (usize, u64);
+}
+
+/// Files kept in memory for the life of the process. Downloads only take the read lock, and
+/// writers hold the lock just long enough to update the map.
+#[derive(Default)]
+pub struct MemoryStore {
+    files: RwLock<HashMap<String, Arc<Vec<u8>>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl FileStore for MemoryStore {
+    fn insert(&self, file_id: String, content: Vec<u8>) {
+        let mut files = self.files.write().unwrap_or_else(PoisonError::into_inner);
+        files.insert(file_id, Arc::new(content));
+    }
+
+    fn get(&self, file_id: &str) -> Option<Arc<Vec<u8>>> {
+        let files = self.files.read().unwrap_or_else(PoisonError::into_inner);
+        files.get(file_id).cloned()
+    }
+
+    fn clear(&self) -> (usize, u64) {
+        let removed = std::mem::take(&mut *self.files.write().unwrap_or_else(PoisonError::into_inner));
+        (removed.len(), total_size(&removed))
+    }
+
+    fn stats(&self) -> (usize, u64) {
+        let files = self.files.read().unwrap_or_else(PoisonError::into_inner);
+        (files.len(), total_size(&files))
+    }
+}
+
+fn total_size(files: &HashMap<String, Arc<Vec<u8>>>) -> u64 {
+    files.values().map(|content| content.len() as u64).sum()
+}
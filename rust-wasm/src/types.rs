@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 use thiserror::Error;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -7,6 +8,144 @@ pub struct ExamConfig {
     pub name: String,
     pub formats: Vec<String>,
     pub max_sizes: HashMap<String, u64>,
+    /// Smallest accepted file size per format, in bytes
+    #[serde(default)]
+    pub min_sizes: HashMap<String, u64>,
+    /// Required output dimensions per document type, such as "photo" or "signature"
+    #[serde(default)]
+    pub dimensions: HashMap<String, DimensionSpec>,
+    /// Smallest accepted pixel size per document type
+    #[serde(default)]
+    pub min_dimensions: HashMap<String, MinDimensions>,
+    /// DPI written into image metadata per document type
+    #[serde(default)]
+    pub dpi: HashMap<String, u16>,
+}
+
+/// Smallest pixel size an image output may have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MinDimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Byte and pixel bounds a single output has to meet
+#[derive(Debug, Clone, Copy)]
+pub struct SizeLimits {
+    pub min_size: u64,
+    pub max_size: u64,
+    pub min_dimensions: Option<MinDimensions>,
+}
+
+/// Upper bound on the pixels of an image output a request may ask for
+pub const MAX_OUTPUT_PIXELS: u64 = 40_000_000;
+
+/// Exact output size for an image; its aspect ratio is `width / height`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DimensionSpec {
+    pub width: f32,
+    pub height: f32,
+    #[serde(default)]
+    pub unit: DimensionUnit,
+    /// Resolution used to turn physical units into pixels
+    #[serde(default = "DimensionSpec::default_dpi")]
+    pub dpi: f32,
+    #[serde(default)]
+    pub fit: FitPolicy,
+    /// RGB fill for padding and transparent areas
+    #[serde(default = "DimensionSpec::default_background")]
+    pub background: [u8; 3],
+}
+
+impl DimensionSpec {
+    fn default_dpi() -> f32 {
+        300.0
+    }
+
+    fn default_background() -> [u8; 3] {
+        [255, 255, 255]
+    }
+
+    /// Spec measured in pixels, cropped to fill on a white background
+    pub fn pixels(width: u32, height: u32) -> Self {
+        Self {
+            width: width as f32,
+            height: height as f32,
+            unit: DimensionUnit::Px,
+            dpi: Self::default_dpi(),
+            fit: FitPolicy::default(),
+            background: Self::default_background(),
+        }
+    }
+
+    /// Spec measured in centimetres at the default DPI
+    pub fn centimeters(width: f32, height: f32) -> Self {
+        Self {
+            width,
+            height,
+            unit: DimensionUnit::Cm,
+            ..Self::pixels(0, 0)
+        }
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width / self.height
+    }
+
+    /// Target size in whole pixels
+    pub fn pixel_size(&self) -> (u32, u32) {
+        let to_pixels = |value: f32| ((value * self.pixels_per_unit()).round() as u32).max(1);
+        (to_pixels(self.width), to_pixels(self.height))
+    }
+
+    /// Reject sizes and resolutions that are not finite and positive, or that ask for more than
+    /// `MAX_OUTPUT_PIXELS`
+    pub fn validate(&self) -> Result<(), ConversionError> {
+        let invalid = |message: String| Err(ConversionError::InvalidContent { message });
+        for (name, value) in [("width", self.width), ("height", self.height), ("DPI", self.dpi)] {
+            if !(value.is_finite() && value > 0.0) {
+                return invalid(format!("Dimension {} must be a positive number, got {}", name, value));
+            }
+        }
+        let pixels_per_unit = self.pixels_per_unit() as f64;
+        let pixels = (self.width as f64 * pixels_per_unit).round().max(1.0) * (self.height as f64 * pixels_per_unit).round().max(1.0);
+        if pixels > MAX_OUTPUT_PIXELS as f64 {
+            return invalid(format!("Dimensions of {} pixels exceed the limit of {}", pixels, MAX_OUTPUT_PIXELS));
+        }
+        Ok(())
+    }
+
+    fn pixels_per_unit(&self) -> f32 {
+        match self.unit {
+            DimensionUnit::Px => 1.0,
+            DimensionUnit::Cm => self.dpi / 2.54,
+            DimensionUnit::Mm => self.dpi / 25.4,
+            DimensionUnit::In => self.dpi,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DimensionUnit {
+    #[default]
+    Px,
+    Cm,
+    Mm,
+    In,
+}
+
+/// How an image with a different aspect ratio is brought to the target size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FitPolicy {
+    /// Scale to cover the target and trim the overflow, keeping the centre
+    #[default]
+    Crop,
+    /// Scale to fit inside the target and fill the rest with the background colour
+    Pad,
+    /// Scale each axis independently, distorting the image
+    Stretch,
 }
 
 #[derive(Debug, Clone)]
@@ -36,12 +175,30 @@ pub enum ConversionError {
     
     #[error("File size {actual} exceeds limit {limit}")]
     SizeLimit { actual: u64, limit: u64 },
+
+    #[error("Upload form is too large: {message}")]
+    FormTooLarge { message: String },
     
     #[error("Invalid file content: {message}")]
     InvalidContent { message: String },
     
     #[error("Compression failed: {message}")]
     CompressionFailed { message: String },
+    
+    #[error("Minimum requirement not met: {message}")]
+    MinimumNotMet { message: String },
+
+    #[error("Conversion was cancelled")]
+    Cancelled,
+
+    #[error("Too many conversions are waiting; try again later")]
+    QueueFull,
+
+    #[error("Conversion workers are not running")]
+    WorkersStopped,
+
+    #[error("Conversion task failed: {message}")]
+    TaskFailed { message: String },
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -52,21 +209,386 @@ pub struct ConvertedFile {
     pub format: String,
     pub size: u64,
     pub compression_ratio: Option<f64>,
+    /// Optimization step that brought an existing PDF within its size limit
+    pub pdf_strategy: Option<PdfStrategy>,
+    /// JPEG quality chosen to fit the size limit
+    pub quality: Option<u8>,
+    /// Pixel dimensions of image outputs
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Metadata stripped from the upload before it was returned
+    pub metadata_removed: Vec<MetadataField>,
+    /// Problems that did not stop the conversion but may need the user's attention
+    pub warnings: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Kinds of embedded metadata removed by the privacy scrub
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataField {
+    Exif,
+    Gps,
+    Xmp,
+    Iptc,
+    IccProfile,
+    /// Comments and PNG text chunks
+    Text,
+    /// PDF document information dictionary (author, producer, dates)
+    DocumentInfo,
+}
+
+/// PDF optimization strategies, from least to most aggressive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PdfStrategy {
+    StreamCompression,
+    ObjectStreams,
+    MetadataRemoval,
+    ImageDownsampling,
+    Grayscale,
+}
+
+/// Converted bytes along with how they were fitted to the size limit
+#[derive(Debug, Clone, Default)]
+pub struct ConversionOutput {
+    pub content: Vec<u8>,
+    pub pdf_strategy: Option<PdfStrategy>,
+    pub quality: Option<u8>,
+    pub dimensions: Option<(u32, u32)>,
+    pub metadata_removed: Vec<MetadataField>,
+    pub warnings: Vec<String>,
+}
+
+impl From<Vec<u8>> for ConversionOutput {
+    fn from(content: Vec<u8>) -> Self {
+        Self {
+            content,
+            ..Self::default()
+        }
+    }
+}
+
+/// Encoded image together with the quality and size the encoder settled on
+#[derive(Debug, Clone)]
+pub struct EncodedImage {
+    pub data: Vec<u8>,
+    /// JPEG quality; `None` for lossless formats
+    pub quality: Option<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl From<EncodedImage> for ConversionOutput {
+    fn from(image: EncodedImage) -> Self {
+        Self {
+            content: image.data,
+            quality: image.quality,
+            dimensions: Some((image.width, image.height)),
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct FileData {
     pub name: String,
     pub content: String, // base64 encoded
     pub mime_type: String,
+    /// Settings for this file in place of the request's; merging stays request-wide
+    #[serde(default)]
+    pub options: Option<ConversionOptions>,
+}
+
+/// A step of a batch conversion; `file` is the position of the upload in the request
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    FileStarted { file: usize },
+    /// The upload was not converted at all
+    FileSkipped { file: usize, reason: String },
+    /// A stage of converting one file to one target format
+    Stage {
+        file: usize,
+        format: String,
+        #[serde(flatten)]
+        stage: ConversionStage,
+    },
+    /// Outputs of one target format, failed conversions included
+    FormatFinished { file: usize, format: String, results: Vec<ConvertedFile> },
+    FileFinished { file: usize },
+    MergeFinished { result: ConvertedFile },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum ConversionStage {
+    /// Image frames decoded from the upload
+    Decoded,
+    /// Scan flattening, clean-up, background or passport framing applied
+    Enhanced,
+    /// An encoding attempt of the size search, counted from 1 per file and format
+    Compressing { iteration: u32 },
+    /// An output was stored for download
+    Stored,
+}
+
+/// Called before each encoding attempt of a size search
+pub type AttemptHook = Arc<dyn Fn() + Send + Sync>;
+
+/// A file to convert whose content is already decoded, such as a multipart file part
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub name: String,
+    pub content: Vec<u8>,
+    pub mime_type: String,
+    pub options: Option<ConversionOptions>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ConvertRequest {
+    /// Base64 files of a JSON request; multipart requests send the files as separate parts
+    #[serde(default)]
     pub files: Vec<FileData>,
     pub exam_type: String,
     pub target_formats: Vec<String>,
     pub max_sizes: HashMap<String, u64>,
+    #[serde(default)]
+    pub min_sizes: HashMap<String, u64>,
+    #[serde(flatten)]
+    pub options: ConversionOptions,
+}
+
+/// Optional conversion settings shared by every file in a request
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ConversionOptions {
+    pub pages: PageSelection,
+    pub page_layout: PageLayout,
+    /// Combine all files, in order, into a single PDF
+    pub merge: bool,
+    /// Name for the merged PDF, without extension
+    pub merged_name: Option<String>,
+    /// Keep, drop or split pages of an uploaded PDF for PDF output
+    pub extract: Option<PageExtraction>,
+    /// Exact pixel size for JPEG and PNG outputs
+    pub dimensions: Option<DimensionSpec>,
+    /// Smallest pixel size for JPEG and PNG outputs
+    pub min_dimensions: Option<MinDimensions>,
+    /// DPI to record in JPEG and PNG metadata
+    pub dpi: Option<u16>,
+    /// Resolution PDF pages are rendered at for image output
+    pub render_dpi: Option<f32>,
+    /// Encode WebP outputs losslessly, sized like PNG, instead of lossy like JPEG
+    pub lossless_webp: bool,
+    /// Carry the source ICC colour profile over to JPEG and PNG outputs
+    pub keep_icc_profile: bool,
+    /// Detect the page in photographed documents and flatten it before encoding
+    pub scan: bool,
+    /// Clean up scanned pages before encoding
+    pub enhance: Option<Enhancement>,
+    /// Treat image uploads as signatures for JPEG and PNG output
+    pub signature: Option<SignatureCleanup>,
+    /// Frame image uploads as passport photos around the detected face
+    pub passport_photo: Option<PassportPhoto>,
+    /// Replace the plain backdrop behind photo uploads with a solid colour
+    pub background: Option<BackgroundReplacement>,
+}
+
+/// Background replacement for candidate photos
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct BackgroundReplacement {
+    /// RGB colour painted behind the subject
+    pub color: [u8; 3],
+    /// RGB distance from the border colour still treated as backdrop
+    pub tolerance: f32,
+}
+
+impl Default for BackgroundReplacement {
+    fn default() -> Self {
+        Self { color: [255, 255, 255], tolerance: 24.0 }
+    }
+}
+
+/// Passport photo framing around the detected face
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct PassportPhoto {
+    /// Fraction of the frame height taken by the face, hairline to chin
+    pub face_height: f32,
+}
+
+impl Default for PassportPhoto {
+    fn default() -> Self {
+        Self { face_height: 0.55 }
+    }
+}
+
+/// Signature clean-up: the ink is isolated from the paper, darkened and cropped
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct SignatureCleanup {
+    /// Make the paper transparent instead of white (PNG output only)
+    pub transparent: bool,
+    /// Margin kept around the ink, as a fraction of the ink's larger side
+    pub padding: f32,
+    /// How far strokes are darkened towards black, from 0 (unchanged) to 1 (solid black)
+    pub darken: f32,
+}
+
+impl Default for SignatureCleanup {
+    fn default() -> Self {
+        Self {
+            transparent: false,
+            padding: 0.08,
+            darken: 0.5,
+        }
+    }
+}
+
+/// Scan clean-up: lighting is flattened, text straightened and the paper whitened
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Enhancement {
+    /// Largest text tilt, in degrees, that is straightened; 0 disables deskewing
+    pub max_skew_degrees: f32,
+    /// Reduce the page to pure black and white
+    pub binarize: bool,
+}
+
+impl Default for Enhancement {
+    fn default() -> Self {
+        Self {
+            max_skew_degrees: 5.0,
+            binarize: false,
+        }
+    }
+}
+
+impl ConversionOptions {
+    /// DPI for image metadata: the explicit setting, else the resolution of a physical dimension spec
+    pub fn output_dpi(&self) -> Option<u16> {
+        self.dpi.or_else(|| {
+            self.dimensions
+                .as_ref()
+                .filter(|spec| spec.unit != DimensionUnit::Px)
+                .map(|spec| spec.dpi.round().clamp(1.0, u16::MAX as f32) as u16)
+        })
+    }
+
+    /// The requested output dimensions, if any, rejected when they are not a usable size
+    pub fn dimensions(&self) -> Result<Option<&DimensionSpec>, ConversionError> {
+        match &self.dimensions {
+            Some(spec) => spec.validate().map(|()| Some(spec)),
+            None => Ok(None),
+        }
+    }
+
+    /// The requested PDF render resolution, if any, rejected when outside the supported range
+    pub fn render_dpi(&self) -> Result<Option<f32>, ConversionError> {
+        use crate::pdf_renderer::{MAX_RENDER_DPI, MIN_RENDER_DPI};
+        match self.render_dpi {
+            Some(dpi) if !(MIN_RENDER_DPI..=MAX_RENDER_DPI).contains(&dpi) => Err(ConversionError::InvalidContent {
+                message: format!("Render DPI {} is outside {}..={}", dpi, MIN_RENDER_DPI, MAX_RENDER_DPI),
+            }),
+            dpi => Ok(dpi),
+        }
+    }
+}
+
+/// Which PDF pages to rasterize; page numbers are 1-based
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageSelection {
+    Single(u32),
+    Range { start: u32, end: u32 },
+    All,
+}
+
+impl Default for PageSelection {
+    fn default() -> Self {
+        PageSelection::Single(1)
+    }
+}
+
+impl PageSelection {
+    /// Expand the selection into concrete page numbers for a document with `page_count` pages
+    pub fn resolve(&self, page_count: u32) -> Result<Vec<u32>, ConversionError> {
+        let (start, end) = match *self {
+            PageSelection::Single(page) => (page, page),
+            PageSelection::Range { start, end } => (start, end),
+            PageSelection::All => (1, page_count),
+        };
+
+        if start == 0 || start > end || end > page_count {
+            return Err(ConversionError::InvalidContent {
+                message: format!("Page selection {:?} is out of range for a {}-page PDF", self, page_count),
+            });
+        }
+        Ok((start..=end).collect())
+    }
+}
+
+/// Page extraction applied to an uploaded PDF; page numbers are 1-based
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageExtraction {
+    /// Keep only the selected pages
+    Keep(Vec<PageSelection>),
+    /// Remove the selected pages
+    Drop(Vec<PageSelection>),
+    /// One PDF per selection; an empty list splits into single pages
+    Split(Vec<PageSelection>),
+}
+
+impl PageExtraction {
+    /// Page numbers for each output PDF of a document with `page_count` pages
+    pub fn page_groups(&self, page_count: u32) -> Result<Vec<Vec<u32>>, ConversionError> {
+        let selected = |selections: &[PageSelection]| -> Result<Vec<u32>, ConversionError> {
+            let mut pages = Vec::new();
+            for selection in selections {
+                pages.extend(selection.resolve(page_count)?);
+            }
+            pages.sort_unstable();
+            pages.dedup();
+            Ok(pages)
+        };
+
+        let groups = match self {
+            PageExtraction::Keep(selections) => vec![selected(selections)?],
+            PageExtraction::Drop(selections) => {
+                let dropped = selected(selections)?;
+                vec![(1..=page_count).filter(|page| !dropped.contains(page)).collect()]
+            }
+            PageExtraction::Split(selections) if selections.is_empty() => {
+                (1..=page_count).map(|page| vec![page]).collect()
+            }
+            PageExtraction::Split(selections) => selections
+                .iter()
+                .map(|selection| selection.resolve(page_count))
+                .collect::<Result<_, _>>()?,
+        };
+
+        if groups.iter().any(Vec::is_empty) {
+            return Err(ConversionError::InvalidContent {
+                message: format!("Page extraction {:?} leaves no pages of a {}-page PDF", self, page_count),
+            });
+        }
+        Ok(groups)
+    }
+}
+
+/// How rendered PDF pages are arranged in the output images
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageLayout {
+    /// One image per page
+    #[default]
+    Separate,
+    /// All pages stacked top to bottom in one image
+    Stacked,
+    /// Pages placed left to right, top to bottom in a grid
+    Grid { columns: u32 },
 }
 
 #[derive(Debug, Serialize)]
@@ -79,16 +601,16 @@ pub struct ConvertResponse {
 #[derive(Debug, Clone)]
 pub struct CompressionSettings {
     pub quality: u8,        // 1-100 for JPEG
-    pub png_compression: u8, // 0-9 for PNG
-    pub max_iterations: u32, // Maximum compression attempts
+    pub dpi: Option<u16>,    // Density written to JPEG/PNG metadata
+    pub lossless_webp: bool, // Lossless instead of lossy WebP
 }
 
 impl Default for CompressionSettings {
     fn default() -> Self {
         Self {
             quality: 85,
-            png_compression: 6,
-            max_iterations: 5,
+            dpi: None,
+            lossless_webp: false,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,132 @@
+use crate::types::*;
+use actix_multipart::{Field, Multipart};
+use futures_util::StreamExt;
+
+/// Largest file part accepted in a multipart upload
+pub const MAX_FILE_PART_SIZE: usize = 10 * 1024 * 1024;
+
+/// Largest multipart upload, counting the bodies of all its parts
+pub const MAX_FORM_SIZE: usize = 50 * 1024 * 1024;
+
+/// Most parts (files, settings and options) a multipart upload may have
+pub const MAX_FORM_PARTS: usize = 64;
+
+/// Largest JSON settings part (`request` or `options`)
+const MAX_SETTINGS_PART_SIZE: usize = 64 * 1024;
+
+/// Bounds on what one multipart form may make the server buffer
+#[derive(Debug, Clone, Copy)]
+pub struct FormLimits {
+    pub max_file_size: usize,
+    pub max_form_size: usize,
+    pub max_parts: usize,
+}
+
+impl Default for FormLimits {
+    fn default() -> Self {
+        Self {
+            max_file_size: MAX_FILE_PART_SIZE,
+            max_form_size: MAX_FORM_SIZE,
+            max_parts: MAX_FORM_PARTS,
+        }
+    }
+}
+
+/// Read a multipart `/convert` form. The `request` part holds the JSON settings of a
+/// `ConvertRequest` without `files`; every part with a file name is a file, and an `options` part
+/// holds `ConversionOptions` for the file part that follows it. Parts are read chunk by chunk and
+/// the form is rejected as soon as it passes any of `limits`.
+pub async fn read_convert_form(mut form: Multipart, limits: FormLimits) -> Result<(ConvertRequest, Vec<Upload>), ConversionError> {
+    let mut request: Option<ConvertRequest> = None;
+    let mut uploads = Vec::new();
+    let mut next_options: Option<ConversionOptions> = None;
+    let mut parts = 0;
+    let mut form_size = FormSize { read: 0, limit: limits.max_form_size };
+
+    while let Some(field) = form.next().await {
+        let mut field = field.map_err(form_error)?;
+        parts += 1;
+        if parts > limits.max_parts {
+            return Err(ConversionError::FormTooLarge {
+                message: format!("more than {} parts", limits.max_parts),
+            });
+        }
+        let file_name = field.content_disposition().and_then(|disposition| disposition.get_filename()).map(str::to_string);
+        let field_name = field.name().unwrap_or_default().to_string();
+
+        match (file_name, field_name.as_str()) {
+            (Some(name), _) => {
+                let mime_type = field.content_type().map_or_else(|| "application/octet-stream".to_string(), |mime| mime.essence_str().to_string());
+                let content = read_part(&mut field, limits.max_file_size, &mut form_size).await?;
+                log::info!("Received file part {} ({} bytes, {})", name, content.len(), mime_type);
+                uploads.push(Upload { name, content, mime_type, options: next_options.take() });
+            }
+            (None, "request") => {
+                let settings: ConvertRequest = parse_json(&read_part(&mut field, MAX_SETTINGS_PART_SIZE, &mut form_size).await?, "request")?;
+                if !settings.files.is_empty() {
+                    return Err(ConversionError::InvalidContent {
+                        message: "Multipart requests send files as file parts, not in the request field".to_string(),
+                    });
+                }
+                request = Some(settings);
+            }
+            (None, "options") => {
+                next_options = Some(parse_json(&read_part(&mut field, MAX_SETTINGS_PART_SIZE, &mut form_size).await?, "options")?);
+            }
+            (None, other) => {
+                return Err(ConversionError::InvalidContent {
+                    message: format!("Unexpected form field {:?}", other),
+                });
+            }
+        }
+    }
+
+    if next_options.is_some() {
+        log::warn!("Ignoring an options part that no file part followed");
+    }
+    let request = request.ok_or_else(|| ConversionError::InvalidContent {
+        message: "Multipart request is missing its request field".to_string(),
+    })?;
+    Ok((request, uploads))
+}
+
+/// Bytes read so far across every part of a form
+struct FormSize {
+    read: usize,
+    limit: usize,
+}
+
+/// Collect a part's body, failing once it grows past `limit` bytes or the form as a whole grows
+/// past its own limit
+async fn read_part(field: &mut Field, limit: usize, form_size: &mut FormSize) -> Result<Vec<u8>, ConversionError> {
+    let mut content = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(form_error)?;
+        if content.len() + chunk.len() > limit {
+            return Err(ConversionError::SizeLimit {
+                actual: (content.len() + chunk.len()) as u64,
+                limit: limit as u64,
+            });
+        }
+        form_size.read += chunk.len();
+        if form_size.read > form_size.limit {
+            return Err(ConversionError::FormTooLarge {
+                message: format!("more than {} bytes", form_size.limit),
+            });
+        }
+        content.extend_from_slice(&chunk);
+    }
+    Ok(content)
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(content: &[u8], field: &str) -> Result<T, ConversionError> {
+    serde_json::from_slice(content).map_err(|e| ConversionError::InvalidContent {
+        message: format!("Invalid {} field: {}", field, e),
+    })
+}
+
+fn form_error(error: actix_multipart::MultipartError) -> ConversionError {
+    ConversionError::InvalidContent {
+        message: format!("Malformed multipart body: {}", error),
+    }
+}
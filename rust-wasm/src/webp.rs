@@ -0,0 +1,844 @@
+use crate::types::ConversionError;
+use image::codecs::webp::WebPEncoder;
+use image::{ColorType, DynamicImage, GrayImage, RgbaImage};
+
+/// Largest width or height a WebP image can have
+const MAX_SIDE: u32 = 16383;
+
+/// Encode an image as WebP: lossless when `quality` is `None`, otherwise a lossy VP8 frame at
+/// `quality` (0-100). Transparency is kept either way.
+pub fn encode(img: &DynamicImage, quality: Option<u8>) -> Result<Vec<u8>, ConversionError> {
+    if img.width() > MAX_SIDE || img.height() > MAX_SIDE {
+        return Err(ConversionError::CompressionFailed {
+            message: format!("{}x{} is larger than WebP allows ({} pixels per side)", img.width(), img.height(), MAX_SIDE),
+        });
+    }
+
+    match quality {
+        None => {
+            let mut output = Vec::new();
+            if img.color().has_alpha() {
+                let rgba = img.to_rgba8();
+                WebPEncoder::new_lossless(&mut output).encode(&rgba, rgba.width(), rgba.height(), ColorType::Rgba8)?;
+            } else {
+                let rgb = img.to_rgb8();
+                WebPEncoder::new_lossless(&mut output).encode(&rgb, rgb.width(), rgb.height(), ColorType::Rgb8)?;
+            }
+            Ok(output)
+        }
+        Some(quality) => encode_lossy(img, quality),
+    }
+}
+
+/// The lossy encoder in `image` needs libwebp, so VP8 key frames are written here: 16x16 luma
+/// and 8x8 chroma intra prediction, the VP8 integer transforms and the default token
+/// probabilities, without loop filtering
+fn encode_lossy(img: &DynamicImage, quality: u8) -> Result<Vec<u8>, ConversionError> {
+    let rgba = img.to_rgba8();
+    let frame = Vp8Frame::new(&rgba, quantizer_index(quality)).encode();
+
+    let alpha = img.color().has_alpha() && rgba.pixels().any(|pixel| pixel.0[3] < 255);
+    let mut chunks = Vec::new();
+    if alpha {
+        let mut flags = [0u8; 10];
+        flags[0] = 0x10; // alpha
+        flags[4..7].copy_from_slice(&(rgba.width() - 1).to_le_bytes()[..3]);
+        flags[7..10].copy_from_slice(&(rgba.height() - 1).to_le_bytes()[..3]);
+        riff_chunk(&mut chunks, b"VP8X", &flags);
+        riff_chunk(&mut chunks, b"ALPH", &alpha_chunk(&rgba)?);
+    }
+    riff_chunk(&mut chunks, b"VP8 ", &frame);
+
+    let mut output = Vec::with_capacity(chunks.len() + 12);
+    output.extend_from_slice(b"RIFF");
+    output.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+    output.extend_from_slice(b"WEBP");
+    output.extend_from_slice(&chunks);
+    Ok(output)
+}
+
+/// Map 0-100 quality onto the VP8 quantizer index, where 0 is the finest step
+fn quantizer_index(quality: u8) -> usize {
+    (100 - quality.min(100) as usize) * 127 / 100
+}
+
+fn riff_chunk(output: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(kind);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+/// ALPH payload: the alpha plane as a header-less lossless stream carried in the green channel
+fn alpha_chunk(rgba: &RgbaImage) -> Result<Vec<u8>, ConversionError> {
+    let alpha = GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| image::Luma([rgba.get_pixel(x, y).0[3]]));
+    let green = DynamicImage::ImageLuma8(alpha).to_rgb8();
+    let mut lossless = Vec::new();
+    WebPEncoder::new_lossless(&mut lossless).encode(&green, green.width(), green.height(), ColorType::Rgb8)?;
+
+    // RIFF header (12 bytes) and VP8L chunk header (8), then the signature and size header (5)
+    const STREAM_START: usize = 12 + 8 + 5;
+    let stream_length = u32::from_le_bytes([lossless[16], lossless[17], lossless[18], lossless[19]]) as usize - 5;
+    let mut chunk = Vec::with_capacity(stream_length + 1);
+    chunk.push(1); // no filtering or pre-processing, lossless compression
+    chunk.extend_from_slice(&lossless[STREAM_START..STREAM_START + stream_length]);
+    Ok(chunk)
+}
+
+// === VP8 KEY FRAME ===
+
+const DC_PRED: i8 = 0;
+const V_PRED: i8 = 1;
+const H_PRED: i8 = 2;
+const TM_PRED: i8 = 3;
+
+/// Token probability planes: luma AC after Y2, Y2, chroma
+const PLANE_Y_AFTER_Y2: usize = 0;
+const PLANE_Y2: usize = 1;
+const PLANE_CHROMA: usize = 2;
+
+const DCT_EOB: i8 = 11;
+
+/// Per-macroblock coefficient-presence context: Y2, four luma columns or rows, two U, two V
+type Context = [bool; 9];
+
+struct Quantizer {
+    y: (i32, i32),
+    y2: (i32, i32),
+    uv: (i32, i32),
+}
+
+impl Quantizer {
+    /// Step sizes as the decoder derives them from the index with no deltas
+    fn new(index: usize) -> Self {
+        let (dc, ac) = (DC_QUANT[index] as i32, AC_QUANT[index] as i32);
+        Self {
+            y: (dc, ac),
+            y2: (dc * 2, (ac * 155 / 100).max(8)),
+            uv: (dc.min(132), ac),
+        }
+    }
+}
+
+/// Quantized coefficients of a macroblock in zigzag order: Y2, 16 luma, 4 U and 4 V blocks
+struct Macroblock {
+    luma_mode: i8,
+    chroma_mode: i8,
+    blocks: [[i32; 16]; 25],
+}
+
+impl Macroblock {
+    fn is_empty(&self) -> bool {
+        self.blocks.iter().all(|block| block.iter().all(|&level| level == 0))
+    }
+}
+
+/// A YUV 4:2:0 picture padded to whole macroblocks, with the decoder-side reconstruction that
+/// later macroblocks are predicted from
+struct Vp8Frame {
+    width: u32,
+    height: u32,
+    mb_width: usize,
+    mb_height: usize,
+    quantizer_index: usize,
+    quantizer: Quantizer,
+    source: [Vec<u8>; 3],
+    reconstructed: [Vec<u8>; 3],
+}
+
+impl Vp8Frame {
+    fn new(rgba: &RgbaImage, quantizer_index: usize) -> Self {
+        let (width, height) = rgba.dimensions();
+        let (mb_width, mb_height) = (width.div_ceil(16) as usize, height.div_ceil(16) as usize);
+        let (luma_stride, chroma_stride) = (mb_width * 16, mb_width * 8);
+
+        // BT.601 studio swing, the conversion VP8 decoders invert; edges are replicated into
+        // the padding so it costs nothing to code
+        let pixel = |x: usize, y: usize| {
+            let pixel = rgba.get_pixel((x as u32).min(width - 1), (y as u32).min(height - 1)).0;
+            [pixel[0] as i32, pixel[1] as i32, pixel[2] as i32]
+        };
+        let mut luma = vec![0u8; luma_stride * mb_height * 16];
+        for y in 0..mb_height * 16 {
+            for x in 0..luma_stride {
+                let [r, g, b] = pixel(x, y);
+                luma[y * luma_stride + x] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            }
+        }
+        let mut u = vec![0u8; chroma_stride * mb_height * 8];
+        let mut v = vec![0u8; chroma_stride * mb_height * 8];
+        for y in 0..mb_height * 8 {
+            for x in 0..chroma_stride {
+                let mut sum = [0; 3];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let rgb = pixel(x * 2 + dx, y * 2 + dy);
+                    for (total, value) in sum.iter_mut().zip(rgb) {
+                        *total += value;
+                    }
+                }
+                let [r, g, b] = sum.map(|total| (total + 2) / 4);
+                u[y * chroma_stride + x] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128).clamp(0, 255) as u8;
+                v[y * chroma_stride + x] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128).clamp(0, 255) as u8;
+            }
+        }
+
+        let reconstructed = [vec![0; luma.len()], vec![0; u.len()], vec![0; v.len()]];
+        Self {
+            width,
+            height,
+            mb_width,
+            mb_height,
+            quantizer_index,
+            quantizer: Quantizer::new(quantizer_index),
+            source: [luma, u, v],
+            reconstructed,
+        }
+    }
+
+    /// The VP8 bitstream: frame tag, key frame header and both partitions
+    fn encode(mut self) -> Vec<u8> {
+        let mut macroblocks = Vec::with_capacity(self.mb_width * self.mb_height);
+        for mb_y in 0..self.mb_height {
+            for mb_x in 0..self.mb_width {
+                macroblocks.push(self.encode_macroblock(mb_x, mb_y));
+            }
+        }
+
+        let skipped = macroblocks.iter().filter(|mb| mb.is_empty()).count();
+        let prob_skip_false = ((macroblocks.len() - skipped) * 256 / macroblocks.len()).clamp(1, 255) as u8;
+
+        let mut header = BoolWriter::new();
+        header.write_literal(0, 1); // colour space
+        header.write_literal(0, 1); // clamping required
+        // Every macroblock stays in segment 0, whose quantizer is given as an absolute value;
+        // some decoders only honour the frame quantizer through segment data
+        header.write_literal(1, 1); // segmentation enabled
+        header.write_literal(0, 1); // no segment map
+        header.write_literal(1, 1); // segment data follows
+        header.write_literal(1, 1); // absolute values
+        for _ in 0..4 {
+            header.write_literal(1, 1);
+            header.write_literal(self.quantizer_index as u32, 7);
+            header.write_literal(0, 1); // sign
+        }
+        for _ in 0..4 {
+            header.write_literal(0, 1); // no loop filter level
+        }
+        header.write_literal(0, 1); // normal loop filter
+        header.write_literal(0, 6); // loop filter level: off
+        header.write_literal(0, 3); // sharpness
+        header.write_literal(0, 1); // no loop filter deltas
+        header.write_literal(0, 2); // one token partition
+        header.write_literal(self.quantizer_index as u32, 7);
+        for _ in 0..5 {
+            header.write_literal(0, 1); // no quantizer deltas
+        }
+        header.write_literal(0, 1); // refresh entropy probabilities
+        for update in COEFF_UPDATE_PROBS.iter().flatten().flatten().flatten() {
+            header.write_bool(*update, false);
+        }
+        header.write_literal(1, 1);
+        header.write_literal(prob_skip_false as u32, 8);
+
+        let mut tokens = BoolWriter::new();
+        let mut above = vec![Context::default(); self.mb_width];
+        for row in macroblocks.chunks(self.mb_width) {
+            let mut left = Context::default();
+            for (mb, above) in row.iter().zip(above.iter_mut()) {
+                let skip = mb.is_empty();
+                header.write_bool(prob_skip_false, skip);
+                header.write_tree(&KEYFRAME_YMODE_TREE, &KEYFRAME_YMODE_PROBS, mb.luma_mode, 0);
+                header.write_tree(&KEYFRAME_UV_MODE_TREE, &KEYFRAME_UV_MODE_PROBS, mb.chroma_mode, 0);
+                if skip {
+                    *above = Context::default();
+                    left = Context::default();
+                } else {
+                    write_residuals(&mut tokens, mb, above, &mut left);
+                }
+            }
+        }
+
+        let first_partition = header.finish();
+        let second_partition = tokens.finish();
+        let mut output = Vec::with_capacity(10 + first_partition.len() + second_partition.len());
+        // Key frame, version 0, shown, followed by the first partition size
+        let tag = (1 << 4) | ((first_partition.len() as u32) << 5);
+        output.extend_from_slice(&tag.to_le_bytes()[..3]);
+        output.extend_from_slice(&[0x9d, 0x01, 0x2a]);
+        output.extend_from_slice(&(self.width as u16).to_le_bytes());
+        output.extend_from_slice(&(self.height as u16).to_le_bytes());
+        output.extend_from_slice(&first_partition);
+        output.extend_from_slice(&second_partition);
+        output
+    }
+
+    /// Pick prediction modes, quantize the residual and reconstruct the macroblock as the
+    /// decoder will
+    fn encode_macroblock(&mut self, mb_x: usize, mb_y: usize) -> Macroblock {
+        let mut mb = Macroblock {
+            luma_mode: DC_PRED,
+            chroma_mode: TM_PRED,
+            blocks: [[0; 16]; 25],
+        };
+
+        // Luma: 16x16 prediction, block DCs gathered into the Y2 Walsh-Hadamard block
+        let (luma_mode, prediction) = [DC_PRED, V_PRED, H_PRED, TM_PRED]
+            .into_iter()
+            .map(|mode| (mode, self.predict(0, mb_x, mb_y, 16, mode)))
+            .min_by_key(|(_, prediction)| self.cost(0, mb_x, mb_y, 16, prediction))
+            .unwrap();
+        mb.luma_mode = luma_mode;
+
+        let coefficients: Vec<[i32; 16]> = self.residual(0, mb_x, mb_y, 16, &prediction).iter().map(forward_dct).collect();
+        let dc = std::array::from_fn(|index| coefficients[index][0]);
+        let y2 = forward_wht(&dc);
+        mb.blocks[0] = quantize(&y2, self.quantizer.y2, 0);
+        let mut dc = dequantize(&mb.blocks[0], self.quantizer.y2);
+        inverse_wht(&mut dc);
+
+        let mut residual = Vec::with_capacity(16);
+        for (index, block) in coefficients.iter().enumerate() {
+            mb.blocks[1 + index] = quantize(block, self.quantizer.y, 1);
+            let mut levels = dequantize(&mb.blocks[1 + index], self.quantizer.y);
+            levels[0] = dc[index];
+            inverse_dct(&mut levels);
+            residual.push(levels);
+        }
+        self.reconstruct(0, mb_x, mb_y, 16, &prediction, &residual);
+
+        // Chroma: one mode for both planes. DC prediction is left out because decoders differ
+        // on the borders of partial edge macroblocks, which only DC averages into visible pixels.
+        let chroma_mode = [TM_PRED, V_PRED, H_PRED]
+            .into_iter()
+            .min_by_key(|&mode| {
+                (1..3)
+                    .map(|plane| self.cost(plane, mb_x, mb_y, 8, &self.predict(plane, mb_x, mb_y, 8, mode)))
+                    .sum::<u32>()
+            })
+            .unwrap();
+        mb.chroma_mode = chroma_mode;
+        for plane in 1..3 {
+            let prediction = self.predict(plane, mb_x, mb_y, 8, chroma_mode);
+            let mut residual = Vec::with_capacity(4);
+            for (index, block) in self.residual(plane, mb_x, mb_y, 8, &prediction).iter().enumerate() {
+                let levels = &mut mb.blocks[17 + (plane - 1) * 4 + index];
+                *levels = quantize(&forward_dct(block), self.quantizer.uv, 0);
+                let mut coefficients = dequantize(levels, self.quantizer.uv);
+                inverse_dct(&mut coefficients);
+                residual.push(coefficients);
+            }
+            self.reconstruct(plane, mb_x, mb_y, 8, &prediction, &residual);
+        }
+
+        mb
+    }
+
+    fn stride(&self, plane: usize) -> usize {
+        self.mb_width * if plane == 0 { 16 } else { 8 }
+    }
+
+    /// Intra prediction of a `size` square from reconstructed neighbours; outside the frame the
+    /// row above reads 127 and the column to the left 129
+    fn predict(&self, plane: usize, mb_x: usize, mb_y: usize, size: usize, mode: i8) -> Vec<u8> {
+        let stride = self.stride(plane);
+        let pixels = &self.reconstructed[plane];
+        let (x0, y0) = (mb_x * size, mb_y * size);
+        let above: Vec<i32> = (0..size)
+            .map(|x| if mb_y == 0 { 127 } else { pixels[(y0 - 1) * stride + x0 + x] as i32 })
+            .collect();
+        let left: Vec<i32> = (0..size)
+            .map(|y| if mb_x == 0 { 129 } else { pixels[(y0 + y) * stride + x0 - 1] as i32 })
+            .collect();
+        let corner = match (mb_x, mb_y) {
+            (_, 0) => 127,
+            (0, _) => 129,
+            _ => pixels[(y0 - 1) * stride + x0 - 1] as i32,
+        };
+
+        let mut prediction = vec![0u8; size * size];
+        for y in 0..size {
+            for x in 0..size {
+                prediction[y * size + x] = match mode {
+                    V_PRED => above[x],
+                    H_PRED => left[y],
+                    TM_PRED => (left[y] + above[x] - corner).clamp(0, 255),
+                    _ => {
+                        let shift = size.trailing_zeros();
+                        match (mb_x > 0, mb_y > 0) {
+                            (false, false) => 128,
+                            (true, false) => (left.iter().sum::<i32>() + (1 << (shift - 1))) >> shift,
+                            (false, true) => (above.iter().sum::<i32>() + (1 << (shift - 1))) >> shift,
+                            (true, true) => (left.iter().chain(&above).sum::<i32>() + (1 << shift)) >> (shift + 1),
+                        }
+                    }
+                } as u8;
+            }
+        }
+        prediction
+    }
+
+    /// Sum of absolute differences between a prediction and the source macroblock
+    fn cost(&self, plane: usize, mb_x: usize, mb_y: usize, size: usize, prediction: &[u8]) -> u32 {
+        let stride = self.stride(plane);
+        let (x0, y0) = (mb_x * size, mb_y * size);
+        (0..size * size)
+            .map(|i| (self.source[plane][(y0 + i / size) * stride + x0 + i % size] as i32 - prediction[i] as i32).unsigned_abs())
+            .sum()
+    }
+
+    /// Source minus prediction for a macroblock, as 4x4 blocks in raster order
+    fn residual(&self, plane: usize, mb_x: usize, mb_y: usize, size: usize, prediction: &[u8]) -> Vec<[i32; 16]> {
+        let stride = self.stride(plane);
+        let blocks_per_row = size / 4;
+        (0..blocks_per_row * blocks_per_row)
+            .map(|index| {
+                let (bx, by) = (index % blocks_per_row * 4, index / blocks_per_row * 4);
+                std::array::from_fn(|i| {
+                    let (x, y) = (bx + i % 4, by + i / 4);
+                    self.source[plane][(mb_y * size + y) * stride + mb_x * size + x] as i32 - prediction[y * size + x] as i32
+                })
+            })
+            .collect()
+    }
+
+    /// Prediction plus decoded residual, clamped, into the reconstructed plane
+    fn reconstruct(&mut self, plane: usize, mb_x: usize, mb_y: usize, size: usize, prediction: &[u8], residual: &[[i32; 16]]) {
+        let stride = self.stride(plane);
+        let blocks_per_row = size / 4;
+        for y in 0..size {
+            for x in 0..size {
+                let block = &residual[(y / 4) * blocks_per_row + x / 4];
+                let value = prediction[y * size + x] as i32 + block[(y % 4) * 4 + x % 4];
+                self.reconstructed[plane][(mb_y * size + y) * stride + mb_x * size + x] = value.clamp(0, 255) as u8;
+            }
+        }
+    }
+}
+
+/// Token partition data for one macroblock, updating the coefficient-presence contexts
+fn write_residuals(writer: &mut BoolWriter, mb: &Macroblock, above: &mut Context, left: &mut Context) {
+    let has_y2 = write_block(writer, &mb.blocks[0], PLANE_Y2, (above[0] as usize) + (left[0] as usize), 0);
+    above[0] = has_y2;
+    left[0] = has_y2;
+
+    for index in 0..16 {
+        let (x, y) = (index % 4, index / 4);
+        let context = above[1 + x] as usize + left[1 + y] as usize;
+        let nonzero = write_block(writer, &mb.blocks[1 + index], PLANE_Y_AFTER_Y2, context, 1);
+        above[1 + x] = nonzero;
+        left[1 + y] = nonzero;
+    }
+
+    for (plane, offset) in [(0, 5), (1, 7)] {
+        for index in 0..4 {
+            let (x, y) = (index % 2, index / 2);
+            let context = above[offset + x] as usize + left[offset + y] as usize;
+            let nonzero = write_block(writer, &mb.blocks[17 + plane * 4 + index], PLANE_CHROMA, context, 0);
+            above[offset + x] = nonzero;
+            left[offset + y] = nonzero;
+        }
+    }
+}
+
+/// Code one block's levels from `first` on; returns whether any were non-zero
+fn write_block(writer: &mut BoolWriter, levels: &[i32; 16], plane: usize, mut context: usize, first: usize) -> bool {
+    let probabilities = &COEFF_PROBS[plane];
+    let Some(last) = (first..16).rev().find(|&i| levels[i] != 0) else {
+        writer.write_tree(&DCT_TOKEN_TREE, &probabilities[COEFF_BANDS[first]][context], DCT_EOB, 0);
+        return false;
+    };
+
+    let mut after_zero = false;
+    for (i, &level) in levels.iter().enumerate().take(last + 1).skip(first) {
+        let probabilities = &probabilities[COEFF_BANDS[i]][context];
+        // End of block cannot directly follow a zero, so the tree then starts past it
+        let start = if after_zero { 2 } else { 0 };
+        let magnitude = level.unsigned_abs();
+        match magnitude {
+            0..=4 => writer.write_tree(&DCT_TOKEN_TREE, probabilities, magnitude as i8, start),
+            _ => {
+                let category = DCT_CAT_BASE.iter().rposition(|&base| magnitude >= base).unwrap();
+                writer.write_tree(&DCT_TOKEN_TREE, probabilities, 5 + category as i8, start);
+                let extra = magnitude - DCT_CAT_BASE[category];
+                let bits = PROB_DCT_CAT[category].iter().take_while(|&&p| p > 0).count();
+                for (bit, &probability) in PROB_DCT_CAT[category][..bits].iter().enumerate() {
+                    writer.write_bool(probability, (extra >> (bits - 1 - bit)) & 1 == 1);
+                }
+            }
+        }
+        if magnitude > 0 {
+            writer.write_bool(128, level < 0);
+        }
+        after_zero = magnitude == 0;
+        context = magnitude.min(2) as usize;
+    }
+
+    if last < 15 {
+        writer.write_tree(&DCT_TOKEN_TREE, &probabilities[COEFF_BANDS[last + 1]][context], DCT_EOB, 0);
+    }
+    true
+}
+
+/// Quantize raster-order coefficients into zigzag-order levels; positions before `first` are
+/// coded elsewhere
+fn quantize(coefficients: &[i32; 16], (dc_step, ac_step): (i32, i32), first: usize) -> [i32; 16] {
+    let mut levels = [0; 16];
+    for (i, level) in levels.iter_mut().enumerate().skip(first) {
+        let coefficient = coefficients[ZIGZAG[i]];
+        let step = if i == 0 { dc_step } else { ac_step };
+        // A slight dead zone around zero for AC saves bits on noise
+        let rounding = if i == 0 { step / 2 } else { step / 3 };
+        let magnitude = ((coefficient.abs() + rounding) / step).min(2047);
+        *level = if coefficient < 0 { -magnitude } else { magnitude };
+    }
+    levels
+}
+
+/// Zigzag-order levels back to raster-order coefficients
+fn dequantize(levels: &[i32; 16], (dc_step, ac_step): (i32, i32)) -> [i32; 16] {
+    let mut coefficients = [0; 16];
+    for (i, &level) in levels.iter().enumerate() {
+        coefficients[ZIGZAG[i]] = level * if i == 0 { dc_step } else { ac_step };
+    }
+    coefficients
+}
+
+fn forward_dct(input: &[i32; 16]) -> [i32; 16] {
+    let mut temp = [0i32; 16];
+    for i in 0..4 {
+        let row = &input[i * 4..i * 4 + 4];
+        let a1 = (row[0] + row[3]) * 8;
+        let b1 = (row[1] + row[2]) * 8;
+        let c1 = (row[1] - row[2]) * 8;
+        let d1 = (row[0] - row[3]) * 8;
+        temp[i * 4] = a1 + b1;
+        temp[i * 4 + 2] = a1 - b1;
+        temp[i * 4 + 1] = (c1 * 2217 + d1 * 5352 + 14500) >> 12;
+        temp[i * 4 + 3] = (d1 * 2217 - c1 * 5352 + 7500) >> 12;
+    }
+
+    let mut output = [0i32; 16];
+    for i in 0..4 {
+        let a1 = temp[i] + temp[12 + i];
+        let b1 = temp[4 + i] + temp[8 + i];
+        let c1 = temp[4 + i] - temp[8 + i];
+        let d1 = temp[i] - temp[12 + i];
+        output[i] = (a1 + b1 + 7) >> 4;
+        output[8 + i] = (a1 - b1 + 7) >> 4;
+        output[4 + i] = ((c1 * 2217 + d1 * 5352 + 12000) >> 16) + (d1 != 0) as i32;
+        output[12 + i] = (d1 * 2217 - c1 * 5352 + 51000) >> 16;
+    }
+    output
+}
+
+fn forward_wht(input: &[i32; 16]) -> [i32; 16] {
+    let mut temp = [0i32; 16];
+    for i in 0..4 {
+        let row = &input[i * 4..i * 4 + 4];
+        let a1 = (row[0] + row[2]) * 4;
+        let d1 = (row[1] + row[3]) * 4;
+        let c1 = (row[1] - row[3]) * 4;
+        let b1 = (row[0] - row[2]) * 4;
+        temp[i * 4] = a1 + d1 + (a1 != 0) as i32;
+        temp[i * 4 + 1] = b1 + c1;
+        temp[i * 4 + 2] = b1 - c1;
+        temp[i * 4 + 3] = a1 - d1;
+    }
+
+    let mut output = [0i32; 16];
+    for i in 0..4 {
+        let a1 = temp[i] + temp[8 + i];
+        let d1 = temp[4 + i] + temp[12 + i];
+        let c1 = temp[4 + i] - temp[12 + i];
+        let b1 = temp[i] - temp[8 + i];
+        for (j, value) in [a1 + d1, b1 + c1, b1 - c1, a1 - d1].into_iter().enumerate() {
+            let value = value + (value < 0) as i32;
+            output[j * 4 + i] = (value + 3) >> 3;
+        }
+    }
+    output
+}
+
+/// Inverse transforms exactly as in RFC 6386 section 14, so reconstruction matches decoders
+fn inverse_dct(block: &mut [i32; 16]) {
+    const C1: i64 = 20091;
+    const C2: i64 = 35468;
+    let mut temp = [0i64; 16];
+    for i in 0..4 {
+        let [b0, b4, b8, b12] = [block[i], block[4 + i], block[8 + i], block[12 + i]].map(i64::from);
+        let a1 = b0 + b8;
+        let b1 = b0 - b8;
+        let c1 = ((b4 * C2) >> 16) - (b12 + ((b12 * C1) >> 16));
+        let d1 = (b4 + ((b4 * C1) >> 16)) + ((b12 * C2) >> 16);
+        temp[i] = a1 + d1;
+        temp[4 + i] = b1 + c1;
+        temp[8 + i] = b1 - c1;
+        temp[12 + i] = a1 - d1;
+    }
+    for i in 0..4 {
+        let [t0, t1, t2, t3] = [temp[4 * i], temp[4 * i + 1], temp[4 * i + 2], temp[4 * i + 3]];
+        let a1 = t0 + t2;
+        let b1 = t0 - t2;
+        let c1 = ((t1 * C2) >> 16) - (t3 + ((t3 * C1) >> 16));
+        let d1 = (t1 + ((t1 * C1) >> 16)) + ((t3 * C2) >> 16);
+        block[4 * i] = ((a1 + d1 + 4) >> 3) as i32;
+        block[4 * i + 1] = ((b1 + c1 + 4) >> 3) as i32;
+        block[4 * i + 2] = ((b1 - c1 + 4) >> 3) as i32;
+        block[4 * i + 3] = ((a1 - d1 + 4) >> 3) as i32;
+    }
+}
+
+fn inverse_wht(block: &mut [i32; 16]) {
+    for i in 0..4 {
+        let a1 = block[i] + block[12 + i];
+        let b1 = block[4 + i] + block[8 + i];
+        let c1 = block[4 + i] - block[8 + i];
+        let d1 = block[i] - block[12 + i];
+        block[i] = a1 + b1;
+        block[4 + i] = c1 + d1;
+        block[8 + i] = a1 - b1;
+        block[12 + i] = d1 - c1;
+    }
+    for i in 0..4 {
+        let a1 = block[4 * i] + block[4 * i + 3];
+        let b1 = block[4 * i + 1] + block[4 * i + 2];
+        let c1 = block[4 * i + 1] - block[4 * i + 2];
+        let d1 = block[4 * i] - block[4 * i + 3];
+        block[4 * i] = (a1 + b1 + 3) >> 3;
+        block[4 * i + 1] = (c1 + d1 + 3) >> 3;
+        block[4 * i + 2] = (a1 - b1 + 3) >> 3;
+        block[4 * i + 3] = (d1 - c1 + 3) >> 3;
+    }
+}
+
+/// Boolean entropy encoder of RFC 6386 section 7
+struct BoolWriter {
+    output: Vec<u8>,
+    range: u32,
+    bottom: u32,
+    bit_count: i32,
+}
+
+impl BoolWriter {
+    fn new() -> Self {
+        Self { output: Vec::new(), range: 255, bottom: 0, bit_count: 24 }
+    }
+
+    fn write_bool(&mut self, probability: u8, bit: bool) {
+        let split = 1 + (((self.range - 1) * probability as u32) >> 8);
+        if bit {
+            self.bottom = self.bottom.wrapping_add(split);
+            self.range -= split;
+        } else {
+            self.range = split;
+        }
+        while self.range < 128 {
+            self.range <<= 1;
+            if self.bottom & (1 << 31) != 0 {
+                self.carry();
+            }
+            self.bottom <<= 1;
+            self.bit_count -= 1;
+            if self.bit_count == 0 {
+                self.output.push((self.bottom >> 24) as u8);
+                self.bottom &= (1 << 24) - 1;
+                self.bit_count = 8;
+            }
+        }
+    }
+
+    fn carry(&mut self) {
+        for byte in self.output.iter_mut().rev() {
+            if *byte == 255 {
+                *byte = 0;
+            } else {
+                *byte += 1;
+                return;
+            }
+        }
+    }
+
+    fn write_literal(&mut self, value: u32, bits: u32) {
+        for bit in (0..bits).rev() {
+            self.write_bool(128, (value >> bit) & 1 == 1);
+        }
+    }
+
+    /// Write `value` as a path through a VP8 token tree, where leaves hold negated values
+    fn write_tree(&mut self, tree: &[i8], probabilities: &[u8], value: i8, start: usize) {
+        fn path(tree: &[i8], node: usize, value: i8, bits: &mut Vec<(usize, bool)>) -> bool {
+            for branch in 0..2 {
+                bits.push((node >> 1, branch == 1));
+                let next = tree[node + branch];
+                if (next <= 0 && -next == value) || (next > 0 && path(tree, next as usize, value, bits)) {
+                    return true;
+                }
+                bits.pop();
+            }
+            false
+        }
+
+        let mut bits = Vec::with_capacity(8);
+        path(tree, start, value, &mut bits);
+        for (index, bit) in bits {
+            self.write_bool(probabilities[index], bit);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let mut count = self.bit_count;
+        let mut value = self.bottom;
+        if value & (1 << (32 - count)) != 0 {
+            self.carry();
+        }
+        value <<= count & 7;
+        count >>= 3;
+        while count > 0 {
+            value <<= 8;
+            count -= 1;
+        }
+        for _ in 0..4 {
+            self.output.push((value >> 24) as u8);
+            value <<= 8;
+        }
+        self.output
+    }
+}
+
+// === VP8 TABLES (RFC 6386) ===
+
+type TokenProbs = [[[[u8; 11]; 3]; 8]; 4];
+
+static KEYFRAME_YMODE_TREE: [i8; 8] = [-4, 2, 4, 6, -DC_PRED, -V_PRED, -H_PRED, -TM_PRED];
+static KEYFRAME_YMODE_PROBS: [u8; 4] = [145, 156, 163, 128];
+static KEYFRAME_UV_MODE_TREE: [i8; 6] = [-DC_PRED, 2, -V_PRED, 4, -H_PRED, -TM_PRED];
+static KEYFRAME_UV_MODE_PROBS: [u8; 3] = [142, 114, 183];
+
+static DCT_TOKEN_TREE: [i8; 22] = [-DCT_EOB, 2, 0, 4, -1, 6, 8, 12, -2, 10, -3, -4, 14, 16, -5, -6, 18, 20, -7, -8, -9, -10];
+static PROB_DCT_CAT: [[u8; 12]; 6] = [
+    [159, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [165, 145, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [173, 148, 140, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [176, 155, 140, 135, 0, 0, 0, 0, 0, 0, 0, 0],
+    [180, 157, 141, 134, 130, 0, 0, 0, 0, 0, 0, 0],
+    [254, 254, 243, 230, 196, 177, 153, 140, 133, 130, 129, 0],
+];
+static DCT_CAT_BASE: [u32; 6] = [5, 7, 11, 19, 35, 67];
+static COEFF_BANDS: [usize; 16] = [0, 1, 2, 3, 6, 4, 5, 6, 6, 6, 6, 6, 6, 6, 6, 7];
+static ZIGZAG: [usize; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+#[rustfmt::skip]
+static DC_QUANT: [i16; 128] = [
+      4,   5,   6,   7,   8,   9,  10,  10,  11,  12,  13,  14,  15,  16,  17,  17,
+     18,  19,  20,  20,  21,  21,  22,  22,  23,  23,  24,  25,  25,  26,  27,  28,
+     29,  30,  31,  32,  33,  34,  35,  36,  37,  37,  38,  39,  40,  41,  42,  43,
+     44,  45,  46,  46,  47,  48,  49,  50,  51,  52,  53,  54,  55,  56,  57,  58,
+     59,  60,  61,  62,  63,  64,  65,  66,  67,  68,  69,  70,  71,  72,  73,  74,
+     75,  76,  76,  77,  78,  79,  80,  81,  82,  83,  84,  85,  86,  87,  88,  89,
+     91,  93,  95,  96,  98, 100, 101, 102, 104, 106, 108, 110, 112, 114, 116, 118,
+    122, 124, 126, 128, 130, 132, 134, 136, 138, 140, 143, 145, 148, 151, 154, 157,
+];
+
+#[rustfmt::skip]
+static AC_QUANT: [i16; 128] = [
+      4,   5,   6,   7,   8,   9,  10,  11,  12,  13,  14,  15,  16,  17,  18,  19,
+     20,  21,  22,  23,  24,  25,  26,  27,  28,  29,  30,  31,  32,  33,  34,  35,
+     36,  37,  38,  39,  40,  41,  42,  43,  44,  45,  46,  47,  48,  49,  50,  51,
+     52,  53,  54,  55,  56,  57,  58,  60,  62,  64,  66,  68,  70,  72,  74,  76,
+     78,  80,  82,  84,  86,  88,  90,  92,  94,  96,  98, 100, 102, 104, 106, 108,
+    110, 112, 114, 116, 119, 122, 125, 128, 131, 134, 137, 140, 143, 146, 149, 152,
+    155, 158, 161, 164, 167, 170, 173, 177, 181, 185, 189, 193, 197, 201, 205, 209,
+    213, 217, 221, 225, 229, 234, 239, 245, 249, 254, 259, 264, 269, 274, 279, 284,
+];
+
+/// Probabilities that a key frame updates each token probability; this encoder never does
+#[rustfmt::skip]
+static COEFF_UPDATE_PROBS: TokenProbs = [
+    [
+        [[255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[176, 246, 255, 255, 255, 255, 255, 255, 255, 255, 255], [223, 241, 252, 255, 255, 255, 255, 255, 255, 255, 255], [249, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 244, 252, 255, 255, 255, 255, 255, 255, 255, 255], [234, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255], [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 246, 254, 255, 255, 255, 255, 255, 255, 255, 255], [239, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255], [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255], [251, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255], [251, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255], [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 254, 253, 255, 254, 255, 255, 255, 255, 255, 255], [250, 255, 254, 255, 254, 255, 255, 255, 255, 255, 255], [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+    ],
+    [
+        [[217, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [225, 252, 241, 253, 255, 255, 254, 255, 255, 255, 255], [234, 250, 241, 250, 253, 255, 253, 254, 255, 255, 255]],
+        [[255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255], [223, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255], [238, 253, 254, 254, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255], [249, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 253, 255, 255, 255, 255, 255, 255, 255, 255, 255], [247, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255], [252, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255], [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255], [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+    ],
+    [
+        [[186, 251, 250, 255, 255, 255, 255, 255, 255, 255, 255], [234, 251, 244, 254, 255, 255, 255, 255, 255, 255, 255], [251, 251, 243, 253, 254, 255, 254, 255, 255, 255, 255]],
+        [[255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255], [236, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255], [251, 253, 253, 254, 254, 255, 255, 255, 255, 255, 255]],
+        [[255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255], [254, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255], [254, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255], [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+    ],
+    [
+        [[248, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [250, 254, 252, 254, 255, 255, 255, 255, 255, 255, 255], [248, 254, 249, 253, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255], [246, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255], [252, 254, 251, 254, 254, 255, 255, 255, 255, 255, 255]],
+        [[255, 254, 252, 255, 255, 255, 255, 255, 255, 255, 255], [248, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255], [253, 255, 254, 254, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255], [245, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255], [253, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 251, 253, 255, 255, 255, 255, 255, 255, 255, 255], [252, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255], [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 252, 255, 255, 255, 255, 255, 255, 255, 255, 255], [249, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 255, 253, 255, 255, 255, 255, 255, 255, 255, 255], [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+        [[255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255], [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]],
+    ],
+];
+
+/// Default token probabilities, by plane, coefficient band and context
+#[rustfmt::skip]
+static COEFF_PROBS: TokenProbs = [
+    [
+        [[128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128], [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128], [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128]],
+        [[253, 136, 254, 255, 228, 219, 128, 128, 128, 128, 128], [189, 129, 242, 255, 227, 213, 255, 219, 128, 128, 128], [106, 126, 227, 252, 214, 209, 255, 255, 128, 128, 128]],
+        [[1, 98, 248, 255, 236, 226, 255, 255, 128, 128, 128], [181, 133, 238, 254, 221, 234, 255, 154, 128, 128, 128], [78, 134, 202, 247, 198, 180, 255, 219, 128, 128, 128]],
+        [[1, 185, 249, 255, 243, 255, 128, 128, 128, 128, 128], [184, 150, 247, 255, 236, 224, 128, 128, 128, 128, 128], [77, 110, 216, 255, 236, 230, 128, 128, 128, 128, 128]],
+        [[1, 101, 251, 255, 241, 255, 128, 128, 128, 128, 128], [170, 139, 241, 252, 236, 209, 255, 255, 128, 128, 128], [37, 116, 196, 243, 228, 255, 255, 255, 128, 128, 128]],
+        [[1, 204, 254, 255, 245, 255, 128, 128, 128, 128, 128], [207, 160, 250, 255, 238, 128, 128, 128, 128, 128, 128], [102, 103, 231, 255, 211, 171, 128, 128, 128, 128, 128]],
+        [[1, 152, 252, 255, 240, 255, 128, 128, 128, 128, 128], [177, 135, 243, 255, 234, 225, 128, 128, 128, 128, 128], [80, 129, 211, 255, 194, 224, 128, 128, 128, 128, 128]],
+        [[1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128], [246, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128], [255, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128]],
+    ],
+    [
+        [[198, 35, 237, 223, 193, 187, 162, 160, 145, 155, 62], [131, 45, 198, 221, 172, 176, 220, 157, 252, 221, 1], [68, 47, 146, 208, 149, 167, 221, 162, 255, 223, 128]],
+        [[1, 149, 241, 255, 221, 224, 255, 255, 128, 128, 128], [184, 141, 234, 253, 222, 220, 255, 199, 128, 128, 128], [81, 99, 181, 242, 176, 190, 249, 202, 255, 255, 128]],
+        [[1, 129, 232, 253, 214, 197, 242, 196, 255, 255, 128], [99, 121, 210, 250, 201, 198, 255, 202, 128, 128, 128], [23, 91, 163, 242, 170, 187, 247, 210, 255, 255, 128]],
+        [[1, 200, 246, 255, 234, 255, 128, 128, 128, 128, 128], [109, 178, 241, 255, 231, 245, 255, 255, 128, 128, 128], [44, 130, 201, 253, 205, 192, 255, 255, 128, 128, 128]],
+        [[1, 132, 239, 251, 219, 209, 255, 165, 128, 128, 128], [94, 136, 225, 251, 218, 190, 255, 255, 128, 128, 128], [22, 100, 174, 245, 186, 161, 255, 199, 128, 128, 128]],
+        [[1, 182, 249, 255, 232, 235, 128, 128, 128, 128, 128], [124, 143, 241, 255, 227, 234, 128, 128, 128, 128, 128], [35, 77, 181, 251, 193, 211, 255, 205, 128, 128, 128]],
+        [[1, 157, 247, 255, 236, 231, 255, 255, 128, 128, 128], [121, 141, 235, 255, 225, 227, 255, 255, 128, 128, 128], [45, 99, 188, 251, 195, 217, 255, 224, 128, 128, 128]],
+        [[1, 1, 251, 255, 213, 255, 128, 128, 128, 128, 128], [203, 1, 248, 255, 255, 128, 128, 128, 128, 128, 128], [137, 1, 177, 255, 224, 255, 128, 128, 128, 128, 128]],
+    ],
+    [
+        [[253, 9, 248, 251, 207, 208, 255, 192, 128, 128, 128], [175, 13, 224, 243, 193, 185, 249, 198, 255, 255, 128], [73, 17, 171, 221, 161, 179, 236, 167, 255, 234, 128]],
+        [[1, 95, 247, 253, 212, 183, 255, 255, 128, 128, 128], [239, 90, 244, 250, 211, 209, 255, 255, 128, 128, 128], [155, 77, 195, 248, 188, 195, 255, 255, 128, 128, 128]],
+        [[1, 24, 239, 251, 218, 219, 255, 205, 128, 128, 128], [201, 51, 219, 255, 196, 186, 128, 128, 128, 128, 128], [69, 46, 190, 239, 201, 218, 255, 228, 128, 128, 128]],
+        [[1, 191, 251, 255, 255, 128, 128, 128, 128, 128, 128], [223, 165, 249, 255, 213, 255, 128, 128, 128, 128, 128], [141, 124, 248, 255, 255, 128, 128, 128, 128, 128, 128]],
+        [[1, 16, 248, 255, 255, 128, 128, 128, 128, 128, 128], [190, 36, 230, 255, 236, 255, 128, 128, 128, 128, 128], [149, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128]],
+        [[1, 226, 255, 128, 128, 128, 128, 128, 128, 128, 128], [247, 192, 255, 128, 128, 128, 128, 128, 128, 128, 128], [240, 128, 255, 128, 128, 128, 128, 128, 128, 128, 128]],
+        [[1, 134, 252, 255, 255, 128, 128, 128, 128, 128, 128], [213, 62, 250, 255, 255, 128, 128, 128, 128, 128, 128], [55, 93, 255, 128, 128, 128, 128, 128, 128, 128, 128]],
+        [[128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128], [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128], [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128]],
+    ],
+    [
+        [[202, 24, 213, 235, 186, 191, 220, 160, 240, 175, 255], [126, 38, 182, 232, 169, 184, 228, 174, 255, 187, 128], [61, 46, 138, 219, 151, 178, 240, 170, 255, 216, 128]],
+        [[1, 112, 230, 250, 199, 191, 247, 159, 255, 255, 128], [166, 109, 228, 252, 211, 215, 255, 174, 128, 128, 128], [39, 77, 162, 232, 172, 180, 245, 178, 255, 255, 128]],
+        [[1, 52, 220, 246, 198, 199, 249, 220, 255, 255, 128], [124, 74, 191, 243, 183, 193, 250, 221, 255, 255, 128], [24, 71, 130, 219, 154, 170, 243, 182, 255, 255, 128]],
+        [[1, 182, 225, 249, 219, 240, 255, 224, 128, 128, 128], [149, 150, 226, 252, 216, 205, 255, 171, 128, 128, 128], [28, 108, 170, 242, 183, 194, 254, 223, 255, 255, 128]],
+        [[1, 81, 230, 252, 204, 203, 255, 192, 128, 128, 128], [123, 102, 209, 247, 188, 196, 255, 233, 128, 128, 128], [20, 95, 153, 243, 164, 173, 255, 203, 128, 128, 128]],
+        [[1, 222, 248, 255, 216, 213, 128, 128, 128, 128, 128], [168, 175, 246, 252, 235, 205, 255, 255, 128, 128, 128], [47, 116, 215, 255, 211, 212, 255, 255, 128, 128, 128]],
+        [[1, 121, 236, 253, 212, 214, 255, 255, 128, 128, 128], [141, 84, 213, 252, 201, 202, 255, 219, 128, 128, 128], [42, 80, 160, 240, 162, 185, 255, 205, 128, 128, 128]],
+        [[1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128], [244, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128], [238, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128]],
+    ],
+];